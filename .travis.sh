@@ -9,5 +9,16 @@ git submodule update
 popd
 
 export PKG_CONFIG_PATH=./rust-gamedev-kit/install/lib/pkgconfig
-rustc -C link-args="`pkg-config --libs glfw3`" -L ./rust-gamedev-kit/install/lib/rustlib/*/lib src/cubeland/main.rs
-rustc -L ./rust-gamedev-kit/install/lib/rustlib/*/lib src/terrain-benchmark/main.rs
+LIBS=`echo ./rust-gamedev-kit/install/lib/rustlib/*/lib`
+rustc -L $LIBS src/cubeland-world/lib.rs
+rustc --test -L $LIBS -o cubeland-world-test src/cubeland-world/lib.rs
+./cubeland-world-test
+rustc -C link-args="`pkg-config --libs glfw3`" -L $LIBS -L . src/cubeland/main.rs
+rustc -L $LIBS -L . src/terrain-benchmark/main.rs
+rustc -L $LIBS -L . src/view-benchmark/main.rs
+rustc -L $LIBS -L . src/storage-benchmark/main.rs
+rustc -L $LIBS -L . src/terrain-export/main.rs
+rustc -L $LIBS -L . src/cubeland-server/main.rs
+
+# Draw a frame with Mesa's software renderer
+LIBGL_ALWAYS_SOFTWARE=1 xvfb-run -s "-screen 0 1024x768x24" src/cubeland/main --seed 7 --screenshot /tmp/cubeland-screenshot.png
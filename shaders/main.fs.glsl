@@ -4,16 +4,48 @@ const vec4 fog_color = vec4(0.0, 0.75, 1.0, 1.0);
 
 uniform sampler2D texture;
 
-varying vec4 frag_diffuse_factor;
+// Keep in sync with MAX_CASCADES in shadow.rs
+const int MAX_CASCADES = 4;
+uniform sampler2DShadow shadow_maps[MAX_CASCADES];
+// View depth each cascade reaches to
+uniform float cascade_far[MAX_CASCADES];
+uniform int num_cascades;
+
+varying vec3 frag_sun;
+varying vec3 frag_ambient;
+varying vec3 frag_block_light;
+varying vec4 frag_color;
+varying vec3 frag_shadow_coords[MAX_CASCADES];
+varying float frag_view_depth;
 varying vec2 frag_texcoord1;
 varying vec2 frag_texcoord2;
 varying float frag_tex_factor;
 varying float frag_fog_factor;
 
+// 1 where the sun reaches the fragment, 0 in full shadow. Past the last
+// cascade everything is lit.
+float sunlight() {
+    // GLSL 1.20 can only index sampler arrays with constants
+    if (num_cascades > 0 && frag_view_depth < cascade_far[0]) {
+        return shadow2D(shadow_maps[0], frag_shadow_coords[0]).r;
+    }
+    if (num_cascades > 1 && frag_view_depth < cascade_far[1]) {
+        return shadow2D(shadow_maps[1], frag_shadow_coords[1]).r;
+    }
+    if (num_cascades > 2 && frag_view_depth < cascade_far[2]) {
+        return shadow2D(shadow_maps[2], frag_shadow_coords[2]).r;
+    }
+    if (num_cascades > 3 && frag_view_depth < cascade_far[3]) {
+        return shadow2D(shadow_maps[3], frag_shadow_coords[3]).r;
+    }
+    return 1.0;
+}
+
 void main() {
     vec4 noise = mix(texture2D(texture, frag_texcoord1),
                      texture2D(texture, frag_texcoord2),
                      frag_tex_factor);
-    gl_FragColor = noise * frag_diffuse_factor;
+    vec3 lit = max(frag_sun * sunlight() + frag_ambient, frag_block_light);
+    gl_FragColor = noise * vec4(lit, 1.0) * frag_color;
     gl_FragColor = mix(fog_color, gl_FragColor, frag_fog_factor);
 }
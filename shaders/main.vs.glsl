@@ -5,12 +5,35 @@ uniform mat4 projection;
 uniform vec3 camera_position;
 uniform vec3 light_direction;
 uniform vec3 chunk_position;
+// Width of a block in this chunk; more than 1 for distant terrain
+uniform float chunk_scale;
+uniform float planet_radius;
+uniform float fog_density;
 uniform vec3 normal;
 
+// Indexed by block id. Keep in sync with MAX_BLOCK_TYPES in registry.rs.
+const int MAX_BLOCK_TYPES = 32;
+uniform vec4 block_colors[MAX_BLOCK_TYPES];
+uniform vec3 block_textures[MAX_BLOCK_TYPES];
+
+// Keep in sync with MAX_CASCADES in shadow.rs
+const int MAX_CASCADES = 4;
+uniform mat4 shadow_matrices[MAX_CASCADES];
+
 attribute vec3 position;
 attribute float blocktype;
+// Ambient occlusion from 0 (darkest) to 3 (unoccluded)
+attribute float ao;
+// Sky and block light levels from 0 to 15. Keep in sync with MAX_LIGHT in light.rs.
+attribute vec2 light;
 
-varying vec4 frag_diffuse_factor;
+// Sunlight, which shadows can block, and the light they can't
+varying vec3 frag_sun;
+varying vec3 frag_ambient;
+varying vec3 frag_block_light;
+varying vec4 frag_color;
+varying vec3 frag_shadow_coords[MAX_CASCADES];
+varying float frag_view_depth;
 varying vec2 frag_texcoord1;
 varying vec2 frag_texcoord2;
 varying float frag_tex_factor;
@@ -18,58 +41,56 @@ varying float frag_fog_factor;
 
 const vec4 light_diffuse = vec4(0.8, 0.8, 0.8, 0.0);
 const vec4 light_ambient = vec4(0.2, 0.2, 0.2, 1.0);
+const vec3 block_light_color = vec3(1.0, 0.85, 0.6);
+// Darkest a block can get, so caves are never pitch black
+const float min_brightness = 0.03;
 
-const float fog_density = 0.003;
-const float tex_size = 128.0;
+// Each level down is this much dimmer than the one above it
+float brightness(float level) {
+    return max(pow(0.8, 15.0 - level), min_brightness);
+}
 
-const float BLOCK_GRASS = 1.0;
-const float BLOCK_STONE = 2.0;
-const float BLOCK_DIRT = 3.0;
-const float BLOCK_WATER = 4.0;
+const float tex_size = 128.0;
 
 void main() {
-    vec3 world_position = chunk_position + position;
+    vec3 world_position = chunk_position + position * chunk_scale;
+
+    // Drop terrain with distance for the planet's curvature. Keep in sync
+    // with curvature_drop in renderer.rs.
+    float horiz_dist = max(length(world_position.xz - camera_position.xz) - 100.0, 0.0);
+    world_position.y -= horiz_dist * horiz_dist / (2.0 * planet_radius);
+
     vec4 eye_position = view * vec4(world_position, 1.0);
 
     gl_Position = projection * eye_position;
 
-    frag_texcoord1 = vec2(dot(normal.zxy, position),
-                          dot(normal.yzx, position));
+    for (int i = 0; i < MAX_CASCADES; i++) {
+        frag_shadow_coords[i] = (shadow_matrices[i] * vec4(world_position, 1.0)).xyz * 0.5 + 0.5;
+    }
+    frag_view_depth = -eye_position.z;
+
+    vec3 block_position = position * chunk_scale;
+    frag_texcoord1 = vec2(dot(normal.zxy, block_position),
+                          dot(normal.yzx, block_position));
 
     frag_texcoord1 /= tex_size;
     frag_texcoord2 = frag_texcoord1;
 
-    vec4 base_color;
-    if (blocktype == BLOCK_GRASS) {
-        base_color = vec4(0.0, 0.8, 0.2, 1.0);
-        frag_texcoord1 *= 0.5;
-        frag_texcoord2 *= 16.0;
-        frag_tex_factor = 0.8;
-    } else if (blocktype == BLOCK_STONE) {
-        base_color = vec4(0.8, 0.8, 0.8, 1.0);
-        frag_texcoord1 *= 1.0;
-        frag_texcoord2 *= 8.0;
-        frag_tex_factor = 0.3;
-    } else if (blocktype == BLOCK_DIRT) {
-        base_color = vec4(0.63, 0.35, 0.03, 1.0);
-        frag_texcoord1 *= 0.5;
-        frag_texcoord2 *= 16.0;
-        frag_tex_factor = 0.8;
-    } else if (blocktype == BLOCK_WATER) {
-        base_color = vec4(0.1, 0.1, 0.9, 1.0);
-        frag_texcoord1 *= 2.0;
-        frag_texcoord2 *= 0.1;
-        frag_tex_factor = 0.8;
-    } else {
-        base_color = vec4(1.0, 0.0, 0.0, 1.0);
-        frag_texcoord1 *= 16.0;
-        frag_texcoord2 *= 16.0;
-        frag_tex_factor = 0.5;
-    }
+    int id = int(blocktype + 0.5);
+    vec4 base_color = block_colors[id];
+    vec3 tex = block_textures[id];
+    frag_texcoord1 *= tex.x;
+    frag_texcoord2 *= tex.y;
+    frag_tex_factor = tex.z;
 
     vec4 diffuse_factor
         = max(-dot(normal, light_direction), 0.0) * light_diffuse;
-    frag_diffuse_factor = (diffuse_factor + light_ambient) * base_color;
+    float occlusion = 0.4 + 0.6 * ao / 3.0;
+    float sky = brightness(light.x) * occlusion;
+    frag_sun = diffuse_factor.rgb * sky;
+    frag_ambient = light_ambient.rgb * sky;
+    frag_block_light = block_light_color * brightness(light.y) * occlusion;
+    frag_color = base_color;
 
     frag_fog_factor = clamp(exp2(-pow(length(eye_position), 2.0) * pow(fog_density, 2.0) * 1.44), 0.0, 1.0);
 }
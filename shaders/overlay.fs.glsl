@@ -0,0 +1,7 @@
+#version 120
+
+varying vec4 frag_color;
+
+void main() {
+    gl_FragColor = frag_color;
+}
@@ -0,0 +1,14 @@
+#version 120
+
+uniform vec2 window_size;
+
+// In pixels from the top left
+attribute vec2 position;
+attribute vec4 color;
+
+varying vec4 frag_color;
+
+void main() {
+    gl_Position = vec4(position / window_size * vec2(2.0, -2.0) + vec2(-1.0, 1.0), 0.0, 1.0);
+    frag_color = color;
+}
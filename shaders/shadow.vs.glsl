@@ -0,0 +1,19 @@
+#version 120
+
+uniform mat4 shadow_matrix;
+uniform vec3 camera_position;
+uniform vec3 chunk_position;
+uniform float chunk_scale;
+uniform float planet_radius;
+
+attribute vec3 position;
+
+void main() {
+    vec3 world_position = chunk_position + position * chunk_scale;
+
+    // Keep in sync with main.vs.glsl so shadows land on the lowered terrain
+    float horiz_dist = max(length(world_position.xz - camera_position.xz) - 100.0, 0.0);
+    world_position.y -= horiz_dist * horiz_dist / (2.0 * planet_radius);
+
+    gl_Position = shadow_matrix * vec4(world_position, 1.0);
+}
@@ -0,0 +1,114 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate getopts;
+extern crate world = "cubeland-world";
+
+use std::os;
+use std::io::net::ip::SocketAddr;
+
+use world::config::WorldConfig;
+use world::metrics::{Metrics, LogError, LogInfo, parse_log_level};
+use world::region::RegionStore;
+use world::registry::BlockRegistry;
+use world::server::Server;
+
+static DEFAULT_LISTEN_ADDR : &'static str = "0.0.0.0:7777";
+
+fn main() {
+    let args = os::args();
+    let mut opts = WorldConfig::opts();
+    opts.push_all_move(~[
+        getopts::optopt("l", "listen", format!("address to listen on (default: {})", DEFAULT_LISTEN_ADDR), "ADDR:PORT"),
+        getopts::optopt("b", "blocks", "block registry (default: data/blocks.json)", "FILE"),
+        getopts::optopt("", "log-level", "error, info or debug (default info)", "LEVEL"),
+        getopts::optflag("h", "help", "show this help"),
+    ]);
+    let brief = format!("Usage: {} [options]", args[0]);
+
+    let matches = match getopts::getopts(args.tail(), opts) {
+        Ok(m) => m,
+        Err(f) => {
+            println!("{}\n\n{}", f.to_err_msg(), getopts::usage(brief, opts));
+            os::set_exit_status(1);
+            return;
+        }
+    };
+
+    if matches.opt_present("h") {
+        println!("{}", getopts::usage(brief, opts));
+        return;
+    }
+
+    let config = match WorldConfig::from_matches(&matches) {
+        Ok(config) => config,
+        Err(msg) => {
+            println!("error in world config: {}", msg);
+            os::set_exit_status(1);
+            return;
+        }
+    };
+
+    let log_level = match matches.opt_str("log-level") {
+        Some(s) => match parse_log_level(s) {
+            Ok(level) => level,
+            Err(msg) => {
+                println!("{}", msg);
+                os::set_exit_status(1);
+                return;
+            }
+        },
+        None => LogInfo,
+    };
+    let metrics = Metrics::new(log_level);
+
+    let listen = matches.opt_str("l").unwrap_or(DEFAULT_LISTEN_ADDR.to_owned());
+    let addr = match from_str::<SocketAddr>(listen) {
+        Some(addr) => addr,
+        None => {
+            println!("invalid listen address: {}", listen);
+            os::set_exit_status(1);
+            return;
+        }
+    };
+
+    let blocks_path = Path::new(matches.opt_str("b").unwrap_or(~"data/blocks.json"));
+    let registry = match BlockRegistry::load(&blocks_path) {
+        Ok(registry) => registry,
+        Err(msg) => {
+            println!("failed to load block registry: {}", msg);
+            os::set_exit_status(1);
+            return;
+        }
+    };
+
+    let store = match RegionStore::new(Path::new(format!("world/{}", config.storage_name(&registry)))) {
+        Ok(store) => store,
+        Err(e) => {
+            println!("failed to open world storage: {}", e);
+            os::set_exit_status(1);
+            return;
+        }
+    };
+
+    // The server's tasks keep the process running after main returns.
+    // Every edit is saved as it is applied, so killing it loses nothing.
+    match Server::start(addr, &config, store, registry, metrics.clone()) {
+        Ok(server) => metrics.info(format!("listening on {}", server.addr)),
+        Err(e) => {
+            metrics.log(LogError, format!("failed to listen on {}: {}", addr, e));
+            os::set_exit_status(1);
+        }
+    }
+}
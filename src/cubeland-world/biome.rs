@@ -0,0 +1,246 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate noise;
+
+use std;
+
+use noise::sources::Perlin;
+use noise::Source;
+
+use config::WorldConfig;
+
+use terrain::BlockType;
+use terrain::{BlockGrass, BlockStone, BlockDirt, BlockSand, BlockSnow};
+
+#[deriving(Eq, Show)]
+pub enum Biome {
+    BiomeOcean = 0,
+    BiomePlains = 1,
+    BiomeDesert = 2,
+    BiomeForest = 3,
+    BiomeTundra = 4,
+    BiomeMountains = 5,
+}
+
+static NUM_BIOMES : uint = 6;
+
+static biomes : [Biome, ..NUM_BIOMES] = [
+    BiomeOcean, BiomePlains, BiomeDesert, BiomeForest, BiomeTundra, BiomeMountains,
+];
+
+pub struct BiomeParams {
+    /// Position in (temperature, moisture) space where this biome is strongest
+    climate: (f64, f64),
+    /// Surface height where the height noise is zero
+    base_height: f64,
+    /// Multiplier for the height noise
+    height_amplitude: f64,
+    /// Empty space below this height is filled with water
+    water_level: f64,
+    surface: BlockType,
+    surface_depth: f64,
+    subsurface: BlockType,
+    subsurface_depth: f64,
+}
+
+static biome_params : [BiomeParams, ..NUM_BIOMES] = [
+    /* ocean */
+    BiomeParams {
+        climate: (0.2, 0.5),
+        base_height: -45.0, height_amplitude: 40.0, water_level: -12.0,
+        surface: BlockSand, surface_depth: 3.0,
+        subsurface: BlockDirt, subsurface_depth: 6.0,
+    },
+
+    /* plains */
+    BiomeParams {
+        climate: (0.1, -0.05),
+        base_height: 0.0, height_amplitude: 60.0, water_level: -12.0,
+        surface: BlockGrass, surface_depth: 2.0,
+        subsurface: BlockDirt, subsurface_depth: 2.0,
+    },
+
+    /* desert */
+    BiomeParams {
+        climate: (0.45, -0.35),
+        base_height: 5.0, height_amplitude: 40.0, water_level: -30.0,
+        surface: BlockSand, surface_depth: 4.0,
+        subsurface: BlockSand, subsurface_depth: 8.0,
+    },
+
+    /* forest */
+    BiomeParams {
+        climate: (0.15, 0.25),
+        base_height: 5.0, height_amplitude: 80.0, water_level: -12.0,
+        surface: BlockGrass, surface_depth: 2.0,
+        subsurface: BlockDirt, subsurface_depth: 5.0,
+    },
+
+    /* tundra */
+    BiomeParams {
+        climate: (-0.4, 0.1),
+        base_height: 0.0, height_amplitude: 70.0, water_level: -12.0,
+        surface: BlockSnow, surface_depth: 2.0,
+        subsurface: BlockDirt, subsurface_depth: 4.0,
+    },
+
+    /* mountains */
+    BiomeParams {
+        climate: (-0.2, -0.35),
+        base_height: 40.0, height_amplitude: 220.0, water_level: -12.0,
+        surface: BlockStone, surface_depth: 1.0,
+        subsurface: BlockStone, subsurface_depth: 1.0,
+    },
+];
+
+impl Biome {
+    pub fn params(&self) -> &'static BiomeParams {
+        &biome_params[*self as uint]
+    }
+}
+
+/// Upper bound on the surface and water level of any column, given a bound
+/// on the magnitude of the height noise
+///
+/// Blended columns are weighted averages of the biomes, so no column is
+/// higher than the highest biome.
+pub fn max_surface_height(noise_bound: f64) -> f64 {
+    biome_params.iter().
+        map(|p| (p.base_height + p.height_amplitude * noise_bound).max(p.water_level)).
+        fold(std::f64::NEG_INFINITY, |a, b| a.max(b))
+}
+
+/// Lower bound on the surface of any column, given a bound on the magnitude
+/// of the height noise
+pub fn min_surface_height(noise_bound: f64) -> f64 {
+    biome_params.iter().
+        map(|p| p.base_height - p.height_amplitude * noise_bound).
+        fold(std::f64::INFINITY, |a, b| a.min(b))
+}
+
+/// Biome parameters for one column, blended across nearby biomes
+pub struct BiomeColumn {
+    /// The biome with the most influence
+    biome: Biome,
+    base_height: f64,
+    height_amplitude: f64,
+    water_level: f64,
+    surface: BlockType,
+    surface_depth: f64,
+    subsurface: BlockType,
+    subsurface_depth: f64,
+}
+
+pub struct BiomeMap {
+    temperature : Perlin,
+    moisture : Perlin,
+    blend_width : f64,
+}
+
+impl BiomeMap {
+    pub fn new(config: &WorldConfig) -> BiomeMap {
+        BiomeMap {
+            temperature: config.temperature.perlin(config.seed as int * 13, noise::Standard),
+            moisture: config.moisture.perlin(config.seed as int * 29, noise::Standard),
+            blend_width: config.biome_blend_width,
+        }
+    }
+
+    /// Returns (temperature, moisture) at a world position
+    pub fn climate(&self, x: f64, z: f64) -> (f64, f64) {
+        (self.temperature.get(x, 0.0, z), self.moisture.get(x, 0.0, z))
+    }
+
+    pub fn biome_at(&self, x: f64, z: f64) -> Biome {
+        self.column(x, z).biome
+    }
+
+    pub fn column(&self, x: f64, z: f64) -> BiomeColumn {
+        let (temperature, moisture) = self.climate(x, z);
+        blend(temperature, moisture, self.blend_width)
+    }
+}
+
+/// Weight each biome by its distance from the climate point. Numeric
+/// parameters are interpolated; block types come from the dominant biome.
+fn blend(temperature: f64, moisture: f64, width: f64) -> BiomeColumn {
+    let mut weights = [0.0, ..NUM_BIOMES];
+    let mut total = 0.0;
+    let mut dominant = 0;
+
+    for i in range(0, NUM_BIOMES) {
+        let (t, m) = biome_params[i].climate;
+        let d2 = (temperature - t) * (temperature - t) + (moisture - m) * (moisture - m);
+        weights[i] = (-d2 / (width * width)).exp();
+        total += weights[i];
+        if weights[i] > weights[dominant] {
+            dominant = i;
+        }
+    }
+
+    let mut column = BiomeColumn {
+        biome: biomes[dominant],
+        base_height: 0.0,
+        height_amplitude: 0.0,
+        water_level: 0.0,
+        surface: biome_params[dominant].surface,
+        surface_depth: 0.0,
+        subsurface: biome_params[dominant].subsurface,
+        subsurface_depth: 0.0,
+    };
+
+    // Far from every biome center all weights underflow; use the closest one
+    if total == 0.0 {
+        weights[dominant] = 1.0;
+        total = 1.0;
+    }
+
+    for i in range(0, NUM_BIOMES) {
+        let w = weights[i] / total;
+        let p = &biome_params[i];
+        column.base_height += p.base_height * w;
+        column.height_amplitude += p.height_amplitude * w;
+        column.water_level += p.water_level * w;
+        column.surface_depth += p.surface_depth * w;
+        column.subsurface_depth += p.subsurface_depth * w;
+    }
+
+    column
+}
+
+#[test]
+fn test_biome_centers() {
+    let width = WorldConfig::default().biome_blend_width;
+    for &biome in biomes.iter() {
+        let (t, m) = biome.params().climate;
+        assert_eq!(blend(t, m, width).biome, biome);
+    }
+}
+
+#[test]
+fn test_biome_blend_smooth() {
+    // Walk across climate space in small steps; blended heights should never jump
+    let width = WorldConfig::default().biome_blend_width;
+    let step = 0.001;
+    let mut prev = blend(-1.0, -0.7, width);
+    for i in range(0, 2000) {
+        let v = -1.0 + i as f64 * step;
+        let cur = blend(v, v * 0.7, width);
+        assert!((cur.base_height - prev.base_height).abs() < 1.0);
+        assert!((cur.height_amplitude - prev.height_amplitude).abs() < 2.0);
+        assert!((cur.water_level - prev.water_level).abs() < 1.0);
+        prev = cur;
+    }
+}
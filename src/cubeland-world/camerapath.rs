@@ -0,0 +1,314 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Recorded camera paths, for benchmarks that fly the same route every run
+//!
+//! A path is the camera's position and angles sampled every frame, with the
+//! time since recording began. Replaying samples rather than key and mouse
+//! input keeps playback identical from run to run, even in walk mode where
+//! the camera's motion depends on which chunks happened to be loaded.
+//!
+//! Paths are saved as text, one sample per line: time in seconds, position,
+//! then pitch and yaw in radians.
+
+extern crate cgmath;
+
+use std::f64::consts::PI;
+use std::io::File;
+
+use cgmath::vector::Vector;
+use cgmath::vector::Vec2;
+use cgmath::vector::Vec3;
+
+static HEADER : &'static str = "# cubeland camera path\n# time x y z pitch yaw\n";
+
+#[deriving(Clone, Eq, Show)]
+pub struct PathSample {
+    /// Seconds since the path began
+    time: f64,
+    position: Vec3<f64>,
+    angle: Vec2<f64>,
+}
+
+#[deriving(Clone, Eq, Show)]
+pub struct CameraPath {
+    /// In time order
+    priv samples: ~[PathSample],
+}
+
+impl CameraPath {
+    pub fn new() -> CameraPath {
+        CameraPath { samples: ~[] }
+    }
+
+    pub fn samples<'a>(&'a self) -> &'a [PathSample] {
+        self.samples.as_slice()
+    }
+
+    /// Samples earlier than the last one are dropped
+    pub fn push(&mut self, sample: PathSample) {
+        match self.samples.last() {
+            Some(last) if sample.time < last.time => return,
+            _ => {},
+        }
+        self.samples.push(sample);
+    }
+
+    /// Time of the last sample
+    pub fn duration(&self) -> f64 {
+        self.samples.last().map_or(0.0, |s| s.time)
+    }
+
+    /// Position and angles at a time, interpolated between the samples on
+    /// either side and held at the ends
+    ///
+    /// Angles turn the short way round, so a yaw that wraps from 2π to 0
+    /// does not spin the camera.
+    pub fn sample_at(&self, time: f64) -> Option<(Vec3<f64>, Vec2<f64>)> {
+        let (first, last) = match (self.samples.head(), self.samples.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return None,
+        };
+        if time <= first.time {
+            return Some((first.position, first.angle));
+        }
+        if time >= last.time {
+            return Some((last.position, last.angle));
+        }
+
+        // First sample after time
+        let i = self.samples.iter().position(|s| s.time > time).unwrap();
+        let a = &self.samples[i - 1];
+        let b = &self.samples[i];
+        let t = (time - a.time) / (b.time - a.time);
+        let position = a.position.add_v(&b.position.sub_v(&a.position).mul_s(t));
+        let angle = Vec2::new(lerp_angle(a.angle.x, b.angle.x, t),
+                              lerp_angle(a.angle.y, b.angle.y, t));
+        Some((position, angle))
+    }
+
+    pub fn to_str(&self) -> ~str {
+        let mut s = HEADER.to_owned();
+        for sample in self.samples.iter() {
+            s.push_str(format!("{} {} {} {} {} {}\n", sample.time,
+                               sample.position.x, sample.position.y, sample.position.z,
+                               sample.angle.x, sample.angle.y));
+        }
+        s
+    }
+
+    pub fn from_str(s: &str) -> Result<CameraPath, ~str> {
+        let mut path = CameraPath::new();
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with("#") {
+                continue;
+            }
+
+            let fields : ~[Option<f64>] = line.words().map(|w| from_str::<f64>(w)).collect();
+            if fields.len() != 6 || fields.iter().any(|f| f.is_none()) {
+                return Err(format!("line {}: expected time, x, y, z, pitch and yaw", i + 1));
+            }
+            let x : ~[f64] = fields.move_iter().map(|f| f.unwrap()).collect();
+            if x[0] < path.duration() {
+                return Err(format!("line {}: time goes backwards", i + 1));
+            }
+            path.push(PathSample {
+                time: x[0],
+                position: Vec3::new(x[1], x[2], x[3]),
+                angle: Vec2::new(x[4], x[5]),
+            });
+        }
+        Ok(path)
+    }
+
+    pub fn load(path: &Path) -> Result<CameraPath, ~str> {
+        let src = match File::open(path).read_to_end() {
+            Ok(src) => src,
+            Err(e) => return Err(format!("{}: {}", path.display(), e)),
+        };
+        match ::std::str::from_utf8(src) {
+            Some(s) => CameraPath::from_str(s).map_err(|e| format!("{}: {}", path.display(), e)),
+            None => Err(format!("{}: invalid UTF-8", path.display())),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), ~str> {
+        File::create(path).write_str(self.to_str()).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+/// Steps along a path at a fixed interval, however long each frame takes
+pub struct Playback {
+    priv path: CameraPath,
+    priv step: f64,
+    priv ticks: uint,
+}
+
+impl Playback {
+    pub fn new(path: CameraPath, step: f64) -> Playback {
+        assert!(step > 0.0);
+        Playback { path: path, step: step, ticks: 0 }
+    }
+
+    /// Frames it takes to reach the end of the path
+    pub fn len(&self) -> uint {
+        (self.path.duration() / self.step).floor() as uint + 1
+    }
+}
+
+impl Iterator<(Vec3<f64>, Vec2<f64>)> for Playback {
+    fn next(&mut self) -> Option<(Vec3<f64>, Vec2<f64>)> {
+        if self.ticks >= self.len() {
+            return None;
+        }
+        let time = self.ticks as f64 * self.step;
+        self.ticks += 1;
+        self.path.sample_at(time)
+    }
+}
+
+/// Interpolate from a to b the short way round
+fn lerp_angle(a: f64, b: f64, t: f64) -> f64 {
+    let mut d = (b - a) % (2.0 * PI);
+    if d > PI {
+        d -= 2.0 * PI;
+    } else if d < -PI {
+        d += 2.0 * PI;
+    }
+    a + d * t
+}
+
+/// Every frame time of a benchmark run, for exact percentiles
+pub struct FrameTimes {
+    /// Milliseconds
+    priv times: ~[f64],
+}
+
+impl FrameTimes {
+    pub fn new() -> FrameTimes {
+        FrameTimes { times: ~[] }
+    }
+
+    pub fn push(&mut self, ms: f64) {
+        self.times.push(ms);
+    }
+
+    pub fn len(&self) -> uint {
+        self.times.len()
+    }
+
+    pub fn mean(&self) -> f64 {
+        if self.times.is_empty() {
+            0.0
+        } else {
+            self.times.iter().fold(0.0, |a, &b| a + b) / self.times.len() as f64
+        }
+    }
+
+    /// Smallest time at least a fraction q of frames took no longer than
+    pub fn percentile(&self, q: f64) -> f64 {
+        if self.times.is_empty() {
+            return 0.0;
+        }
+        let mut sorted = self.times.clone();
+        sorted.sort_by(|a, b| if a < b { Less } else if a > b { Greater } else { Equal });
+        let rank = (q * sorted.len() as f64).ceil() as uint;
+        let index = if rank == 0 { 0 } else { rank - 1 };
+        sorted[index.min(sorted.len() - 1)]
+    }
+
+    /// One line with the frame count, mean and percentiles
+    pub fn summary(&self) -> ~str {
+        format!("{} frames, mean {:.2f}ms, p50 {:.2f}ms, p90 {:.2f}ms, p99 {:.2f}ms, max {:.2f}ms",
+                self.len(), self.mean(), self.percentile(0.5), self.percentile(0.9),
+                self.percentile(0.99), self.percentile(1.0))
+    }
+}
+
+#[cfg(test)]
+fn test_path() -> CameraPath {
+    let mut path = CameraPath::new();
+    path.push(PathSample { time: 0.0, position: Vec3::new(0.0, 20.0, 0.0), angle: Vec2::new(0.0, 0.1) });
+    path.push(PathSample { time: 1.0, position: Vec3::new(10.0, 20.0, -4.0), angle: Vec2::new(0.5, 2.0 * PI - 0.1) });
+    path.push(PathSample { time: 3.0, position: Vec3::new(10.0, 30.0, -4.0), angle: Vec2::new(0.5, 1.0) });
+    path
+}
+
+#[cfg(test)]
+fn assert_close(a: f64, b: f64) {
+    assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+}
+
+#[test]
+fn test_camera_path_interpolation() {
+    let path = test_path();
+    assert_eq!(path.duration(), 3.0);
+    assert_eq!(CameraPath::new().sample_at(0.0), None);
+
+    let (p, a) = path.sample_at(0.5).unwrap();
+    assert_eq!(p, Vec3::new(5.0, 20.0, -2.0));
+    assert_close(a.x, 0.25);
+    // Yaw goes back through 0 rather than forward through π
+    assert_close(a.y, 0.0);
+
+    let (p, _) = path.sample_at(2.0).unwrap();
+    assert_eq!(p, Vec3::new(10.0, 25.0, -4.0));
+
+    // Held at the ends
+    assert_eq!(path.sample_at(-1.0).unwrap().0, Vec3::new(0.0, 20.0, 0.0));
+    assert_eq!(path.sample_at(5.0).unwrap().0, Vec3::new(10.0, 30.0, -4.0));
+}
+
+#[test]
+fn test_camera_path_text() {
+    let path = test_path();
+    let parsed = CameraPath::from_str(path.to_str()).unwrap();
+    assert_eq!(parsed.samples().len(), path.samples().len());
+    for (a, b) in parsed.samples().iter().zip(path.samples().iter()) {
+        assert_close(a.time, b.time);
+        assert_close(a.position.x, b.position.x);
+        assert_close(a.position.y, b.position.y);
+        assert_close(a.position.z, b.position.z);
+        assert_close(a.angle.x, b.angle.x);
+        assert_close(a.angle.y, b.angle.y);
+    }
+
+    assert!(CameraPath::from_str("0 1 2 3 4").is_err());
+    assert!(CameraPath::from_str("0 1 2 3 4 x").is_err());
+    assert!(CameraPath::from_str("1 0 0 0 0 0\n0 0 0 0 0 0").is_err());
+    assert_eq!(CameraPath::from_str("# comment\n\n0 1 2 3 4 5\n").unwrap().samples().len(), 1);
+}
+
+#[test]
+fn test_playback_fixed_step() {
+    let playback = Playback::new(test_path(), 0.5);
+    assert_eq!(playback.len(), 7);
+    let heights : ~[f64] = playback.map(|(p, _)| p.y).collect();
+    assert_eq!(heights, ~[20.0, 20.0, 20.0, 22.5, 25.0, 27.5, 30.0]);
+}
+
+#[test]
+fn test_frame_times() {
+    let mut times = FrameTimes::new();
+    assert_eq!(times.percentile(0.5), 0.0);
+    for i in range(1, 101) {
+        times.push(i as f64);
+    }
+    assert_eq!(times.mean(), 50.5);
+    assert_eq!(times.percentile(0.5), 50.0);
+    assert_eq!(times.percentile(0.99), 99.0);
+    assert_eq!(times.percentile(1.0), 100.0);
+    assert_eq!(times.percentile(0.0), 1.0);
+}
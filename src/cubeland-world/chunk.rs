@@ -0,0 +1,959 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate cgmath;
+
+use std::comm::{Data, Empty, Disconnected};
+use std::rt::default_sched_threads;
+use std::num::Integer;
+use std::mem;
+use std::io::IoError;
+use collections::HashMap;
+use collections::HashSet;
+
+use sync::Arc;
+use sync::Mutex;
+
+use cgmath::vector::Vector;
+use cgmath::vector::Vec3;
+
+use CHUNK_SIZE;
+use client::Client;
+use protocol::{RequestChunk, ChunkData, SetBlock, PlayerPosition, PlayerLeft};
+use terrain::Block;
+use terrain::BlockType;
+use terrain::Terrain;
+use terrain::TerrainGenerator;
+use light::{LightMap, FACE_NEIGHBORS};
+use mesh::Mesh;
+use ratelimiter::RateLimiter;
+use metrics::{Metrics, LogError, LogInfo};
+use region::RegionStore;
+use registry::BlockRegistry;
+use config::WorldConfig;
+use view::ViewSet;
+use lru::LruCache;
+use pool::{JobPool, Worker, StageTimer};
+use lod::{LodChunk, LodLevel, lod_gen, lod_scale, select_levels};
+
+/// Chunks being loaded, per worker, including those waiting in the queue
+static INFLIGHT_PER_WORKER : uint = 4;
+
+/// Remeshes show edits, so they go ahead of every load
+static REMESH_PRIORITY : i64 = -1;
+
+enum WorkerRequest {
+    LoadChunk(Vec3<i64>),
+    /// Mesh terrain received from a server
+    BuildChunk(Vec3<i64>, ~Terrain),
+    /// Carries the terrain version, so a remesh that finishes after a newer
+    /// one can be dropped
+    RemeshChunk(Vec3<i64>, uint, ~Terrain, ~LightMap),
+    /// Generate a downsampled chunk at a level above 0
+    LoadLod(uint, Vec3<i64>),
+}
+
+enum WorkerResponse {
+    /// Carries the error when a saved chunk could not be read and was
+    /// generated instead, for the loader to log
+    ChunkLoaded(~Chunk, Option<IoError>),
+    ChunkRemeshed(Vec3<i64>, uint, ~Mesh),
+    LodLoaded(~LodChunk),
+}
+
+/// Where chunks come from and where edits go
+enum Backend {
+    /// Workers generate chunks, and evicted chunks are saved locally
+    BackendLocal(Arc<Mutex<RegionStore>>),
+    /// A server streams chunks, relays edits and saves the world
+    BackendRemote(Client),
+}
+
+pub struct ChunkLoader {
+    /// Chunks in view are pinned; the rest are evicted least recently
+    /// seen first once there are more than the capacity
+    cache : LruCache<(i64, i64, i64), ~Chunk>,
+    /// Chunks around the camera, which are never evicted
+    view: ViewSet,
+    visible_radius: uint,
+    inflight: HashSet<(i64, i64, i64)>,
+    /// Downsampled terrain past the view radius, starting at level 1
+    lods: ~[LodLevel],
+    remesh_queue: ~[Vec3<i64>],
+    /// Edits to chunks on their way, applied when they arrive. Edits to
+    /// chunks that are neither loaded nor in flight go straight to storage.
+    pending_edits: HashMap<(i64, i64, i64), ~[(Vec3<int>, BlockType)]>,
+    pool: JobPool<WorkerRequest, WorkerResponse>,
+    backend: Backend,
+    registry: Arc<BlockRegistry>,
+    /// Generates chunks that are edited before they were ever saved
+    terrain_generator: TerrainGenerator,
+    /// Source of Chunk::mesh_version, never reused
+    mesh_version_counter: uint,
+    metrics: Metrics,
+    load_rate_display_limiter: RateLimiter,
+    load_rate_counter: uint,
+}
+
+impl ChunkLoader {
+    pub fn new(config: &WorldConfig, store: RegionStore, registry: BlockRegistry) -> ChunkLoader {
+        let store = Arc::new(Mutex::new(store));
+        ChunkLoader::with_backend(config, BackendLocal(store), registry)
+    }
+
+    /// Stream chunks from a server instead of generating them
+    pub fn new_remote(config: &WorldConfig, client: Client, registry: BlockRegistry) -> ChunkLoader {
+        ChunkLoader::with_backend(config, BackendRemote(client), registry)
+    }
+
+    fn with_backend(config: &WorldConfig, backend: Backend, registry: BlockRegistry) -> ChunkLoader {
+        let registry = Arc::new(registry);
+        let store = match backend {
+            BackendLocal(ref store) => Some(store.clone()),
+            BackendRemote(_) => None,
+        };
+
+        // Distant terrain comes straight from the generator, which would not
+        // show what a server's world looks like
+        let lod_levels = match backend {
+            BackendLocal(_) => config.lod_levels,
+            BackendRemote(_) => 0,
+        };
+
+        let workers = range(0, default_sched_threads()).map(|_| {
+            ~ChunkWorker {
+                terrain_generator: TerrainGenerator::new(config, registry.get()),
+                registry: registry.clone(),
+                store: store.clone(),
+            } as ~Worker<WorkerRequest, WorkerResponse>:Send
+        }).collect();
+        let pool = JobPool::new(workers);
+        let terrain_generator = TerrainGenerator::new(config, registry.get());
+
+        ChunkLoader {
+            cache: LruCache::new((config.visible_radius*2)*(config.visible_radius*2)*(config.visible_radius*2)*2),
+            view: ViewSet::new(config.visible_radius),
+            visible_radius: config.visible_radius,
+            inflight: HashSet::new(),
+            lods: range(0, lod_levels).map(|i| LodLevel::new(i + 1, config.visible_radius, &terrain_generator)).collect(),
+            remesh_queue: ~[],
+            pending_edits: HashMap::new(),
+            pool: pool,
+            backend: backend,
+            terrain_generator: terrain_generator,
+            registry: registry,
+            mesh_version_counter: 0,
+            metrics: Metrics::new(LogInfo),
+            load_rate_display_limiter: RateLimiter::new(1000*1000*1000),
+            load_rate_counter: 0,
+        }
+    }
+
+    /// Whether everything in view has been loaded and meshed, as of the
+    /// last call to work
+    pub fn is_idle(&self) -> bool {
+        self.inflight.is_empty() &&
+            self.lods.iter().all(|lod| lod.inflight.is_empty()) &&
+            self.remesh_queue.is_empty() &&
+            self.pool.outstanding() == 0
+    }
+
+    /// Record into a registry shared with the rest of the game
+    pub fn set_metrics(&mut self, metrics: Metrics) {
+        self.metrics = metrics;
+    }
+
+    pub fn get<'a>(&'a self, c: Vec3<i64>) -> Option<&'a ~Chunk> {
+        self.cache.find(&(c.x, c.y, c.z))
+    }
+
+    /// Change the block at a world position
+    ///
+    /// Updates the owning chunk and the border copies held by its neighbors
+    /// and queues all of them for remeshing. Neighbors on their way get the
+    /// edit when they arrive, and the rest are edited in storage. Returns
+    /// false if the owning chunk is not loaded.
+    ///
+    /// When connected to a server the edit is also sent there. The server
+    /// echoes it back in the order it applied it, so concurrent edits to the
+    /// same block end up the same for every player.
+    pub fn set_block(&mut self, p: Vec3<i64>, blocktype: BlockType) -> bool {
+        let (chunk_coord, _) = world_to_chunk(p);
+        if !self.cache.contains_key(&(chunk_coord.x, chunk_coord.y, chunk_coord.z)) {
+            return false;
+        }
+
+        match self.backend {
+            BackendRemote(ref mut client) => {
+                match client.send(&SetBlock(p, blocktype)) {
+                    Ok(()) => {},
+                    Err(e) => fail!("lost connection to server: {}", e),
+                }
+            },
+            BackendLocal(_) => {},
+        }
+
+        self.apply_block(p, blocktype);
+        true
+    }
+
+    /// Tell the server where the player is; does nothing for local worlds
+    pub fn send_position(&mut self, p: Vec3<f64>) {
+        match self.backend {
+            BackendRemote(ref mut client) => {
+                let player_id = client.player_id();
+                match client.send(&PlayerPosition(player_id, p)) {
+                    Ok(()) => {},
+                    Err(e) => fail!("lost connection to server: {}", e),
+                }
+            },
+            BackendLocal(_) => {},
+        }
+    }
+
+    fn apply_block(&mut self, p: Vec3<i64>, blocktype: BlockType) {
+        let (chunk_coord, local) = world_to_chunk(p);
+        for dx in range(-1i64, 2) {
+            for dy in range(-1i64, 2) {
+                for dz in range(-1i64, 2) {
+                    let c = chunk_coord.add_v(&Vec3::new(dx, dy, dz));
+                    let l = p.sub_v(&c.mul_s(CHUNK_SIZE as i64));
+                    if !in_padded_chunk(l) {
+                        continue;
+                    }
+
+                    let local = Vec3::new(l.x as int, l.y as int, l.z as int);
+                    let key = (c.x, c.y, c.z);
+                    match self.cache.find_mut(&key) {
+                        Some(chunk) => {
+                            chunk.terrain.set(local.x, local.y, local.z, blocktype);
+                            chunk.terrain_version += 1;
+                            if !self.remesh_queue.contains(&c) {
+                                self.remesh_queue.push(c);
+                            }
+                            continue;
+                        },
+                        None => {},
+                    }
+
+                    if self.inflight.contains(&key) {
+                        self.pending_edits.
+                            find_or_insert_with(key, |_| ~[]).
+                            push((local, blocktype));
+                    } else {
+                        self.write_through(c, [(local, blocktype)]);
+                    }
+                }
+            }
+        }
+
+        match self.cache.find_mut(&(chunk_coord.x, chunk_coord.y, chunk_coord.z)) {
+            Some(chunk) => chunk.light.update_block(chunk.terrain, self.registry.get(), local),
+            None => return,
+        }
+        self.spread_light(chunk_coord);
+    }
+
+    /// Apply edits to the stored copy of a chunk that is not loaded
+    ///
+    /// Chunks that were never saved are generated first. With a server
+    /// there is nothing to do, as it saves edits itself.
+    fn write_through(&self, c: Vec3<i64>, edits: &[(Vec3<int>, BlockType)]) {
+        let store = match self.backend {
+            BackendLocal(ref store) => store,
+            BackendRemote(_) => return,
+        };
+
+        let mut store = store.lock();
+        let mut terrain = match store.load(c) {
+            Ok(Some(terrain)) => terrain,
+            Ok(None) => {
+                let p = Vec3::new(c.x as f64, c.y as f64, c.z as f64).mul_s(CHUNK_SIZE as f64);
+                self.terrain_generator.gen(p)
+            },
+            Err(e) => {
+                self.metrics.log(LogError, format!("failed to load chunk ({}, {}, {}): {}", c.x, c.y, c.z, e));
+                return;
+            },
+        };
+
+        for &(l, blocktype) in edits.iter() {
+            terrain.set(l.x, l.y, l.z, blocktype);
+        }
+
+        match store.save(c, terrain) {
+            Ok(()) => {},
+            Err(e) => self.metrics.log(LogError, format!("failed to save chunk ({}, {}, {}): {}", c.x, c.y, c.z, e)),
+        }
+    }
+
+    /// Pass a change in a chunk's light on to its neighbors
+    ///
+    /// Each loaded neighbor copies the chunk's edge into its padding. The
+    /// ones whose meshes show the change are queued for remeshing, and the
+    /// ones whose own light changed pass it on in turn, until the light
+    /// settles.
+    fn spread_light(&mut self, c: Vec3<i64>) {
+        let registry = self.registry.get();
+        let mut queue = ~[c];
+        while !queue.is_empty() {
+            let c = queue.shift().unwrap();
+            for o in FACE_NEIGHBORS.iter() {
+                let edge = match self.cache.find(&(c.x, c.y, c.z)) {
+                    Some(chunk) => chunk.light.edge(*o),
+                    None => break,
+                };
+
+                let n = c.add_v(&Vec3::new(o.x as i64, o.y as i64, o.z as i64));
+                match self.cache.find_mut(&(n.x, n.y, n.z)) {
+                    Some(neighbor) => {
+                        let change = neighbor.light.update_padding(neighbor.terrain, registry, o.mul_s(-1), edge);
+                        if change.visible {
+                            neighbor.terrain_version += 1;
+                            if !self.remesh_queue.contains(&n) {
+                                self.remesh_queue.push(n);
+                            }
+                        }
+                        if change.interior && !queue.contains(&n) {
+                            queue.push(n);
+                        }
+                    },
+                    None => {},
+                }
+            }
+        }
+    }
+
+    /// Move the camera, queueing the chunks that came into view for loading
+    ///
+    /// Cheap unless the camera crossed into another chunk.
+    pub fn set_view(&mut self, position: Vec3<f64>, direction: Vec3<f64>) {
+        let change = self.view.update(position, direction);
+
+        // Out of view chunks are evicted in the order they left it
+        for c in change.left.iter() {
+            self.cache.unpin(&(c.x, c.y, c.z));
+        }
+        for c in change.entered.iter() {
+            self.cache.pin(&(c.x, c.y, c.z));
+        }
+
+        let mut reprioritized = change.reprioritized;
+        for (i, lod) in self.lods.mut_iter().enumerate() {
+            let scale = lod_scale(i + 1) as f64;
+            let change = lod.view.update(position.div_s(scale), direction);
+            for c in change.left.iter() {
+                lod.chunks.remove(&(c.x, c.y, c.z));
+            }
+            reprioritized = reprioritized || change.reprioritized;
+        }
+
+        if reprioritized {
+            let cancelled = {
+                let view = &self.view;
+                let lods = &self.lods;
+                self.pool.reorder(|job| {
+                    match *job {
+                        LoadChunk(c) | BuildChunk(c, _) => {
+                            if view.contains(c) { Some(view.cost(c)) } else { None }
+                        },
+                        RemeshChunk(..) => Some(REMESH_PRIORITY),
+                        LoadLod(level, c) => {
+                            let lod = &lods[level - 1];
+                            if lod.view.contains(c) { Some(lod_cost(lod, level, c)) } else { None }
+                        },
+                    }
+                })
+            };
+            for job in cancelled.iter() {
+                match *job {
+                    LoadChunk(c) | BuildChunk(c, _) => {
+                        let key = (c.x, c.y, c.z);
+                        self.inflight.remove(&key);
+                        match self.pending_edits.pop(&key) {
+                            Some(edits) => self.write_through(c, edits.as_slice()),
+                            None => {},
+                        }
+                    },
+                    RemeshChunk(..) => {},
+                    LoadLod(level, c) => { self.lods[level - 1].inflight.remove(&(c.x, c.y, c.z)); },
+                }
+            }
+        }
+    }
+
+    /// How far from the camera terrain is loaded, in blocks
+    pub fn view_distance(&self) -> f64 {
+        (self.visible_radius as int * CHUNK_SIZE * lod_scale(self.lods.len())) as f64
+    }
+
+    /// Loaded chunks to draw, nearest first within each level
+    ///
+    /// Past the view radius, and nearer where full resolution chunks have
+    /// not loaded yet, downsampled chunks are drawn instead. Each part of
+    /// the world is covered by only one of the two lists.
+    pub fn visible_chunks<'a>(&'a self) -> (~[&'a ~Chunk], ~[&'a ~LodChunk]) {
+        let top_coords = match self.lods.last() {
+            Some(lod) => lod.view.coords(),
+            None => self.view.coords(),
+        };
+
+        let selected = select_levels(self.lods.len(), top_coords, |level, c| {
+            let key = (c.x, c.y, c.z);
+            if level == 0 {
+                self.view.contains(c) && self.cache.contains_key(&key)
+            } else {
+                self.lods[level - 1].chunks.contains_key(&key)
+            }
+        });
+
+        let mut chunks = ~[];
+        let mut lod_chunks = ~[];
+        for &(level, c) in selected.iter() {
+            let key = (c.x, c.y, c.z);
+            if level == 0 {
+                chunks.push(self.cache.find(&key).unwrap());
+            } else {
+                lod_chunks.push(self.lods[level - 1].chunks.find(&key).unwrap());
+            }
+        }
+
+        (chunks, lod_chunks)
+    }
+
+    pub fn work(&mut self) {
+        self.poll_server();
+
+        loop {
+            match self.pool.try_recv() {
+                Some(ChunkLoaded(mut chunk, load_error)) => {
+                    let c = chunk.coord;
+                    match load_error {
+                        Some(e) => self.metrics.log(LogError,
+                            format!("failed to load chunk ({}, {}, {}): {}", c.x, c.y, c.z, e)),
+                        None => {},
+                    }
+                    self.metrics.record("chunk.mesh_bytes", chunk.mesh.size_bytes() as f64);
+                    let mut changed = false;
+                    match self.pending_edits.pop(&(c.x, c.y, c.z)) {
+                        Some(edits) => {
+                            for &(l, blocktype) in edits.iter() {
+                                chunk.terrain.set(l.x, l.y, l.z, blocktype);
+                                if in_chunk(l) {
+                                    chunk.light.update_block(chunk.terrain, self.registry.get(), l);
+                                }
+                            }
+                            changed = true;
+                        },
+                        None => {},
+                    }
+
+                    // The worker lit the chunk without its neighbors
+                    for o in FACE_NEIGHBORS.iter() {
+                        let n = c.add_v(&Vec3::new(o.x as i64, o.y as i64, o.z as i64));
+                        match self.cache.find(&(n.x, n.y, n.z)) {
+                            Some(neighbor) => {
+                                let edge = neighbor.light.edge(o.mul_s(-1));
+                                if chunk.light.update_padding(chunk.terrain, self.registry.get(), *o, edge).visible {
+                                    changed = true;
+                                }
+                            },
+                            None => {},
+                        }
+                    }
+
+                    if changed {
+                        chunk.terrain_version += 1;
+                        self.remesh_queue.push(c);
+                    }
+                    self.mesh_version_counter += 1;
+                    chunk.mesh_version = self.mesh_version_counter;
+                    self.cache.insert((c.x, c.y, c.z), chunk);
+                    if self.view.contains(c) {
+                        self.cache.pin(&(c.x, c.y, c.z));
+                    }
+                    self.inflight.remove(&(c.x, c.y, c.z));
+                    self.load_rate_counter += 1;
+                    self.metrics.count("chunk.loaded", 1);
+                    self.spread_light(c);
+                },
+                Some(LodLoaded(mut chunk)) => {
+                    let lod = &mut self.lods[chunk.level - 1];
+                    let key = (chunk.coord.x, chunk.coord.y, chunk.coord.z);
+                    lod.inflight.remove(&key);
+                    if lod.view.contains(chunk.coord) {
+                        self.mesh_version_counter += 1;
+                        chunk.mesh_version = self.mesh_version_counter;
+                        lod.chunks.insert(key, chunk);
+                    }
+                },
+                Some(ChunkRemeshed(c, terrain_version, mesh)) => {
+                    match self.cache.find_mut(&(c.x, c.y, c.z)) {
+                        Some(chunk) => {
+                            if terrain_version > chunk.mesh_terrain_version {
+                                self.metrics.count("chunk.remeshed", 1);
+                                self.metrics.record("chunk.mesh_bytes", mesh.size_bytes() as f64);
+                                chunk.mesh = mesh;
+                                chunk.mesh_terrain_version = terrain_version;
+                                self.mesh_version_counter += 1;
+                                chunk.mesh_version = self.mesh_version_counter;
+                            }
+                        },
+                        None => {},
+                    }
+                },
+                None => break,
+            }
+        }
+
+        let backend = &self.backend;
+        let metrics = &self.metrics;
+        self.cache.evict(|_, chunk| save_chunk(backend, metrics, chunk));
+
+        while !self.remesh_queue.is_empty() {
+            let c = self.remesh_queue.shift().unwrap();
+            match self.cache.find(&(c.x, c.y, c.z)) {
+                Some(chunk) => {
+                    self.pool.submit(RemeshChunk(c, chunk.terrain_version, chunk.terrain.clone(),
+                                                 chunk.light.clone()),
+                                     REMESH_PRIORITY);
+                },
+                None => {},
+            }
+        }
+
+        while self.inflight.len() < self.pool.num_workers() * INFLIGHT_PER_WORKER {
+            let c = match self.view.pop() {
+                Some(c) => c,
+                None => break,
+            };
+            let key = (c.x, c.y, c.z);
+            if self.cache.contains_key(&key) || self.inflight.contains(&key) {
+                continue;
+            }
+            self.inflight.insert(key);
+            match self.backend {
+                BackendLocal(_) => {
+                    self.pool.submit(LoadChunk(c), self.view.cost(c));
+                },
+                BackendRemote(ref mut client) => {
+                    match client.send(&RequestChunk(c)) {
+                        Ok(()) => {},
+                        Err(e) => fail!("lost connection to server: {}", e),
+                    }
+                },
+            }
+        }
+
+        // Distant terrain gets whatever room full resolution chunks left
+        let mut num_inflight = self.lods.iter().fold(self.inflight.len(), |n, lod| n + lod.inflight.len());
+        for (i, lod) in self.lods.mut_iter().enumerate() {
+            let level = i + 1;
+            while num_inflight < self.pool.num_workers() * INFLIGHT_PER_WORKER {
+                let c = match lod.view.pop() {
+                    Some(c) => c,
+                    None => break,
+                };
+                let key = (c.x, c.y, c.z);
+                if lod.chunks.contains_key(&key) || lod.inflight.contains(&key) {
+                    continue;
+                }
+                lod.inflight.insert(key);
+                num_inflight += 1;
+                self.pool.submit(LoadLod(level, c), lod_cost(lod, level, c));
+            }
+        }
+
+        self.record_metrics(num_inflight);
+    }
+
+    /// Gauges, and the pool's counters and stage latencies since the last
+    /// call
+    fn record_metrics(&mut self, num_inflight: uint) {
+        let stats = self.pool.take_stats();
+        self.metrics.count("pool.completed", stats.completed as u64);
+        self.metrics.count("pool.cancelled", stats.cancelled as u64);
+        for &(stage, ref latency) in stats.latencies.iter() {
+            self.metrics.merge(format!("job.{}_us", stage), latency);
+        }
+
+        self.metrics.set_gauge("pool.workers", self.pool.num_workers() as f64);
+        self.metrics.set_gauge("pool.queue_depth", stats.queue_depth as f64);
+        self.metrics.set_gauge("chunk.inflight", num_inflight as f64);
+        self.metrics.set_gauge("chunk.cache_size", self.cache.len() as f64);
+
+        if self.load_rate_counter > 0 && self.load_rate_display_limiter.limit() {
+            self.metrics.debug(format!("loaded {} chunks over the last second, {} in flight",
+                                       self.load_rate_counter, num_inflight));
+            self.load_rate_counter = 0;
+        }
+    }
+
+    /// Handle everything the server has sent since the last call
+    fn poll_server(&mut self) {
+        loop {
+            let msg = match self.backend {
+                BackendRemote(ref client) => {
+                    match client.try_recv() {
+                        Data(msg) => msg,
+                        Empty => return,
+                        Disconnected => fail!("lost connection to server"),
+                    }
+                },
+                BackendLocal(_) => return,
+            };
+
+            match msg {
+                ChunkData(c, terrain) => {
+                    if self.view.contains(c) {
+                        self.pool.submit(BuildChunk(c, terrain), self.view.cost(c));
+                    } else {
+                        self.inflight.remove(&(c.x, c.y, c.z));
+                    }
+                },
+                SetBlock(p, blocktype) => {
+                    // Chunks that are not loaded or on their way will come
+                    // from the server with the edit already applied
+                    let (c, _) = world_to_chunk(p);
+                    let key = (c.x, c.y, c.z);
+                    if self.cache.contains_key(&key) || self.inflight.contains(&key) {
+                        self.apply_block(p, blocktype);
+                    }
+                },
+                // Other players are not drawn
+                PlayerPosition(..) | PlayerLeft(_) => {},
+                _ => self.metrics.log(LogError, "unexpected message from server"),
+            }
+        }
+    }
+
+    /// Write every cached chunk, and edits to chunks still on their way,
+    /// back to the region store
+    pub fn save_all(&mut self) {
+        for (_, chunk) in self.cache.iter() {
+            save_chunk(&self.backend, &self.metrics, *chunk);
+        }
+
+        let pending = mem::replace(&mut self.pending_edits, HashMap::new());
+        for (&(x, y, z), edits) in pending.iter() {
+            self.write_through(Vec3::new(x, y, z), edits.as_slice());
+        }
+    }
+}
+
+fn save_chunk(backend: &Backend, metrics: &Metrics, chunk: &Chunk) {
+    let store = match *backend {
+        BackendLocal(ref store) => store,
+        // The server saves edits as it applies them
+        BackendRemote(_) => return,
+    };
+
+    let c = chunk.coord;
+    match store.lock().save(c, chunk.terrain) {
+        Ok(()) => {},
+        Err(e) => metrics.log(LogError, format!("failed to save chunk ({}, {}, {}): {}", c.x, c.y, c.z, e)),
+    }
+}
+
+/// Loading order of a downsampled chunk, comparable with ViewSet::cost for
+/// full resolution chunks at the same distance
+fn lod_cost(lod: &LodLevel, level: uint, c: Vec3<i64>) -> i64 {
+    lod.view.cost(c) * lod_scale(level) as i64
+}
+
+/// Read access to individual blocks by world position
+pub trait BlockSource {
+    fn registry<'a>(&'a self) -> &'a BlockRegistry;
+
+    /// Returns None if the chunk containing the block is not loaded
+    fn get_block(&self, p: Vec3<i64>) -> Option<Block>;
+}
+
+impl BlockSource for ChunkLoader {
+    fn registry<'a>(&'a self) -> &'a BlockRegistry {
+        self.registry.get()
+    }
+
+    fn get_block(&self, p: Vec3<i64>) -> Option<Block> {
+        let (chunk_coord, l) = world_to_chunk(p);
+        self.get(chunk_coord).map(|chunk| *chunk.terrain.get(l.x, l.y, l.z))
+    }
+}
+
+pub struct Chunk {
+    coord: Vec3<i64>,
+    terrain: ~Terrain,
+    light: ~LightMap,
+    mesh: ~Mesh,
+    /// Changes whenever mesh is replaced, so renderers know to upload it again
+    mesh_version: uint,
+    /// Bumped by every edit to terrain and every change to light
+    terrain_version: uint,
+    /// The terrain_version that mesh was built from
+    mesh_terrain_version: uint,
+}
+
+/// Split a world block position into a chunk coordinate and the position
+/// within that chunk
+pub fn world_to_chunk(p: Vec3<i64>) -> (Vec3<i64>, Vec3<int>) {
+    let s = CHUNK_SIZE as i64;
+    let chunk_coord = Vec3::new(p.x.div_floor(&s), p.y.div_floor(&s), p.z.div_floor(&s));
+    let local = Vec3::new(p.x.mod_floor(&s) as int, p.y.mod_floor(&s) as int, p.z.mod_floor(&s) as int);
+    (chunk_coord, local)
+}
+
+/// Coordinates of the chunks within radius chunks of a world position,
+/// nearest first
+pub fn nearby_chunk_coords(p: Vec3<f64>, radius: uint) -> ~[Vec3<i64>] {
+    let cur_chunk_coord = Vec3::new(p.x as i64, p.y as i64, p.z as i64).div_s(CHUNK_SIZE as i64);
+    let r = radius as i64;
+
+    let mut coords = ~[];
+
+    for x in range(-r, r+1) {
+        for y in range(-r, r+1) {
+            for z in range(-r, r+1) {
+                let c = Vec3::new(x, y, z);
+                if c.dot(&c) < r*r {
+                    coords.push(c);
+                }
+            }
+        }
+    }
+
+    coords.sort_by(|b,a| b.dot(b).cmp(&a.dot(a)));
+
+    for c in coords.mut_iter() {
+        c.add_self_v(&cur_chunk_coord);
+    }
+
+    coords
+}
+
+fn in_chunk(l: Vec3<int>) -> bool {
+    l.x >= 0 && l.x < CHUNK_SIZE && l.y >= 0 && l.y < CHUNK_SIZE && l.z >= 0 && l.z < CHUNK_SIZE
+}
+
+/// Whether a position relative to a chunk's origin is stored in that chunk,
+/// either as one of its own blocks or as padding copied from a neighbor
+pub fn in_padded_chunk(l: Vec3<i64>) -> bool {
+    let s = CHUNK_SIZE as i64;
+    l.x >= -1 && l.x <= s && l.y >= -1 && l.y <= s && l.z >= -1 && l.z <= s
+}
+
+/// Load a chunk from the region store, generating it if it was never saved
+///
+/// A chunk that cannot be read is generated too, and the error returned
+/// alongside it.
+pub fn chunk_load(terrain_generator: &TerrainGenerator,
+                  registry: &BlockRegistry,
+                  store: &Arc<Mutex<RegionStore>>,
+                  coord: Vec3<i64>,
+                  timer: &mut StageTimer) -> (~Chunk, Option<IoError>) {
+    let (saved, error) = match store.lock().load(coord) {
+        Ok(saved) => (saved, None),
+        Err(e) => (None, Some(e)),
+    };
+
+    let chunk = match saved {
+        Some(terrain) => {
+            timer.mark("load");
+            chunk_from_terrain(terrain_generator, registry, coord, terrain, timer)
+        },
+        None => chunk_gen(terrain_generator, registry, coord, timer),
+    };
+    (chunk, error)
+}
+
+/// Light and mesh terrain that was loaded or received rather than generated
+///
+/// The generator guesses at the sky light coming from the chunk above,
+/// which is fixed up once that chunk is loaded.
+pub fn chunk_from_terrain(terrain_generator: &TerrainGenerator, registry: &BlockRegistry,
+                          coord: Vec3<i64>, terrain: ~Terrain, timer: &mut StageTimer) -> ~Chunk {
+    let p = Vec3::new(coord.x as f64, coord.y as f64, coord.z as f64).mul_s(CHUNK_SIZE as f64);
+    let top = p.y + CHUNK_SIZE as f64;
+    let light = LightMap::compute(terrain, registry, |x, z| {
+        terrain_generator.open_to_sky(p.x + x as f64, top, p.z + z as f64)
+    });
+    timer.mark("light");
+    let mesh = Mesh::gen(terrain, light, registry);
+    timer.mark("mesh");
+    ~Chunk {
+        coord: coord,
+        terrain: terrain,
+        light: light,
+        mesh: mesh,
+        mesh_version: 0,
+        terrain_version: 0,
+        mesh_terrain_version: 0,
+    }
+}
+
+pub fn chunk_gen(terrain_generator: &TerrainGenerator, registry: &BlockRegistry, coord: Vec3<i64>,
+                 timer: &mut StageTimer) -> ~Chunk {
+    let p = Vec3::new(coord.x as f64, coord.y as f64, coord.z as f64).mul_s(CHUNK_SIZE as f64);
+    let terrain = terrain_generator.gen(p);
+    timer.mark("terrain");
+    chunk_from_terrain(terrain_generator, registry, coord, terrain, timer)
+}
+
+/// Runs on a pool thread, with its own terrain generator
+struct ChunkWorker {
+    terrain_generator: TerrainGenerator,
+    registry: Arc<BlockRegistry>,
+    /// None when chunks come from a server
+    store: Option<Arc<Mutex<RegionStore>>>,
+}
+
+impl Worker<WorkerRequest, WorkerResponse> for ChunkWorker {
+    fn run(&mut self, job: WorkerRequest, timer: &mut StageTimer) -> WorkerResponse {
+        let registry = self.registry.get();
+        match job {
+            LoadChunk(c) => {
+                let (chunk, error) = match self.store {
+                    Some(ref store) => chunk_load(&self.terrain_generator, registry, store, c, timer),
+                    None => (chunk_gen(&self.terrain_generator, registry, c, timer), None),
+                };
+                ChunkLoaded(chunk, error)
+            },
+            BuildChunk(c, terrain) => {
+                ChunkLoaded(chunk_from_terrain(&self.terrain_generator, registry, c, terrain, timer), None)
+            },
+            RemeshChunk(c, terrain_version, terrain, light) => {
+                let mesh = Mesh::gen(terrain, light, registry);
+                timer.mark("mesh");
+                ChunkRemeshed(c, terrain_version, mesh)
+            },
+            LoadLod(level, c) => {
+                LodLoaded(lod_gen(&self.terrain_generator, registry, level, c, timer))
+            },
+        }
+    }
+}
+
+/// A fixed set of hand-built chunks for testing code that uses BlockSource
+#[cfg(test)]
+pub struct TestWorld {
+    chunks: HashMap<(i64, i64, i64), ~Terrain>,
+    registry: BlockRegistry,
+}
+
+#[cfg(test)]
+impl TestWorld {
+    pub fn new(chunk_coords: &[(i64, i64, i64)]) -> TestWorld {
+        let mut chunks = HashMap::new();
+        for &c in chunk_coords.iter() {
+            chunks.insert(c, Terrain::new());
+        }
+        TestWorld { chunks: chunks, registry: BlockRegistry::builtin() }
+    }
+
+    pub fn set(&mut self, p: Vec3<i64>, blocktype: BlockType) {
+        let (c, l) = world_to_chunk(p);
+        self.chunks.get_mut(&(c.x, c.y, c.z)).set(l.x, l.y, l.z, blocktype);
+    }
+}
+
+#[cfg(test)]
+impl BlockSource for TestWorld {
+    fn registry<'a>(&'a self) -> &'a BlockRegistry {
+        &self.registry
+    }
+
+    fn get_block(&self, p: Vec3<i64>) -> Option<Block> {
+        let (c, l) = world_to_chunk(p);
+        self.chunks.find(&(c.x, c.y, c.z)).map(|t| *t.get(l.x, l.y, l.z))
+    }
+}
+
+#[test]
+fn test_world_to_chunk() {
+    assert_eq!(world_to_chunk(Vec3::new(0, 0, 0)), (Vec3::new(0, 0, 0), Vec3::new(0, 0, 0)));
+    assert_eq!(world_to_chunk(Vec3::new(31, 32, 33)), (Vec3::new(0, 1, 1), Vec3::new(31, 0, 1)));
+    assert_eq!(world_to_chunk(Vec3::new(-1, -32, -33)), (Vec3::new(-1, -1, -2), Vec3::new(31, 0, 31)));
+}
+
+#[cfg(test)]
+fn test_loader(dir: &Path, visible_radius: uint) -> ChunkLoader {
+    let mut config = WorldConfig::default();
+    config.visible_radius = visible_radius;
+    config.lod_levels = 0;
+    let store = RegionStore::new(dir.clone()).unwrap();
+    ChunkLoader::new(&config, store, BlockRegistry::builtin())
+}
+
+/// Load everything around the origin chunk
+#[cfg(test)]
+fn load_origin(loader: &mut ChunkLoader) {
+    loader.set_view(Vec3::new(16.0, 16.0, 16.0), Vec3::new(0.0, 0.0, -1.0));
+    loop {
+        loader.work();
+        if loader.is_idle() {
+            break;
+        }
+        ::std::task::deschedule();
+    }
+}
+
+#[test]
+fn test_set_block_padding() {
+    use std::io::TempDir;
+    use terrain::BlockWater;
+
+    let dir = TempDir::new("cubeland-chunk").unwrap();
+    let mut loader = test_loader(dir.path(), 2);
+    load_origin(&mut loader);
+
+    // A corner block is padding in the seven chunks around that corner
+    assert!(loader.set_block(Vec3::new(0, 0, 0), BlockWater));
+    assert_eq!(loader.get(Vec3::new(0, 0, 0)).unwrap().terrain.get(0, 0, 0).blocktype, BlockWater);
+    for &(c, l) in [(Vec3::new(-1i64, 0, 0), (32, 0, 0)),
+                    (Vec3::new(0i64, -1, 0), (0, 32, 0)),
+                    (Vec3::new(-1i64, -1, -1), (32, 32, 32))].iter() {
+        let (x, y, z) = l;
+        let chunk = loader.get(c).expect("neighbor not loaded");
+        assert_eq!(chunk.terrain.get(x, y, z).blocktype, BlockWater);
+        assert!(loader.remesh_queue.contains(&c));
+    }
+    assert!(loader.pending_edits.is_empty());
+
+    // Edits outside loaded chunks are refused
+    assert!(!loader.set_block(Vec3::new(0, 0, 1000), BlockWater));
+}
+
+#[test]
+fn test_set_block_unloaded_neighbor() {
+    use std::io::TempDir;
+    use terrain::BlockWater;
+
+    let dir = TempDir::new("cubeland-chunk").unwrap();
+    {
+        // Only the origin chunk is in view
+        let mut loader = test_loader(dir.path(), 1);
+        load_origin(&mut loader);
+        assert!(loader.get(Vec3::new(-1, 0, 0)).is_none());
+
+        assert!(loader.set_block(Vec3::new(0, 5, 6), BlockWater));
+        assert!(loader.pending_edits.is_empty());
+        loader.save_all();
+    }
+
+    // The neighbor was never saved, so it was generated and saved with the edit
+    let mut store = RegionStore::new(dir.path().clone()).unwrap();
+    let neighbor = store.load(Vec3::new(-1, 0, 0)).unwrap().expect("neighbor not saved");
+    assert_eq!(neighbor.get(32, 5, 6).blocktype, BlockWater);
+    let chunk = store.load(Vec3::new(0, 0, 0)).unwrap().expect("chunk not saved");
+    assert_eq!(chunk.get(0, 5, 6).blocktype, BlockWater);
+}
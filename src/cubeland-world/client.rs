@@ -0,0 +1,102 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::comm::TryRecvResult;
+use std::io;
+use std::io::{IoError, IoResult};
+use std::io::net::ip::SocketAddr;
+use std::io::net::tcp::TcpStream;
+
+use protocol::{Message, Hello, Welcome, Rejected};
+use protocol::{PROTOCOL_VERSION, read_message, write_message};
+
+/// Connection to a cubeland server
+///
+/// Messages from the server are read by a separate task and queued until
+/// they are received.
+pub struct Client {
+    player_id: u32,
+    stream: TcpStream,
+    incoming: Receiver<Message>,
+}
+
+impl Client {
+    pub fn connect(addr: SocketAddr) -> IoResult<Client> {
+        let mut stream = try!(TcpStream::connect(addr));
+        try!(write_message(&mut stream, &Hello(PROTOCOL_VERSION)));
+
+        let player_id = match try!(read_message(&mut stream)) {
+            Welcome(player_id) => player_id,
+            Rejected(reason) => return Err(IoError {
+                kind: io::ConnectionRefused,
+                desc: "server rejected the connection",
+                detail: Some(reason),
+            }),
+            _ => return Err(IoError {
+                kind: io::InvalidInput,
+                desc: "unexpected reply to hello",
+                detail: None,
+            }),
+        };
+
+        let (tx, rx) = channel();
+        let mut reader = stream.clone();
+        spawn(proc() {
+            loop {
+                match read_message(&mut reader) {
+                    Ok(msg) => {
+                        if tx.send_opt(msg).is_err() {
+                            break;
+                        }
+                    },
+                    Err(_) => break,
+                }
+            }
+        });
+
+        Ok(Client {
+            player_id: player_id,
+            stream: stream,
+            incoming: rx,
+        })
+    }
+
+    pub fn player_id(&self) -> u32 {
+        self.player_id
+    }
+
+    pub fn send(&mut self, msg: &Message) -> IoResult<()> {
+        write_message(&mut self.stream, msg)
+    }
+
+    /// Returns Disconnected once the server has closed the connection and
+    /// every message before that was received
+    pub fn try_recv(&self) -> TryRecvResult<Message> {
+        self.incoming.try_recv()
+    }
+
+    /// Blocks until a message arrives, or returns None if the connection closed
+    pub fn recv(&self) -> Option<Message> {
+        self.incoming.recv_opt()
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        // The reader task holds a clone of the socket, so dropping ours
+        // would not close the connection
+        let _ = self.stream.close_read();
+        let _ = self.stream.close_write();
+    }
+}
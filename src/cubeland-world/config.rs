@@ -0,0 +1,323 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate getopts;
+extern crate noise;
+extern crate serialize;
+
+use std;
+use std::hash::sip::hash;
+
+use noise::sources::Perlin;
+
+use serialize::json;
+use serialize::json::Json;
+
+use registry::BlockRegistry;
+
+#[deriving(Clone, Eq, Show)]
+pub struct NoiseConfig {
+    octaves: uint,
+    frequency: f64,
+    lacunarity: f64,
+    persistence: f64,
+}
+
+impl NoiseConfig {
+    pub fn perlin(&self, seed: int, quality: noise::Quality) -> Perlin {
+        Perlin {
+            seed: seed,
+            octaves: self.octaves,
+            frequency: self.frequency,
+            lacunarity: self.lacunarity,
+            persistence: self.persistence,
+            quality: quality,
+        }
+    }
+
+    /// Largest magnitude the noise can reach, taking each octave to stay
+    /// within [-1, 1]
+    pub fn amplitude_bound(&self) -> f64 {
+        let mut bound = 0.0;
+        let mut amplitude = 1.0;
+        for _ in range(0, self.octaves) {
+            bound += amplitude;
+            amplitude *= self.persistence;
+        }
+        bound
+    }
+}
+
+/// Parameters for world generation and loading
+///
+/// Starts from the defaults below, then a JSON config file, then command
+/// line overrides. The file mirrors the struct: top level numbers for the
+/// scalar settings and an object for each noise source, e.g.
+///
+///     { "seed": 7, "height": { "frequency": 0.002, "octaves": 6 } }
+///
+/// Command line overrides use the same names joined with dots
+/// (--set height.frequency=0.002). CHUNK_SIZE is not configurable because
+/// it sizes the terrain arrays.
+#[deriving(Clone, Eq, Show)]
+pub struct WorldConfig {
+    seed: u32,
+    /// Chunks within this many chunks of the camera are loaded
+    visible_radius: uint,
+    /// Number of downsampled levels drawn past the view radius, each covering
+    /// twice the distance of the one before
+    lod_levels: uint,
+    /// 3D noise that carves caves
+    density: NoiseConfig,
+    /// Blocks where the density is below this are carved out
+    cave_threshold: f64,
+    /// 2D noise for the surface height, scaled by each biome
+    height: NoiseConfig,
+    temperature: NoiseConfig,
+    moisture: NoiseConfig,
+    /// Width of the transition between biomes, in climate space
+    biome_blend_width: f64,
+}
+
+impl WorldConfig {
+    pub fn default() -> WorldConfig {
+        WorldConfig {
+            seed: 42,
+            visible_radius: 8,
+            lod_levels: 3,
+            density: NoiseConfig { octaves: 4, frequency: 0.015, lacunarity: 2.0, persistence: 0.5 },
+            cave_threshold: -0.2,
+            height: NoiseConfig { octaves: 8, frequency: 0.001, lacunarity: 2.0, persistence: 0.5 },
+            temperature: NoiseConfig { octaves: 3, frequency: 0.0007, lacunarity: 2.0, persistence: 0.5 },
+            moisture: NoiseConfig { octaves: 3, frequency: 0.0007, lacunarity: 2.0, persistence: 0.5 },
+            biome_blend_width: 0.2,
+        }
+    }
+
+    /// Command line options understood by from_matches
+    pub fn opts() -> ~[getopts::OptGroup] {
+        ~[
+            getopts::optopt("c", "config", "world config file (JSON)", "FILE"),
+            getopts::optopt("s", "seed", "world seed", "SEED"),
+            getopts::optopt("r", "radius", "view radius in chunks", "CHUNKS"),
+            getopts::optmulti("", "set", "override a config value, e.g. height.frequency=0.002", "KEY=VALUE"),
+        ]
+    }
+
+    pub fn from_matches(matches: &getopts::Matches) -> Result<WorldConfig, ~str> {
+        let mut config = WorldConfig::default();
+
+        match matches.opt_str("c") {
+            Some(path) => try!(config.load(&Path::new(path))),
+            None => {},
+        }
+
+        match matches.opt_str("s") {
+            Some(s) => try!(config.set_str("seed", s)),
+            None => {},
+        }
+
+        match matches.opt_str("r") {
+            Some(s) => try!(config.set_str("visible_radius", s)),
+            None => {},
+        }
+
+        for kv in matches.opt_strs("set").iter() {
+            match kv.find('=') {
+                Some(i) => try!(config.set_str(kv.slice_to(i), kv.slice_from(i + 1))),
+                None => return Err(format!("expected KEY=VALUE: {}", *kv)),
+            }
+        }
+
+        Ok(config)
+    }
+
+    pub fn load(&mut self, path: &Path) -> Result<(), ~str> {
+        let src = match std::io::File::open(path).read_to_end() {
+            Ok(src) => src,
+            Err(e) => return Err(format!("{}: {}", path.display(), e)),
+        };
+
+        match std::str::from_utf8(src) {
+            Some(s) => self.apply_json_str(s).map_err(|e| format!("{}: {}", path.display(), e)),
+            None => Err(format!("{}: invalid UTF-8", path.display())),
+        }
+    }
+
+    pub fn apply_json_str(&mut self, s: &str) -> Result<(), ~str> {
+        match json::from_str(s) {
+            Ok(root) => self.apply_json("", &root),
+            Err(e) => Err(format!("{}", e)),
+        }
+    }
+
+    fn apply_json(&mut self, prefix: &str, j: &Json) -> Result<(), ~str> {
+        match *j {
+            json::Object(ref obj) => {
+                for (k, v) in obj.iter() {
+                    let key = if prefix.is_empty() { k.clone() } else { format!("{}.{}", prefix, *k) };
+                    try!(self.apply_json(key, v));
+                }
+                Ok(())
+            },
+            json::Number(x) => self.set(prefix, x),
+            _ => Err(format!("{}: expected a number", prefix)),
+        }
+    }
+
+    fn set_str(&mut self, key: &str, value: &str) -> Result<(), ~str> {
+        match from_str::<f64>(value) {
+            Some(x) => self.set(key, x),
+            None => Err(format!("{}: invalid number {}", key, value)),
+        }
+    }
+
+    /// Change one setting by name
+    pub fn set(&mut self, key: &str, value: f64) -> Result<(), ~str> {
+        match key {
+            "seed" => {
+                if value > std::u32::MAX as f64 {
+                    return Err(format!("{}: must be at most {}", key, std::u32::MAX));
+                }
+                self.seed = try!(to_uint(key, value)) as u32;
+            },
+            "visible_radius" => {
+                let radius = try!(to_uint(key, value));
+                if radius < 1 {
+                    return Err(format!("{}: must be at least 1", key));
+                }
+                self.visible_radius = radius;
+            },
+            "lod_levels" => self.lod_levels = try!(to_uint(key, value)),
+            "cave_threshold" => self.cave_threshold = value,
+            "biome_blend_width" => {
+                if value <= 0.0 {
+                    return Err(format!("{}: must be positive", key));
+                }
+                self.biome_blend_width = value;
+            },
+            _ => {
+                let (source, field) = match key.find('.') {
+                    Some(i) => (key.slice_to(i), key.slice_from(i + 1)),
+                    None => return Err(format!("unknown config key {}", key)),
+                };
+
+                let noise = match source {
+                    "density" => &mut self.density,
+                    "height" => &mut self.height,
+                    "temperature" => &mut self.temperature,
+                    "moisture" => &mut self.moisture,
+                    _ => return Err(format!("unknown config key {}", key)),
+                };
+
+                match field {
+                    "octaves" => noise.octaves = try!(to_uint(key, value)),
+                    "frequency" => noise.frequency = value,
+                    "lacunarity" => noise.lacunarity = value,
+                    "persistence" => noise.persistence = value,
+                    _ => return Err(format!("unknown config key {}", key)),
+                }
+            },
+        }
+        Ok(())
+    }
+
+    /// Directory name under world/ for chunks saved with this config and
+    /// block registry
+    ///
+    /// Chunks generated with non-default parameters, or with blocks that
+    /// generate differently from the builtin registry, are kept apart from
+    /// the default world with the same seed.
+    pub fn storage_name(&self, registry: &BlockRegistry) -> ~str {
+        let mut params = self.clone();
+        let default = WorldConfig::default();
+        params.seed = default.seed;
+        params.visible_radius = default.visible_radius;
+        params.lod_levels = default.lod_levels;
+
+        let blocks = registry.generation_key();
+        if params == default && blocks == BlockRegistry::builtin().generation_key() {
+            format!("{}", self.seed)
+        } else {
+            format!("{}-{:x}", self.seed, hash(&format!("{} {}", params, blocks)))
+        }
+    }
+}
+
+/// Reject negative and fractional values
+pub fn to_uint(key: &str, value: f64) -> Result<uint, ~str> {
+    if value < 0.0 || value.fract() != 0.0 {
+        Err(format!("{}: expected a non-negative integer", key))
+    } else {
+        Ok(value as uint)
+    }
+}
+
+#[test]
+fn test_config_set() {
+    let mut config = WorldConfig::default();
+    assert!(config.set("seed", 7.0).is_ok());
+    assert!(config.set("height.frequency", 0.002).is_ok());
+    assert!(config.set("density.octaves", 3.0).is_ok());
+    assert_eq!(config.seed, 7);
+    assert_eq!(config.height.frequency, 0.002);
+    assert_eq!(config.density.octaves, 3);
+
+    assert!(config.set("seed", -1.0).is_err());
+    assert!(config.set("seed", 4294967296.0).is_err());
+    assert!(config.set("visible_radius", 0.0).is_err());
+    assert!(config.set("visible_radius", 1.0).is_ok());
+    assert!(config.set("density.octaves", 2.5).is_err());
+    assert!(config.set("height.bogus", 1.0).is_err());
+    assert!(config.set("bogus.frequency", 1.0).is_err());
+    assert!(config.set("bogus", 1.0).is_err());
+}
+
+#[test]
+fn test_config_json() {
+    let mut config = WorldConfig::default();
+    assert!(config.apply_json_str("{\"visible_radius\": 4, \"moisture\": {\"persistence\": 0.25}}").is_ok());
+    assert_eq!(config.visible_radius, 4);
+    assert_eq!(config.moisture.persistence, 0.25);
+    assert_eq!(config.moisture.octaves, WorldConfig::default().moisture.octaves);
+
+    assert!(config.apply_json_str("{\"seed\": \"x\"}").is_err());
+    assert!(config.apply_json_str("[1]").is_err());
+}
+
+#[test]
+fn test_config_storage_name() {
+    let registry = BlockRegistry::builtin();
+    let mut config = WorldConfig::default();
+    config.visible_radius = 3;
+    assert_eq!(config.storage_name(&registry), ~"42");
+
+    // Changing how an ore generates moves the world too
+    let mut ores = BlockRegistry::builtin();
+    for info in ores.blocks.mut_iter() {
+        match info.ore {
+            Some(ref mut ore) => ore.threshold += 0.1,
+            None => {},
+        }
+    }
+    assert!(config.storage_name(&ores).starts_with("42-"));
+
+    config.cave_threshold = -0.3;
+    let name = config.storage_name(&registry);
+    assert!(name.starts_with("42-"));
+    assert!(config.storage_name(&ores) != name);
+
+    config.seed = 43;
+    assert!(config.storage_name(&registry) != name);
+}
@@ -0,0 +1,337 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate cgmath;
+
+use cgmath::vector::Vector;
+use cgmath::vector::Vec3;
+
+use CHUNK_SIZE;
+use biome::{Biome, BiomePlains, BiomeForest, BiomeTundra, BiomeMountains};
+use registry::BlockRegistry;
+use terrain::{BlockType, BlockAir, BlockStone, Terrain};
+#[cfg(test)]
+use terrain::BlockGrass;
+
+/// Farthest a feature reaches horizontally from the column it grows from,
+/// and the deepest a boulder sinks below the first block above the ground
+pub static MAX_RADIUS : i64 = 2;
+
+/// Highest a feature reaches above the ground block it grows from: the
+/// tallest tree trunk plus its top layer of leaves
+pub static MAX_FEATURE_HEIGHT : i64 = 7;
+
+// Upper bound on the chance of a feature in any biome. Columns that roll
+// above this are skipped without computing their ground height.
+static MAX_FEATURE_CHANCE : f64 = 0.3;
+
+/// The top solid block of a column from the height pass
+pub struct Ground {
+    y: i64,
+    biome: Biome,
+    water_level: f64,
+}
+
+enum Feature {
+    /// Trunk height
+    Tree(i64),
+    /// Radius
+    Boulder(i64),
+    TallGrass,
+}
+
+/// Places trees, boulders and tall grass on the generated terrain
+///
+/// A feature depends only on the seed and the world position of the column it
+/// grows from, and each chunk places every feature that reaches into it, so a
+/// tree on a chunk border comes out the same on both sides regardless of
+/// which chunk is generated first. Blocks missing from the registry disable
+/// the features that use them.
+pub struct Decorator {
+    seed: u32,
+    wood: Option<BlockType>,
+    leaves: Option<BlockType>,
+    tall_grass: Option<BlockType>,
+}
+
+impl Decorator {
+    pub fn new(seed: u32, registry: &BlockRegistry) -> Decorator {
+        Decorator {
+            seed: seed,
+            wood: registry.find("wood"),
+            leaves: registry.find("leaves"),
+            tall_grass: registry.find("tall_grass"),
+        }
+    }
+
+    /// Decorate the padded chunk whose origin is at p
+    ///
+    /// ground is called for the columns that may hold a feature, including
+    /// columns in neighboring chunks. solid tells whether the ground block
+    /// of such a column is still there after caves were carved.
+    pub fn decorate(&self, t: &mut Terrain, p: Vec3<i64>,
+                    ground: |i64, i64| -> Ground, solid: |Vec3<i64>| -> bool) {
+        let s = CHUNK_SIZE as i64;
+        for x in range(p.x - 1 - MAX_RADIUS, p.x + s + 1 + MAX_RADIUS) {
+            for z in range(p.z - 1 - MAX_RADIUS, p.z + s + 1 + MAX_RADIUS) {
+                let roll = to_unit(hash(self.seed, x, z, 0));
+                if roll >= MAX_FEATURE_CHANCE {
+                    continue;
+                }
+
+                let g = ground(x, z);
+                if ((g.y + 1) as f64) < g.water_level {
+                    continue;
+                }
+
+                let param = hash(self.seed, x, z, 1);
+                let (tree, boulder, grass) = feature_chances(g.biome);
+                let feature = if roll < tree {
+                    Tree(4 + (param % 3) as i64)
+                } else if roll < tree + boulder {
+                    Boulder(1 + (param % 2) as i64)
+                } else if roll < tree + boulder + grass {
+                    TallGrass
+                } else {
+                    continue;
+                };
+
+                let (bottom, top) = match feature {
+                    Tree(height) => (g.y + 1, g.y + height + 1),
+                    Boulder(radius) => (g.y + 1 - radius, g.y + 1 + radius),
+                    TallGrass => (g.y + 1, g.y + 1),
+                };
+                if top < p.y - 1 || bottom > p.y + s {
+                    continue;
+                }
+
+                // Nothing grows over a cave that broke through the surface
+                if !solid(Vec3::new(x, g.y, z)) {
+                    continue;
+                }
+
+                self.place_feature(t, p, Vec3::new(x, g.y + 1, z), feature);
+            }
+        }
+    }
+
+    /// base is the first block above the ground
+    fn place_feature(&self, t: &mut Terrain, p: Vec3<i64>, base: Vec3<i64>, feature: Feature) {
+        match feature {
+            Tree(height) => {
+                let (wood, leaves) = match (self.wood, self.leaves) {
+                    (Some(wood), Some(leaves)) => (wood, leaves),
+                    _ => return,
+                };
+
+                for dy in range(0, height) {
+                    self.place(t, p, base.add_v(&Vec3::new(0, dy, 0)), wood, true);
+                }
+
+                // Two wide layers around the top of the trunk and a narrow
+                // one above it, without the corners
+                for dy in range(height - 2, height + 1) {
+                    let r = if dy == height { 1 } else { 2 };
+                    for dx in range(-r, r + 1) {
+                        for dz in range(-r, r + 1) {
+                            if dx.abs() == r && dz.abs() == r {
+                                continue;
+                            }
+                            self.place(t, p, base.add_v(&Vec3::new(dx, dy, dz)), leaves, false);
+                        }
+                    }
+                }
+            },
+            Boulder(radius) => {
+                for dx in range(-radius, radius + 1) {
+                    for dy in range(-radius, radius + 1) {
+                        for dz in range(-radius, radius + 1) {
+                            if dx * dx + dy * dy + dz * dz <= radius * radius {
+                                self.place(t, p, base.add_v(&Vec3::new(dx, dy, dz)), BlockStone, true);
+                            }
+                        }
+                    }
+                }
+            },
+            TallGrass => {
+                match self.tall_grass {
+                    Some(tall_grass) => self.place(t, p, base, tall_grass, false),
+                    None => {},
+                }
+            },
+        }
+    }
+
+    /// Set a block if it falls inside the padded chunk
+    ///
+    /// Blocks only replace air, or other decoration if overwrite is set.
+    fn place(&self, t: &mut Terrain, p: Vec3<i64>, pos: Vec3<i64>, blocktype: BlockType, overwrite: bool) {
+        let l = pos.sub_v(&p);
+        let s = CHUNK_SIZE as i64;
+        if l.x < -1 || l.x > s || l.y < -1 || l.y > s || l.z < -1 || l.z > s {
+            return;
+        }
+
+        let (x, y, z) = (l.x as int, l.y as int, l.z as int);
+        let current = t.get(x, y, z).blocktype;
+        if current == BlockAir || (overwrite && self.is_decoration(current)) {
+            t.set(x, y, z, blocktype);
+        }
+    }
+
+    fn is_decoration(&self, blocktype: BlockType) -> bool {
+        Some(blocktype) == self.leaves || Some(blocktype) == self.tall_grass
+    }
+}
+
+/// Chances of (tree, boulder, tall grass) per column. Each sum must be at
+/// most MAX_FEATURE_CHANCE.
+fn feature_chances(biome: Biome) -> (f64, f64, f64) {
+    match biome {
+        BiomePlains => (0.005, 0.003, 0.25),
+        BiomeForest => (0.04, 0.0, 0.2),
+        BiomeTundra => (0.008, 0.004, 0.0),
+        BiomeMountains => (0.0, 0.008, 0.0),
+        _ => (0.0, 0.0, 0.0),
+    }
+}
+
+fn hash(seed: u32, x: i64, z: i64, salt: u64) -> u64 {
+    let h = mix(seed as u64 ^ salt * 0x9e3779b97f4a7c15u64);
+    let h = mix(h ^ x as u64);
+    mix(h ^ z as u64)
+}
+
+// Finalizer from MurmurHash3
+fn mix(mut h: u64) -> u64 {
+    h ^= h >> 33;
+    h *= 0xff51afd7ed558ccdu64;
+    h ^= h >> 33;
+    h *= 0xc4ceb9fe1a85ec53u64;
+    h ^= h >> 33;
+    h
+}
+
+/// Map a hash to [0, 1)
+fn to_unit(h: u64) -> f64 {
+    (h >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+static TEST_GROUND : i64 = 10;
+
+/// A chunk of flat forest with the ground at TEST_GROUND
+#[cfg(test)]
+fn flat_chunk(decorator: &Decorator, c: Vec3<i64>) -> ~Terrain {
+    let p = c.mul_s(CHUNK_SIZE as i64);
+    let mut t = Terrain::new();
+    for x in range(-1, CHUNK_SIZE+1) {
+        for y in range(-1, CHUNK_SIZE+1) {
+            for z in range(-1, CHUNK_SIZE+1) {
+                if p.y + y as i64 <= TEST_GROUND {
+                    t.set(x, y, z, BlockGrass);
+                }
+            }
+        }
+    }
+    decorator.decorate(t, p, |_, _| Ground { y: TEST_GROUND, biome: BiomeForest, water_level: -100.0 },
+                       |_| true);
+    t
+}
+
+#[test]
+fn test_decoration_chunk_borders() {
+    let registry = BlockRegistry::builtin();
+    let mut shared_decoration = 0;
+
+    for seed in range(0u32, 8) {
+        let decorator = Decorator::new(seed, &registry);
+
+        // Neighbors in x and z, generated in the opposite order from their origin
+        let b = flat_chunk(&decorator, Vec3::new(1, 0, 0));
+        let c = flat_chunk(&decorator, Vec3::new(0, 0, 1));
+        let a = flat_chunk(&decorator, Vec3::new(0, 0, 0));
+
+        // Every world position held by two chunks must match
+        for i in range(-1, CHUNK_SIZE+1) {
+            for y in range(-1, CHUNK_SIZE+1) {
+                for &(la, lb) in [(CHUNK_SIZE - 1, -1), (CHUNK_SIZE, 0)].iter() {
+                    let x_block = a.get(la, y, i).blocktype;
+                    assert_eq!(x_block, b.get(lb, y, i).blocktype);
+                    let z_block = a.get(i, y, la).blocktype;
+                    assert_eq!(z_block, c.get(i, y, lb).blocktype);
+
+                    for &blocktype in [x_block, z_block].iter() {
+                        if decorator.is_decoration(blocktype) || Some(blocktype) == decorator.wood {
+                            shared_decoration += 1;
+                        }
+                    }
+                }
+            }
+        }
+
+        // Decoration never replaces the ground
+        for x in range(-1, CHUNK_SIZE+1) {
+            for y in range(-1, TEST_GROUND as int + 1) {
+                for z in range(-1, CHUNK_SIZE+1) {
+                    assert_eq!(a.get(x, y, z).blocktype, BlockGrass);
+                }
+            }
+        }
+    }
+
+    // Some trees must actually have crossed a border for this to mean anything
+    assert!(shared_decoration > 0);
+}
+
+#[test]
+fn test_decoration_deterministic() {
+    let registry = BlockRegistry::builtin();
+    let a = flat_chunk(&Decorator::new(42, &registry), Vec3::new(3, 0, -2));
+    let b = flat_chunk(&Decorator::new(42, &registry), Vec3::new(3, 0, -2));
+    let c = flat_chunk(&Decorator::new(43, &registry), Vec3::new(3, 0, -2));
+
+    let mut same_as_other_seed = true;
+    for x in range(-1, CHUNK_SIZE+1) {
+        for y in range(-1, CHUNK_SIZE+1) {
+            for z in range(-1, CHUNK_SIZE+1) {
+                assert_eq!(a.get(x, y, z).blocktype, b.get(x, y, z).blocktype);
+                if a.get(x, y, z).blocktype != c.get(x, y, z).blocktype {
+                    same_as_other_seed = false;
+                }
+            }
+        }
+    }
+    assert!(!same_as_other_seed);
+}
+
+#[test]
+fn test_decoration_needs_ground() {
+    let registry = BlockRegistry::builtin();
+    let decorator = Decorator::new(42, &registry);
+    let p = Vec3::new(0i64, 0, 0);
+    let mut t = Terrain::new();
+    let mut asked = 0;
+    decorator.decorate(t, p, |_, _| Ground { y: TEST_GROUND, biome: BiomeForest, water_level: -100.0 },
+                       |pos| { assert_eq!(pos.y, TEST_GROUND); asked += 1; false });
+
+    assert!(asked > 0);
+    for x in range(-1, CHUNK_SIZE+1) {
+        for y in range(-1, CHUNK_SIZE+1) {
+            for z in range(-1, CHUNK_SIZE+1) {
+                assert_eq!(t.get(x, y, z).blocktype, BlockAir);
+            }
+        }
+    }
+}
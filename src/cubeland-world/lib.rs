@@ -0,0 +1,59 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Terrain generation, storage, meshing and chunk management for cubeland
+//!
+//! Nothing in here touches GL, so the game, the benchmark, the exporter and
+//! the tests all build against this one copy.
+
+#![crate_id = "cubeland-world#0.1"]
+#![crate_type = "lib"]
+#![feature(globs)]
+
+extern crate collections;
+extern crate sync;
+extern crate time;
+extern crate cgmath;
+extern crate noise;
+extern crate flate;
+extern crate serialize;
+extern crate getopts;
+
+pub static CHUNK_SIZE: int = 32;
+
+pub mod terrain;
+pub mod biome;
+pub mod decoration;
+pub mod registry;
+pub mod config;
+pub mod mesh;
+pub mod region;
+pub mod chunk;
+pub mod raycast;
+pub mod physics;
+pub mod ratelimiter;
+pub mod spiral;
+pub mod protocol;
+pub mod server;
+pub mod client;
+pub mod view;
+pub mod lru;
+pub mod pool;
+pub mod lod;
+pub mod light;
+pub mod shadow;
+pub mod profiler;
+pub mod metrics;
+pub mod camerapath;
+pub mod png;
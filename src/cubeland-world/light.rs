@@ -0,0 +1,578 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Sky and block light levels for one chunk
+//!
+//! Sky light enters at the top of the world and falls straight down without
+//! losing strength. Block light starts at the level an emissive block gives
+//! off. Both lose a level for every other step they take, and opaque blocks
+//! stop them. An opaque emissive block is lit only by its own light.
+//!
+//! Like Terrain, each chunk's light is padded with a layer copied from its
+//! neighbors with edge() and update_padding(). The padding is a source that
+//! spreads into the chunk and is never brightened from inside it. Removing
+//! light does clear the padding it may have reached, since the neighbor
+//! could have been lit from this chunk; the neighbor's next edge puts back
+//! whatever light it has of its own.
+
+extern crate cgmath;
+
+use std::mem;
+use std::vec;
+
+use cgmath::vector::Vector;
+use cgmath::vector::Vec3;
+
+use CHUNK_SIZE;
+use terrain::Terrain;
+use terrain::NUM_PADDED_BLOCKS;
+use registry::BlockRegistry;
+
+/// Light of the open sky and the brightest emissive block
+pub static MAX_LIGHT : u8 = 15;
+
+/// The neighbors that light spreads to, one per face
+pub static FACE_NEIGHBORS : [Vec3<int>, ..6] = [
+    Vec3 { x: 1, y: 0, z: 0 },
+    Vec3 { x: -1, y: 0, z: 0 },
+    Vec3 { x: 0, y: 1, z: 0 },
+    Vec3 { x: 0, y: -1, z: 0 },
+    Vec3 { x: 0, y: 0, z: 1 },
+    Vec3 { x: 0, y: 0, z: -1 },
+];
+
+#[deriving(Eq)]
+enum Channel {
+    ChannelSky,
+    ChannelBlock,
+}
+
+static CHANNELS : [Channel, ..2] = [ChannelSky, ChannelBlock];
+
+/// Light levels of a padded chunk
+///
+/// Like Terrain, a chunk with the same levels everywhere stores them once,
+/// and only allocates a level per block when one of them changes.
+#[deriving(Clone)]
+pub struct LightMap {
+    /// Sky light in the high nibble, block light in the low one, for every
+    /// padded block, or empty if they all have the levels in uniform
+    priv levels: ~[u8],
+    priv uniform: u8,
+}
+
+/// What changed in a chunk's light when its padding was updated
+pub struct LightChange {
+    /// Light inside the chunk changed, so its edges may have too
+    interior: bool,
+    /// Light that a mesh of the chunk shows changed, either inside it or in
+    /// the padding in front of its border faces
+    visible: bool,
+}
+
+impl LightMap {
+    /// No light anywhere
+    pub fn new() -> ~LightMap {
+        LightMap::uniform(0)
+    }
+
+    /// Full sky light everywhere, for terrain that is never lit properly
+    pub fn daylight() -> ~LightMap {
+        LightMap::uniform(MAX_LIGHT << 4)
+    }
+
+    fn uniform(levels: u8) -> ~LightMap {
+        ~LightMap { levels: ~[], uniform: levels }
+    }
+
+    /// Light a chunk from its own emissive blocks and the sky above it
+    ///
+    /// sky_above says whether the padded column at x, z is open to the sky
+    /// above the chunk. Sky light also falls down those columns of the side
+    /// padding, as a guess at the neighbors' light until they arrive.
+    pub fn compute(t: &Terrain, registry: &BlockRegistry, sky_above: |int, int| -> bool) -> ~LightMap {
+        let mut light = LightMap::new();
+
+        match t.uniform_type() {
+            Some(blocktype) if registry.is_opaque(blocktype) && registry.light(blocktype) == 0 => {
+                return light;
+            },
+            _ => {},
+        }
+
+        let mut sky = ~[];
+        for x in range(-1, CHUNK_SIZE+1) {
+            for z in range(-1, CHUNK_SIZE+1) {
+                if registry.is_opaque(t.get(x, CHUNK_SIZE, z).blocktype) || !sky_above(x, z) {
+                    continue;
+                }
+
+                let mut y = CHUNK_SIZE;
+                while y >= -1 && !registry.is_opaque(t.get(x, y, z).blocktype) {
+                    let p = Vec3::new(x, y, z);
+                    light.put(ChannelSky, p, MAX_LIGHT);
+                    sky.push(p);
+                    y -= 1;
+                }
+            }
+        }
+        light.spread(t, registry, ChannelSky, sky);
+
+        let mut emitters = ~[];
+        for x in range(0, CHUNK_SIZE) {
+            for y in range(0, CHUNK_SIZE) {
+                for z in range(0, CHUNK_SIZE) {
+                    let level = registry.light(t.get(x, y, z).blocktype);
+                    if level > 0 {
+                        let p = Vec3::new(x, y, z);
+                        light.put(ChannelBlock, p, level);
+                        emitters.push(p);
+                    }
+                }
+            }
+        }
+        light.spread(t, registry, ChannelBlock, emitters);
+
+        light.compact();
+        light
+    }
+
+    pub fn sky(&self, x: int, y: int, z: int) -> u8 {
+        self.levels_at(index(Vec3::new(x, y, z))) >> 4
+    }
+
+    pub fn block(&self, x: int, y: int, z: int) -> u8 {
+        self.levels_at(index(Vec3::new(x, y, z))) & 0xf
+    }
+
+    /// Heap and inline bytes used by this chunk's light
+    pub fn memory_size(&self) -> uint {
+        mem::size_of::<LightMap>() + self.levels.len()
+    }
+
+    /// Relight after the block at p, inside the chunk, was changed in t
+    pub fn update_block(&mut self, t: &Terrain, registry: &BlockRegistry, p: Vec3<int>) {
+        for &channel in CHANNELS.iter() {
+            let old = self.get(channel, p);
+            let emitted = emitted(t, registry, channel, p);
+            self.put(channel, p, emitted);
+
+            let mut relight = ~[];
+            if emitted > 0 {
+                relight.push(p);
+            }
+            if old > emitted {
+                self.unspread(t, registry, channel, ~[(p, old)], &mut relight);
+            }
+
+            // A block that no longer stops light lets its neighbors' in
+            for d in FACE_NEIGHBORS.iter() {
+                let n = p.add_v(d);
+                if in_padded_chunk(n) && self.get(channel, n) > 0 {
+                    relight.push(n);
+                }
+            }
+
+            self.spread(t, registry, channel, relight);
+        }
+    }
+
+    /// The layer of this chunk's light on the face toward a neighbor, for
+    /// the neighbor to pass to update_padding
+    pub fn edge(&self, o: Vec3<int>) -> ~[u8] {
+        face_cells(o, false).iter().map(|&p| self.levels_at(index(p))).collect()
+    }
+
+    /// Copy the edge of a neighbor's light into the padding and propagate
+    /// the difference
+    ///
+    /// o points from this chunk to the neighbor, which produced edge with
+    /// edge(-o). Padding behind opaque blocks is never shown, so changes
+    /// there alone are not visible.
+    pub fn update_padding(&mut self, t: &Terrain, registry: &BlockRegistry,
+                          o: Vec3<int>, edge: &[u8]) -> LightChange {
+        let mut change = LightChange { interior: false, visible: false };
+        let mut brighter = [~[], ~[]];
+        let mut darker = [~[], ~[]];
+
+        for (&p, &levels) in face_cells(o, true).iter().zip(edge.iter()) {
+            let i = index(p);
+            let old = self.levels_at(i);
+            if old == levels {
+                continue;
+            }
+            self.set_levels_at(i, levels);
+            if !registry.is_opaque(t.get(p.x, p.y, p.z).blocktype) {
+                change.visible = true;
+            }
+
+            for (c, &channel) in CHANNELS.iter().enumerate() {
+                let (old, new) = (unpack(channel, old), unpack(channel, levels));
+                if new > old {
+                    brighter[c].push(p);
+                } else if new < old {
+                    darker[c].push((p, old));
+                }
+            }
+        }
+
+        for (c, &channel) in CHANNELS.iter().enumerate() {
+            let mut relight = mem::replace(&mut brighter[c], ~[]);
+            let removed = mem::replace(&mut darker[c], ~[]);
+            let darkened = self.unspread(t, registry, channel, removed, &mut relight);
+            let brightened = self.spread(t, registry, channel, relight);
+            if darkened || brightened {
+                change.interior = true;
+                change.visible = true;
+            }
+        }
+        change
+    }
+
+    fn levels_at(&self, i: uint) -> u8 {
+        if self.levels.is_empty() {
+            self.uniform
+        } else {
+            self.levels[i]
+        }
+    }
+
+    fn set_levels_at(&mut self, i: uint, levels: u8) {
+        if self.levels.is_empty() {
+            if levels == self.uniform {
+                return;
+            }
+            self.levels = vec::from_elem(NUM_PADDED_BLOCKS, self.uniform);
+        }
+        self.levels[i] = levels;
+    }
+
+    /// Go back to storing the levels once if they are all the same
+    fn compact(&mut self) {
+        if !self.levels.is_empty() && self.levels.iter().all(|&v| v == self.levels[0]) {
+            self.uniform = self.levels[0];
+            self.levels = ~[];
+        }
+    }
+
+    fn get(&self, channel: Channel, p: Vec3<int>) -> u8 {
+        unpack(channel, self.levels_at(index(p)))
+    }
+
+    fn put(&mut self, channel: Channel, p: Vec3<int>, level: u8) {
+        let i = index(p);
+        let v = self.levels_at(i);
+        self.set_levels_at(i, match channel {
+            ChannelSky => (v & 0xf) | (level << 4),
+            ChannelBlock => (v & 0xf0) | level,
+        });
+    }
+
+    /// Breadth first flood fill from lit positions into the chunk
+    ///
+    /// Returns whether any position was brightened.
+    fn spread(&mut self, t: &Terrain, registry: &BlockRegistry, channel: Channel, mut queue: ~[Vec3<int>]) -> bool {
+        let start = queue.len();
+        let mut i = 0;
+        while i < queue.len() {
+            let p = queue[i];
+            i += 1;
+
+            let level = self.get(channel, p);
+            if level == 0 {
+                continue;
+            }
+
+            for d in FACE_NEIGHBORS.iter() {
+                let n = p.add_v(d);
+                if !in_chunk(n) || registry.is_opaque(t.get(n.x, n.y, n.z).blocktype) {
+                    continue;
+                }
+
+                let level = spread_level(channel, *d, level);
+                if self.get(channel, n) < level {
+                    self.put(channel, n, level);
+                    queue.push(n);
+                }
+            }
+        }
+        queue.len() > start
+    }
+
+    /// Clear the light that came from positions which lost theirs
+    ///
+    /// Each entry has the level the position had. Neighbors that could have
+    /// been lit by it are cleared in turn, padding included, and the
+    /// brighter ones left around the cleared area are added to relight, to
+    /// spread back in. Returns whether any position was cleared.
+    fn unspread(&mut self, t: &Terrain, registry: &BlockRegistry, channel: Channel,
+                mut queue: ~[(Vec3<int>, u8)], relight: &mut ~[Vec3<int>]) -> bool {
+        let start = queue.len();
+        let mut i = 0;
+        while i < queue.len() {
+            let (p, level) = queue[i];
+            i += 1;
+
+            for d in FACE_NEIGHBORS.iter() {
+                let n = p.add_v(d);
+                if !in_padded_chunk(n) {
+                    continue;
+                }
+
+                let n_level = self.get(channel, n);
+                if n_level == 0 {
+                    continue;
+                }
+
+                let emitted = emitted(t, registry, channel, n);
+                let from_p = n_level < level || spread_level(channel, *d, level) == n_level;
+                if n_level > emitted && from_p {
+                    self.put(channel, n, emitted);
+                    queue.push((n, n_level));
+                    if emitted > 0 {
+                        relight.push(n);
+                    }
+                } else {
+                    relight.push(n);
+                }
+            }
+        }
+        queue.len() > start
+    }
+}
+
+impl Eq for LightMap {
+    fn eq(&self, other: &LightMap) -> bool {
+        range(0, NUM_PADDED_BLOCKS).all(|i| self.levels_at(i) == other.levels_at(i))
+    }
+}
+
+/// Level a position gets from a neighbor in direction d from it
+fn spread_level(channel: Channel, d: Vec3<int>, level: u8) -> u8 {
+    if channel == ChannelSky && d.y == -1 && level == MAX_LIGHT {
+        MAX_LIGHT
+    } else {
+        level - 1
+    }
+}
+
+fn emitted(t: &Terrain, registry: &BlockRegistry, channel: Channel, p: Vec3<int>) -> u8 {
+    match channel {
+        ChannelSky => 0,
+        ChannelBlock => registry.light(t.get(p.x, p.y, p.z).blocktype),
+    }
+}
+
+fn unpack(channel: Channel, levels: u8) -> u8 {
+    match channel {
+        ChannelSky => levels >> 4,
+        ChannelBlock => levels & 0xf,
+    }
+}
+
+/// The cells of a chunk face, either the layer just inside it or the
+/// padding beyond it, in the same order for both
+fn face_cells(o: Vec3<int>, padding: bool) -> ~[Vec3<int>] {
+    let bounds = |d: int| {
+        if d < 0 {
+            if padding { (-1, 0) } else { (0, 1) }
+        } else if d > 0 {
+            if padding { (CHUNK_SIZE, CHUNK_SIZE+1) } else { (CHUNK_SIZE-1, CHUNK_SIZE) }
+        } else {
+            (0, CHUNK_SIZE)
+        }
+    };
+
+    let (x0, x1) = bounds(o.x);
+    let (y0, y1) = bounds(o.y);
+    let (z0, z1) = bounds(o.z);
+    let mut cells = ~[];
+    for x in range(x0, x1) {
+        for y in range(y0, y1) {
+            for z in range(z0, z1) {
+                cells.push(Vec3::new(x, y, z));
+            }
+        }
+    }
+    cells
+}
+
+fn in_chunk(p: Vec3<int>) -> bool {
+    p.x >= 0 && p.x < CHUNK_SIZE && p.y >= 0 && p.y < CHUNK_SIZE && p.z >= 0 && p.z < CHUNK_SIZE
+}
+
+fn in_padded_chunk(p: Vec3<int>) -> bool {
+    p.x >= -1 && p.x <= CHUNK_SIZE && p.y >= -1 && p.y <= CHUNK_SIZE && p.z >= -1 && p.z <= CHUNK_SIZE
+}
+
+fn index(p: Vec3<int>) -> uint {
+    static S : int = CHUNK_SIZE + 2;
+    ((p.x+1) * S * S + (p.y+1) * S + (p.z+1)) as uint
+}
+
+#[test]
+fn test_light_sky() {
+    use terrain::BlockStone;
+
+    let registry = BlockRegistry::builtin();
+    let mut t = Terrain::new();
+    // A roof over part of the chunk
+    for x in range(-1, 10) {
+        for z in range(-1, CHUNK_SIZE+1) {
+            t.set(x, 20, z, BlockStone);
+        }
+    }
+    let light = LightMap::compute(t, &registry, |_, _| true);
+
+    // Sky light falls all the way down the open columns
+    for y in range(0, CHUNK_SIZE) {
+        assert_eq!(light.sky(12, y, 5), MAX_LIGHT);
+    }
+
+    // and under the roof loses a level for each block from its edge
+    assert_eq!(light.sky(5, 20, 5), 0);
+    for x in range(0, 10) {
+        assert_eq!(light.sky(x, 10, 5), MAX_LIGHT - (10 - x) as u8);
+    }
+    assert_eq!(light.block(12, 10, 5), 0);
+}
+
+#[test]
+fn test_light_block() {
+    use terrain::{BlockAir, BlockStone};
+
+    let registry = BlockRegistry::builtin();
+    let glowstone = registry.find("glowstone").unwrap();
+    let level = registry.light(glowstone);
+    let mut t = Terrain::new();
+    t.set(10, 10, 10, glowstone);
+    // A wall the light has to go around
+    for y in range(0, CHUNK_SIZE) {
+        for z in range(0, CHUNK_SIZE) {
+            t.set(12, y, z, BlockStone);
+        }
+    }
+    t.set(12, 10, 12, BlockAir);
+    let light = LightMap::compute(t, &registry, |_, _| false);
+
+    assert_eq!(light.block(10, 10, 10), level);
+    assert_eq!(light.block(11, 10, 10), level - 1);
+    assert_eq!(light.block(9, 11, 9), level - 3);
+    assert_eq!(light.block(12, 10, 10), 0);
+    // Through the hole in the wall
+    assert_eq!(light.block(13, 10, 10), level - 7);
+    assert_eq!(light.sky(10, 11, 10), 0);
+}
+
+#[test]
+fn test_light_updates() {
+    use terrain::{BlockAir, BlockStone};
+
+    let registry = BlockRegistry::builtin();
+    let glowstone = registry.find("glowstone").unwrap();
+    let mut t = Terrain::new();
+    for x in range(-1, CHUNK_SIZE+1) {
+        for z in range(-1, CHUNK_SIZE+1) {
+            t.set(x, 10, z, BlockStone);
+            if x >= 5 && x < 15 && z >= 5 && z < 15 {
+                t.set(x, 20, z, BlockStone);
+            }
+        }
+    }
+    t.set(8, 12, 8, glowstone);
+    let mut light = LightMap::compute(t, &registry, |_, _| true);
+
+    // Editing one block at a time ends up the same as lighting from scratch
+    let edits = [
+        (Vec3::new(10, 20, 10), BlockAir),
+        (Vec3::new(3, 15, 3), BlockStone),
+        (Vec3::new(8, 12, 8), BlockAir),
+        (Vec3::new(10, 20, 10), BlockStone),
+        (Vec3::new(9, 11, 9), glowstone),
+        (Vec3::new(7, 10, 7), BlockAir),
+    ];
+    for &(p, blocktype) in edits.iter() {
+        t.set(p.x, p.y, p.z, blocktype);
+        light.update_block(t, &registry, p);
+        assert!(*light == *LightMap::compute(t, &registry, |_, _| true));
+    }
+    assert!(light.sky(7, 5, 7) > 0);
+    assert_eq!(light.block(8, 12, 8), registry.light(glowstone) - 3);
+}
+
+#[test]
+fn test_light_padding() {
+    use terrain::{BlockAir, BlockStone};
+
+    let registry = BlockRegistry::builtin();
+    let glowstone = registry.find("glowstone").unwrap();
+    let level = registry.light(glowstone);
+
+    // Two dark chunks side by side, with a light just inside the first
+    let mut a = Terrain::new();
+    let b = Terrain::new();
+    a.set(CHUNK_SIZE - 2, 5, 5, glowstone);
+    a.set(CHUNK_SIZE - 1, 5, 6, BlockStone);
+    let mut light_a = LightMap::compute(a, &registry, |_, _| false);
+    let mut light_b = LightMap::compute(b, &registry, |_, _| false);
+
+    let o = Vec3::new(1, 0, 0);
+    assert!(light_b.update_padding(b, &registry, -o, light_a.edge(o)).visible);
+    assert!(!light_b.update_padding(b, &registry, -o, light_a.edge(o)).visible);
+    assert_eq!(light_b.block(-1, 5, 5), level - 1);
+    assert_eq!(light_b.block(0, 5, 5), level - 2);
+    assert_eq!(light_b.block(3, 6, 5), level - 6);
+
+    // Light coming back across the border is never brighter than what is there
+    let before = light_a.clone();
+    assert!(light_a.update_padding(a, &registry, o, light_b.edge(-o)).visible);
+    for x in range(0, CHUNK_SIZE) {
+        for y in range(0, CHUNK_SIZE) {
+            for z in range(0, CHUNK_SIZE) {
+                assert_eq!(light_a.block(x, y, z), before.block(x, y, z));
+            }
+        }
+    }
+
+    // Removing the light darkens the neighbor too, without the copy in the
+    // padding lighting the chunk again
+    a.set(CHUNK_SIZE - 2, 5, 5, BlockAir);
+    light_a.update_block(a, &registry, Vec3::new(CHUNK_SIZE - 2, 5, 5));
+    assert!(light_b.update_padding(b, &registry, -o, light_a.edge(o)).interior);
+    assert_eq!(light_a.block(CHUNK_SIZE - 1, 5, 5), 0);
+    assert_eq!(light_b.block(0, 5, 5), 0);
+    assert_eq!(light_b.block(3, 6, 5), 0);
+}
+
+#[test]
+fn test_light_uniform() {
+    use terrain::BlockStone;
+
+    let registry = BlockRegistry::builtin();
+    let glowstone = registry.find("glowstone").unwrap();
+
+    // Solid rock and open sky both keep a single level
+    let stone = Terrain::uniform(BlockStone);
+    assert!(LightMap::compute(stone, &registry, |_, _| true).memory_size() < 100);
+    let mut t = Terrain::new();
+    let mut light = LightMap::compute(t, &registry, |_, _| true);
+    assert!(light.memory_size() < 100);
+    assert_eq!(light.sky(5, 5, 5), MAX_LIGHT);
+
+    t.set(5, 5, 5, glowstone);
+    light.update_block(t, &registry, Vec3::new(5, 5, 5));
+    assert!(light.memory_size() > NUM_PADDED_BLOCKS);
+    assert_eq!(light.block(5, 6, 5), registry.light(glowstone) - 1);
+    assert!(*light == *LightMap::compute(t, &registry, |_, _| true));
+}
@@ -0,0 +1,176 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Downsampled chunks for terrain past the view radius
+//!
+//! Level 0 is the full resolution world. A chunk at level L has the same
+//! number of blocks as a level 0 chunk but each one stands for a cube 2^L
+//! blocks on a side, so it covers eight chunks of level L-1. Every level
+//! keeps its own view set of the same radius, counted in its own chunks, so
+//! each level reaches twice as far as the one below it. Away from the camera
+//! a level only loads the rows of chunks the surface can reach, since
+//! distant chunks above and below it are empty or hidden.
+
+extern crate cgmath;
+
+use collections::HashMap;
+use collections::HashSet;
+
+use cgmath::vector::Vector;
+use cgmath::vector::Vec3;
+
+use CHUNK_SIZE;
+use light::LightMap;
+use mesh::Mesh;
+use pool::StageTimer;
+use registry::BlockRegistry;
+use terrain::TerrainGenerator;
+use view::ViewSet;
+
+/// A mesh of distant terrain
+///
+/// The terrain itself is not kept, since these chunks are never edited or
+/// collided with.
+pub struct LodChunk {
+    level: uint,
+    /// In units of this level's chunks
+    coord: Vec3<i64>,
+    mesh: ~Mesh,
+    /// Changes whenever mesh is replaced, so renderers know to upload it again
+    mesh_version: uint,
+}
+
+/// The chunks loaded at one level
+pub struct LodLevel {
+    view: ViewSet,
+    chunks: HashMap<(i64, i64, i64), ~LodChunk>,
+    inflight: HashSet<(i64, i64, i64)>,
+}
+
+impl LodLevel {
+    pub fn new(level: uint, radius: uint, terrain_generator: &TerrainGenerator) -> LodLevel {
+        // Chunks this close cover every chunk of the level below in view,
+        // so select_levels can reach them all from the top level
+        let near = (radius + 1) / 2 + 2;
+        let size = (CHUNK_SIZE * lod_scale(level)) as f64;
+        let (min_height, max_height) = terrain_generator.surface_range();
+        let min_y = (min_height / size).floor() as i64;
+        let max_y = (max_height / size).floor() as i64;
+        LodLevel {
+            view: ViewSet::banded(radius, near, min_y, max_y),
+            chunks: HashMap::new(),
+            inflight: HashSet::new(),
+        }
+    }
+}
+
+/// Width of one block of a level, in world blocks
+pub fn lod_scale(level: uint) -> int {
+    1 << level
+}
+
+/// Generate and mesh a chunk at the given level
+pub fn lod_gen(terrain_generator: &TerrainGenerator, registry: &BlockRegistry,
+               level: uint, coord: Vec3<i64>, timer: &mut StageTimer) -> ~LodChunk {
+    let size = (CHUNK_SIZE * lod_scale(level)) as f64;
+    let p = Vec3::new(coord.x as f64, coord.y as f64, coord.z as f64).mul_s(size);
+    let terrain = terrain_generator.gen_scaled(p, lod_scale(level));
+    timer.mark("lod_terrain");
+    // Too far away for light to show, so everything is in daylight
+    let mesh = Mesh::gen_skirted(terrain, LightMap::daylight(), registry);
+    timer.mark("lod_mesh");
+
+    ~LodChunk {
+        level: level,
+        coord: coord,
+        mesh: mesh,
+        mesh_version: 0,
+    }
+}
+
+/// The eight chunks one level down that make up a chunk
+pub fn children(c: Vec3<i64>) -> ~[Vec3<i64>] {
+    let mut result = ~[];
+    for x in range(0, 2) {
+        for y in range(0, 2) {
+            for z in range(0, 2) {
+                result.push(c.mul_s(2).add_v(&Vec3::new(x, y, z)));
+            }
+        }
+    }
+    result
+}
+
+/// Pick one level to draw for each part of the world
+///
+/// Starts from the chunks of the top level and moves down a level wherever
+/// all eight chunks below are ready, so the finest loaded terrain is drawn
+/// near the camera and no part of the world is drawn twice. A chunk that is
+/// not ready yet is still refined, so finer chunks that loaded first are
+/// drawn instead of leaving a hole.
+pub fn select_levels(top_level: uint, top_coords: &[Vec3<i64>],
+                     ready: |uint, Vec3<i64>| -> bool) -> ~[(uint, Vec3<i64>)] {
+    let mut result = ~[];
+    let mut stack : ~[(uint, Vec3<i64>)] = top_coords.iter().rev().map(|&c| (top_level, c)).collect();
+
+    loop {
+        let (level, c) = match stack.pop() {
+            Some(x) => x,
+            None => break,
+        };
+
+        if level == 0 {
+            if ready(0, c) {
+                result.push((0, c));
+            }
+            continue;
+        }
+
+        let children = children(c);
+        if ready(level, c) && !children.iter().all(|&child| ready(level - 1, child)) {
+            result.push((level, c));
+        } else {
+            for &child in children.iter().rev() {
+                stack.push((level - 1, child));
+            }
+        }
+    }
+
+    result
+}
+
+#[test]
+fn test_select_levels() {
+    // Level 1 is fully loaded. At level 0 only the children of the chunk at
+    // the origin and one child of its neighbor are ready.
+    let top = [Vec3::new(0, 0, 0), Vec3::new(1, 0, 0)];
+    let ready = |level: uint, c: Vec3<i64>| {
+        if level == 1 {
+            true
+        } else {
+            (c.x < 2 && c.x >= 0 && c.y >= 0 && c.y < 2 && c.z >= 0 && c.z < 2) ||
+                c == Vec3::new(2, 0, 0)
+        }
+    };
+
+    let selected = select_levels(1, top, ready);
+    assert_eq!(selected.len(), 9);
+    assert!(selected.iter().all(|&(level, c)| level == 0 || c == Vec3::new(1, 0, 0)));
+    assert!(selected.contains(&(1, Vec3::new(1, 0, 0))));
+    assert!(!selected.contains(&(0, Vec3::new(2, 0, 0))));
+
+    // With the top level missing the ready chunks below are drawn instead
+    let selected = select_levels(1, top, |level, c| level == 0 && c.x == 0 && c.y == 0 && c.z == 0);
+    assert_eq!(selected, ~[(0, Vec3::new(0, 0, 0))]);
+}
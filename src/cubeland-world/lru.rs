@@ -0,0 +1,265 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::hash::Hash;
+use collections::HashMap;
+use collections::hashmap;
+
+/// Map that evicts its least recently used entries when over capacity
+///
+/// Entries are kept in a doubly linked list threaded through the map by key,
+/// so touching, inserting and evicting are all O(1). Pinned entries are
+/// left out of the list and are never evicted, which can keep the cache over
+/// capacity.
+pub struct LruCache<K, V> {
+    map: HashMap<K, Entry<K, V>>,
+    /// Least recently used unpinned entry
+    head: Option<K>,
+    /// Most recently used unpinned entry
+    tail: Option<K>,
+    capacity: uint,
+}
+
+struct Entry<K, V> {
+    value: V,
+    prev: Option<K>,
+    next: Option<K>,
+    pinned: bool,
+}
+
+impl<K: Hash + TotalEq + Clone, V> LruCache<K, V> {
+    pub fn new(capacity: uint) -> LruCache<K, V> {
+        LruCache {
+            map: HashMap::new(),
+            head: None,
+            tail: None,
+            capacity: capacity,
+        }
+    }
+
+    pub fn len(&self) -> uint {
+        self.map.len()
+    }
+
+    pub fn capacity(&self) -> uint {
+        self.capacity
+    }
+
+    pub fn contains_key(&self, k: &K) -> bool {
+        self.map.contains_key(k)
+    }
+
+    /// Does not count as a use
+    pub fn find<'a>(&'a self, k: &K) -> Option<&'a V> {
+        self.map.find(k).map(|e| &e.value)
+    }
+
+    /// Does not count as a use
+    pub fn find_mut<'a>(&'a mut self, k: &K) -> Option<&'a mut V> {
+        self.map.find_mut(k).map(|e| &mut e.value)
+    }
+
+    /// Insert or replace an entry, making it the most recently used
+    ///
+    /// Nothing is evicted until evict is called.
+    pub fn insert(&mut self, k: K, v: V) {
+        if self.map.contains_key(&k) {
+            self.map.get_mut(&k).value = v;
+            self.touch(&k);
+        } else {
+            self.map.insert(k.clone(), Entry { value: v, prev: None, next: None, pinned: false });
+            self.link_tail(k);
+        }
+    }
+
+    pub fn pop(&mut self, k: &K) -> Option<V> {
+        if !self.map.contains_key(k) {
+            return None;
+        }
+        self.unlink(k);
+        self.map.pop(k).map(|e| e.value)
+    }
+
+    /// Mark an entry as most recently used
+    pub fn touch(&mut self, k: &K) {
+        match self.map.find(k) {
+            Some(e) if !e.pinned => {},
+            _ => return,
+        }
+        self.unlink(k);
+        self.link_tail(k.clone());
+    }
+
+    /// Protect an entry from eviction until it is unpinned
+    pub fn pin(&mut self, k: &K) {
+        match self.map.find(k) {
+            Some(e) if !e.pinned => {},
+            _ => return,
+        }
+        self.unlink(k);
+        self.map.get_mut(k).pinned = true;
+    }
+
+    /// Make a pinned entry evictable again, as the most recently used
+    pub fn unpin(&mut self, k: &K) {
+        match self.map.find(k) {
+            Some(e) if e.pinned => {},
+            _ => return,
+        }
+        self.map.get_mut(k).pinned = false;
+        self.link_tail(k.clone());
+    }
+
+    /// Remove and return the least recently used unpinned entry
+    pub fn pop_lru(&mut self) -> Option<(K, V)> {
+        match self.head.clone() {
+            Some(k) => {
+                let v = self.pop(&k).unwrap();
+                Some((k, v))
+            },
+            None => None,
+        }
+    }
+
+    /// Remove least recently used entries until the cache is within capacity
+    /// or only pinned entries are left, handing each one to evicted
+    pub fn evict(&mut self, evicted: |K, V|) {
+        while self.map.len() > self.capacity {
+            match self.pop_lru() {
+                Some((k, v)) => evicted(k, v),
+                None => break,
+            }
+        }
+    }
+
+    /// Entries in no particular order
+    pub fn iter<'a>(&'a self) -> Entries<'a, K, V> {
+        Entries { iter: self.map.iter() }
+    }
+
+    fn link_tail(&mut self, k: K) {
+        {
+            let e = self.map.get_mut(&k);
+            e.prev = self.tail.clone();
+            e.next = None;
+        }
+        match self.tail.clone() {
+            Some(tail) => self.map.get_mut(&tail).next = Some(k.clone()),
+            None => self.head = Some(k.clone()),
+        }
+        self.tail = Some(k);
+    }
+
+    /// Pinned entries are not linked, so this does nothing for them
+    fn unlink(&mut self, k: &K) {
+        let (prev, next) = {
+            let e = self.map.get_mut(k);
+            if e.pinned {
+                return;
+            }
+            (e.prev.take(), e.next.take())
+        };
+
+        match prev {
+            Some(ref p) => self.map.get_mut(p).next = next.clone(),
+            None => self.head = next.clone(),
+        }
+        match next {
+            Some(ref n) => self.map.get_mut(n).prev = prev,
+            None => self.tail = prev,
+        }
+    }
+}
+
+pub struct Entries<'a, K, V> {
+    iter: hashmap::Entries<'a, K, Entry<K, V>>,
+}
+
+impl<'a, K, V> Iterator<(&'a K, &'a V)> for Entries<'a, K, V> {
+    fn next(&mut self) -> Option<(&'a K, &'a V)> {
+        self.iter.next().map(|(k, e)| (k, &e.value))
+    }
+}
+
+#[cfg(test)]
+fn evict_all(cache: &mut LruCache<int, int>) -> ~[int] {
+    let mut evicted = ~[];
+    cache.evict(|k, v| {
+        assert_eq!(k * 10, v);
+        evicted.push(k);
+    });
+    evicted
+}
+
+#[test]
+fn test_lru_eviction_order() {
+    let mut cache = LruCache::new(3);
+    for i in range(0, 5) {
+        cache.insert(i, i * 10);
+    }
+    assert_eq!(cache.len(), 5);
+
+    assert_eq!(evict_all(&mut cache), ~[0, 1]);
+    assert_eq!(cache.len(), 3);
+
+    cache.touch(&2);
+    cache.insert(5, 50);
+    assert_eq!(evict_all(&mut cache), ~[3]);
+
+    // Replacing counts as a use
+    cache.insert(4, 40);
+    cache.insert(6, 60);
+    assert_eq!(evict_all(&mut cache), ~[2]);
+
+    assert_eq!(cache.find(&4), Some(&40));
+    assert_eq!(cache.find(&5), Some(&50));
+    assert_eq!(cache.find(&6), Some(&60));
+}
+
+#[test]
+fn test_lru_capacity() {
+    let mut cache = LruCache::new(2);
+    for i in range(0, 100) {
+        cache.insert(i, i * 10);
+        evict_all(&mut cache);
+        assert!(cache.len() <= cache.capacity());
+    }
+    assert_eq!(cache.iter().count(), 2);
+    assert!(cache.contains_key(&98) && cache.contains_key(&99));
+
+    assert_eq!(cache.pop(&98), Some(980));
+    assert_eq!(cache.pop(&98), None);
+    assert_eq!(evict_all(&mut cache), ~[]);
+    assert_eq!(cache.pop_lru(), Some((99, 990)));
+    assert_eq!(cache.pop_lru(), None);
+}
+
+#[test]
+fn test_lru_pinned() {
+    let mut cache = LruCache::new(1);
+    for i in range(0, 4) {
+        cache.insert(i, i * 10);
+    }
+    cache.pin(&0);
+    cache.pin(&2);
+
+    // Pinned entries stay even though the cache is over capacity
+    assert_eq!(evict_all(&mut cache), ~[1, 3]);
+    assert_eq!(cache.len(), 2);
+
+    cache.unpin(&2);
+    cache.insert(4, 40);
+    assert_eq!(evict_all(&mut cache), ~[2, 4]);
+    assert!(cache.contains_key(&0));
+}
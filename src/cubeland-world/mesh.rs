@@ -0,0 +1,642 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std;
+use std::vec;
+
+use collections::bitv::BitvSet;
+
+use cgmath::vector::Vector;
+use cgmath::vector::Vec3;
+
+use CHUNK_SIZE;
+use terrain::Terrain;
+use terrain::BlockType;
+use light::LightMap;
+use registry::BlockRegistry;
+use registry::TransparencyInvisible;
+use registry::TransparencyTranslucent;
+
+pub static NUM_FACES : uint = 6;
+
+// Layout of the vertex buffer sent to the GPU
+pub struct VertexData {
+    position : Vec3<f32>,
+    blocktype : f32,
+    /// Ambient occlusion, from 0 (darkest) to 3 (unoccluded)
+    ao : f32,
+    /// Light levels from 0 to MAX_LIGHT
+    sky_light : f32,
+    block_light : f32,
+}
+
+pub struct Face {
+    index: uint,
+    normal: Vec3<f32>,
+    di: Vec3<int>,
+    dj: Vec3<int>,
+    dk: Vec3<int>,
+    vertices: [Vec3<f32>, ..4],
+}
+
+/// Greedy-meshed quads for one chunk, ready to upload to vertex and element
+/// buffers
+pub struct Mesh {
+    vertices: ~[VertexData],
+    elements: ~[u32],
+    /// Opaque and cutout geometry
+    face_ranges: [(uint, uint), ..NUM_FACES],
+    /// Translucent geometry, drawn after everything else with blending
+    translucent_face_ranges: [(uint, uint), ..NUM_FACES],
+}
+
+impl Mesh {
+    pub fn gen(t: &Terrain, light: &LightMap, registry: &BlockRegistry) -> ~Mesh {
+        Mesh::gen_inner(t, light, registry, false)
+    }
+
+    /// Mesh a downsampled chunk, also closing off opaque blocks on the
+    /// chunk border
+    ///
+    /// The extra faces stand in for skirts: where a neighbor is drawn at
+    /// another level its surface does not line up, and the border faces
+    /// cover the crack between them. Next to a chunk at the same level they
+    /// are hidden inside its solid blocks.
+    pub fn gen_skirted(t: &Terrain, light: &LightMap, registry: &BlockRegistry) -> ~Mesh {
+        Mesh::gen_inner(t, light, registry, true)
+    }
+
+    /// Size of the vertex and element buffers
+    pub fn size_bytes(&self) -> uint {
+        self.vertices.len() * std::mem::size_of::<VertexData>() +
+            self.elements.len() * std::mem::size_of::<u32>()
+    }
+
+    fn gen_inner(t: &Terrain, light: &LightMap, registry: &BlockRegistry, skirts: bool) -> ~Mesh {
+        // A uniform chunk's padding hides all its faces, so only skirts
+        // could show
+        match t.uniform_type() {
+            Some(blocktype) if !skirts || !registry.is_opaque(blocktype) => {
+                return ~Mesh {
+                    vertices: ~[],
+                    elements: ~[],
+                    face_ranges: [(0, 0), ..NUM_FACES],
+                    translucent_face_ranges: [(0, 0), ..NUM_FACES],
+                };
+            },
+            _ => {},
+        }
+
+        let mut vertices : ~[VertexData] = ~[];
+        let mut elements : ~[u32] = ~[];
+
+        static expected_vertices : uint = 8000;
+        static expected_elements : uint = expected_vertices * 3 / 2;
+        vertices.reserve(expected_vertices);
+        elements.reserve(expected_elements);
+
+        let mut face_ranges = [(0, 0), ..6];
+        let mut translucent_face_ranges = [(0, 0), ..6];
+
+        // Shading of each exposed face in the current pass
+        let mut face_shading = vec::from_elem((CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as uint,
+                                              Shading { ao: 0, sky: 0, block: 0 });
+
+        for &translucent in [false, true].iter() {
+            for face in faces.iter() {
+                let num_elements_start = elements.len();
+
+                let face_normal_int = Vec3 { x: face.normal.x as int, y: face.normal.y as int, z: face.normal.z as int };
+
+                let mut unmeshed_faces = BlockBitmap::new();
+                for x in std::iter::range(0, CHUNK_SIZE as int) {
+                    for y in std::iter::range(0, CHUNK_SIZE as int) {
+                        for z in std::iter::range(0, CHUNK_SIZE as int) {
+                            let block = &t.get(x, y, z);
+
+                            let transparency = registry.transparency(block.blocktype);
+                            if transparency == TransparencyInvisible ||
+                                    (transparency == TransparencyTranslucent) != translucent {
+                                continue;
+                            }
+
+                            let n = Vec3::new(x, y, z).add_v(&face_normal_int);
+                            let neighbor = t.get(n.x, n.y, n.z);
+
+                            let skirt = skirts && !translucent && !in_chunk(n);
+                            if !skirt && !face_exposed(registry, block.blocktype, neighbor.blocktype) {
+                                continue;
+                            }
+
+                            unmeshed_faces.insert(x, y, z);
+                            face_shading[BlockBitmap::index(x, y, z)] =
+                                face_shading_at(t, light, registry, face, Vec3::new(x, y, z));
+                        }
+                    }
+                }
+
+                for i in std::iter::range(0, CHUNK_SIZE as int) {
+                    for j in std::iter::range(0, CHUNK_SIZE as int) {
+                        for k in std::iter::range(0, CHUNK_SIZE as int) {
+                            let Vec3 { x: x, y: y, z: z } = face.di.mul_s(i).add_v(&face.dj.mul_s(j)).add_v(&face.dk.mul_s(k));
+                            let block = &t.get(x, y, z);
+
+                            if !unmeshed_faces.contains(x, y, z) {
+                                continue;
+                            }
+
+                            let block_position = Vec3 {
+                                x: x as f32,
+                                y: y as f32,
+                                z: z as f32,
+                            };
+
+                            let dim = expand_face(t, &unmeshed_faces, face_shading, face, Vec3 { x: x, y: y, z: z });
+                            let dim_f = Vec3 { x: dim.x as f32, y: dim.y as f32, z: dim.z as f32 };
+
+                            for dx in range(0, dim.x) {
+                                for dy in range(0, dim.y) {
+                                    for dz in range(0, dim.z) {
+                                        unmeshed_faces.remove(x + dx, y + dy, z + dz);
+                                    }
+                                }
+                            }
+
+                            // Every face merged into the quad has the same
+                            // shading as this one
+                            let shading = face_shading[BlockBitmap::index(x, y, z)];
+                            let ao = unpack(shading.ao, 2);
+                            let sky = unpack(shading.sky, 4);
+                            let block_light = unpack(shading.block, 4);
+
+                            let vertex_offset = vertices.len();
+                            for (i, v) in face.vertices.iter().enumerate() {
+                                vertices.push(VertexData {
+                                    position: v.mul_v(&dim_f).add_v(&block_position),
+                                    blocktype: block.blocktype as f32,
+                                    ao: ao[i] as f32,
+                                    sky_light: sky[i] as f32,
+                                    block_light: block_light[i] as f32,
+                                });
+                            }
+
+                            // Split the quad along the diagonal with the
+                            // brighter ends, so a single dark corner only
+                            // shades the triangle it belongs to
+                            let quad_elements = if ao[0] + ao[3] > ao[1] + ao[2] {
+                                &flipped_face_elements
+                            } else {
+                                &face_elements
+                            };
+                            for e in quad_elements.iter() {
+                                elements.push(vertex_offset as u32 + *e);
+                            }
+                        }
+                    }
+                }
+
+                let range = (num_elements_start, elements.len() - num_elements_start);
+                if translucent {
+                    translucent_face_ranges[face.index] = range;
+                } else {
+                    face_ranges[face.index] = range;
+                }
+            }
+        }
+
+        ~Mesh {
+            vertices: vertices,
+            elements: elements,
+            face_ranges: face_ranges,
+            translucent_face_ranges: translucent_face_ranges,
+        }
+    }
+}
+
+/// Whether the face of a block that touches the given neighbor can be seen
+fn face_exposed(registry: &BlockRegistry, blocktype: BlockType, neighbor: BlockType) -> bool {
+    if registry.is_opaque(neighbor) {
+        return false;
+    }
+
+    // Adjacent translucent blocks of the same type are one volume
+    !(neighbor == blocktype && registry.transparency(blocktype) == TransparencyTranslucent)
+}
+
+fn in_chunk(p: Vec3<int>) -> bool {
+    let s = CHUNK_SIZE as int;
+    p.x >= 0 && p.x < s && p.y >= 0 && p.y < s && p.z >= 0 && p.z < s
+}
+
+/// Per-vertex values of a face, packed. Faces are only merged into one quad
+/// when these match.
+#[deriving(Eq, Clone)]
+struct Shading {
+    /// Ambient occlusion, 2 bits per vertex
+    ao: u16,
+    /// Light levels, 4 bits per vertex
+    sky: u16,
+    block: u16,
+}
+
+/// Ambient occlusion and light at each vertex of a block's face
+///
+/// Occlusion goes from 0 (darkest) to 3 and counts the opaque blocks
+/// touching the vertex in the layer in front of the face. Two sides make it
+/// fully dark whatever the corner is. Light is the average over the block
+/// in front of the face and the open blocks around the vertex, so it
+/// changes smoothly across faces.
+fn face_shading_at(t: &Terrain, light: &LightMap, registry: &BlockRegistry,
+                   face: &Face, p: Vec3<int>) -> Shading {
+    let normal = Vec3::new(face.normal.x as int, face.normal.y as int, face.normal.z as int);
+    let front = p.add_v(&normal);
+    let occludes = |q: Vec3<int>| registry.is_opaque(t.get(q.x, q.y, q.z).blocktype);
+
+    let mut ao = [0u8, ..4];
+    let mut sky = [0u8, ..4];
+    let mut block = [0u8, ..4];
+    for (i, v) in face.vertices.iter().enumerate() {
+        // Toward the vertex along each axis in the face's plane
+        let v = Vec3::new(v.x as int, v.y as int, v.z as int);
+        let j = face.dj.mul_s(v.dot(&face.dj) * 2 - 1);
+        let k = face.dk.mul_s(v.dot(&face.dk) * 2 - 1);
+
+        let side1 = occludes(front.add_v(&j));
+        let side2 = occludes(front.add_v(&k));
+        let corner = occludes(front.add_v(&j).add_v(&k));
+        ao[i] = if side1 && side2 {
+            0
+        } else {
+            3 - (side1 as u8 + side2 as u8 + corner as u8)
+        };
+
+        let mut sky_sum = light.sky(front.x, front.y, front.z) as uint;
+        let mut block_sum = light.block(front.x, front.y, front.z) as uint;
+        let mut count = 1;
+        let around = [(front.add_v(&j), !side1),
+                      (front.add_v(&k), !side2),
+                      (front.add_v(&j).add_v(&k), !corner && !(side1 && side2))];
+        for &(q, open) in around.iter() {
+            if open {
+                sky_sum += light.sky(q.x, q.y, q.z) as uint;
+                block_sum += light.block(q.x, q.y, q.z) as uint;
+                count += 1;
+            }
+        }
+        sky[i] = ((sky_sum + count / 2) / count) as u8;
+        block[i] = ((block_sum + count / 2) / count) as u8;
+    }
+
+    Shading {
+        ao: pack(ao, 2),
+        sky: pack(sky, 4),
+        block: pack(block, 4),
+    }
+}
+
+/// Pack one value per vertex into bits bits each
+fn pack(values: [u8, ..4], bits: uint) -> u16 {
+    values.iter().enumerate().fold(0u16, |packed, (i, &v)| packed | (v as u16 << (i * bits)))
+}
+
+fn unpack(packed: u16, bits: uint) -> [u8, ..4] {
+    let mask = (1u16 << bits) - 1;
+    let mut values = [0u8, ..4];
+    for i in range(0, 4) {
+        values[i] = ((packed >> (i * bits)) & mask) as u8;
+    }
+    values
+}
+
+fn expand_face(t : &Terrain,
+               unmeshed_faces : &BlockBitmap,
+               face_shading : &[Shading],
+               face: &Face,
+               p: Vec3<int>) -> Vec3<int> {
+
+    let len_k = run_length(t, unmeshed_faces, face_shading, p, face.dk);
+    let len_j = range(0, len_k).
+        map(|k| run_length(t, unmeshed_faces, face_shading, p.add_v(&face.dk.mul_s(k)), face.dj)).
+        min().unwrap();
+
+    (Vec3 { x: 1, y: 1, z: 1 }).
+        add_v(&face.dk.mul_s(len_k - 1)).
+        add_v(&face.dj.mul_s(len_j - 1))
+}
+
+/// Number of faces in a row from p that can merge with the face at p: same
+/// block type and same shading
+fn run_length(t : &Terrain,
+              unmeshed_faces : &BlockBitmap,
+              face_shading : &[Shading],
+              mut p: Vec3<int>,
+              dp: Vec3<int>) -> int {
+    let block = &t.get(p.x, p.y, p.z);
+    let shading = face_shading[BlockBitmap::index(p.x, p.y, p.z)];
+    let max_len = Vec3::new(CHUNK_SIZE, CHUNK_SIZE, CHUNK_SIZE).sub_v(&p).dot(&dp);
+
+    let mut len = 1;
+
+    while len < max_len {
+        p.add_self_v(&dp);
+
+        if unmeshed_faces.contains(p.x, p.y, p.z) {
+            let b = t.get(p.x, p.y, p.z);
+            if b.blocktype == block.blocktype && face_shading[BlockBitmap::index(p.x, p.y, p.z)] == shading {
+                len += 1;
+            } else {
+                break;
+            }
+        } else {
+            break;
+        }
+    }
+
+    len
+}
+
+struct BlockBitmap {
+    set : BitvSet
+}
+
+impl BlockBitmap {
+    pub fn new() -> BlockBitmap {
+        BlockBitmap {
+            set: BitvSet::new()
+        }
+    }
+
+    pub fn contains(&self, x: int, y: int, z: int) -> bool {
+        self.set.contains(&BlockBitmap::index(x, y, z))
+    }
+
+    pub fn insert(&mut self, x: int, y: int, z: int) {
+        self.set.insert(BlockBitmap::index(x, y, z));
+    }
+
+    pub fn remove(&mut self, x: int, y: int, z: int) {
+        self.set.remove(&BlockBitmap::index(x, y, z));
+    }
+
+    fn index(x: int, y: int, z: int) -> uint {
+        (x*CHUNK_SIZE*CHUNK_SIZE + y*CHUNK_SIZE + z) as uint
+    }
+}
+
+static face_elements : [u32, ..6] = [
+    0, 1, 2, 3, 2, 1,
+];
+
+/// The same quad split along the other diagonal
+static flipped_face_elements : [u32, ..6] = [
+    0, 1, 3, 0, 3, 2,
+];
+
+pub static faces : [Face, ..NUM_FACES] = [
+    /* front */
+    Face {
+        index: 0,
+        normal: Vec3 { x: 0.0, y: 0.0, z: 1.0 },
+        di: Vec3 { x: 0, y: 0, z: 1 },
+        dj: Vec3 { x: 1, y: 0, z: 0 },
+        dk: Vec3 { x: 0, y: 1, z: 0 },
+        vertices: [
+            Vec3 { x: 0.0, y: 0.0, z: 1.0 }, /* bottom left */
+            Vec3 { x: 1.0, y: 0.0, z: 1.0 },  /* bottom right */
+            Vec3 { x: 0.0, y: 1.0, z: 1.0 }, /* top left */
+            Vec3 { x: 1.0, y: 1.0, z: 1.0 },  /* top right */
+        ],
+    },
+
+    /* back */
+    Face {
+        index: 1,
+        normal: Vec3 { x: 0.0, y: 0.0, z: -1.0 },
+        di: Vec3 { x: 0, y: 0, z: 1 },
+        dj: Vec3 { x: 1, y: 0, z: 0 },
+        dk: Vec3 { x: 0, y: 1, z: 0 },
+        vertices: [
+            Vec3 { x: 1.0, y: 0.0, z: 0.0 }, /* bottom right */
+            Vec3 { x: 0.0, y: 0.0, z: 0.0 },  /* bottom left */
+            Vec3 { x: 1.0, y: 1.0, z: 0.0 }, /* top right */
+            Vec3 { x: 0.0, y: 1.0, z: 0.0 },  /* top left */
+        ],
+    },
+
+    /* right */
+    Face {
+        index: 2,
+        normal: Vec3 { x: 1.0, y: 0.0, z: 0.0 },
+        di: Vec3 { x: 1, y: 0, z: 0 },
+        dj: Vec3 { x: 0, y: 1, z: 0 },
+        dk: Vec3 { x: 0, y: 0, z: 1 },
+        vertices: [
+            Vec3 { x: 1.0, y: 0.0, z: 1.0 }, /* bottom front */
+            Vec3 { x: 1.0, y: 0.0, z: 0.0 }, /* bottom back */
+            Vec3 { x: 1.0, y: 1.0, z: 1.0 }, /* top front */
+            Vec3 { x: 1.0, y: 1.0, z: 0.0 }, /* top back */
+        ],
+    },
+
+    /* left */
+    Face {
+        index: 3,
+        normal: Vec3 { x: -1.0, y: 0.0, z: 0.0 },
+        di: Vec3 { x: 1, y: 0, z: 0 },
+        dj: Vec3 { x: 0, y: 1, z: 0 },
+        dk: Vec3 { x: 0, y: 0, z: 1 },
+        vertices: [
+            Vec3 { x: 0.0, y: 0.0, z: 0.0 }, /* bottom back */
+            Vec3 { x: 0.0, y: 0.0, z: 1.0 }, /* bottom front */
+            Vec3 { x: 0.0, y: 1.0, z: 0.0 }, /* top back */
+            Vec3 { x: 0.0, y: 1.0, z: 1.0 }, /* top front */
+        ],
+    },
+
+    /* top */
+    Face {
+        index: 4,
+        normal: Vec3 { x: 0.0, y: 1.0, z: 0.0 },
+        di: Vec3 { x: 0, y: 1, z: 0 },
+        dj: Vec3 { x: 1, y: 0, z: 0 },
+        dk: Vec3 { x: 0, y: 0, z: 1 },
+        vertices: [
+            Vec3 { x: 0.0, y: 1.0, z: 1.0 }, /* front left */
+            Vec3 { x: 1.0, y: 1.0, z: 1.0 }, /* front right */
+            Vec3 { x: 0.0, y: 1.0, z: 0.0 }, /* back left */
+            Vec3 { x: 1.0, y: 1.0, z: 0.0 }, /* back right */
+        ],
+    },
+
+    /* bottom */
+    Face {
+        index: 5,
+        normal: Vec3 { x: 0.0, y: -1.0, z: 0.0 },
+        di: Vec3 { x: 0, y: 1, z: 0 },
+        dj: Vec3 { x: 1, y: 0, z: 0 },
+        dk: Vec3 { x: 0, y: 0, z: 1 },
+        vertices: [
+            Vec3 { x: 0.0, y: 0.0, z: 0.0 }, /* back left */
+            Vec3 { x: 1.0, y: 0.0, z: 0.0 }, /* back right */
+            Vec3 { x: 0.0, y: 0.0, z: 1.0 }, /* front left */
+            Vec3 { x: 1.0, y: 0.0, z: 1.0 }, /* front right */
+        ],
+    },
+];
+
+#[cfg(test)]
+fn quad_counts(ranges: &[(uint, uint), ..NUM_FACES]) -> ~[uint] {
+    ranges.iter().map(|&(_, count)| count / face_elements.len()).collect()
+}
+
+#[test]
+fn test_mesh_water_on_stone() {
+    use terrain::{BlockStone, BlockWater};
+
+    let registry = BlockRegistry::builtin();
+    let mut t = Terrain::new();
+    t.set(5, 5, 5, BlockStone);
+    t.set(5, 6, 5, BlockWater);
+    let mesh = Mesh::gen(t, LightMap::daylight(), &registry);
+
+    // The stone top face shows through the water
+    assert_eq!(quad_counts(&mesh.face_ranges), ~[1, 1, 1, 1, 1, 1]);
+    // The water bottom face is hidden by the stone
+    assert_eq!(quad_counts(&mesh.translucent_face_ranges), ~[1, 1, 1, 1, 1, 0]);
+}
+
+#[test]
+fn test_mesh_water_volume() {
+    use terrain::BlockWater;
+
+    let registry = BlockRegistry::builtin();
+    let mut t = Terrain::new();
+    for x in range(4, 8) {
+        for z in range(4, 6) {
+            t.set(x, 10, z, BlockWater);
+        }
+    }
+    let mesh = Mesh::gen(t, LightMap::daylight(), &registry);
+
+    // No faces between water blocks, so each side is a single quad
+    assert_eq!(quad_counts(&mesh.face_ranges), ~[0, 0, 0, 0, 0, 0]);
+    assert_eq!(quad_counts(&mesh.translucent_face_ranges), ~[1, 1, 1, 1, 1, 1]);
+}
+
+#[test]
+fn test_mesh_stone_faces_hidden() {
+    use terrain::{BlockStone, BlockWater};
+
+    let registry = BlockRegistry::builtin();
+    let mut t = Terrain::new();
+    t.set(5, 5, 5, BlockStone);
+    t.set(6, 5, 5, BlockStone);
+    // Water in the padding outside the chunk does not hide the face next to it
+    t.set(0, 0, 0, BlockStone);
+    t.set(-1, 0, 0, BlockWater);
+    let mesh = Mesh::gen(t, LightMap::daylight(), &registry);
+
+    let quads = quad_counts(&mesh.face_ranges);
+    assert_eq!(quads[2], 2);
+    assert_eq!(quads[3], 2);
+    assert!(mesh.translucent_face_ranges.iter().all(|&(_, count)| count == 0));
+}
+
+#[test]
+fn test_mesh_skirts() {
+    use terrain::BlockStone;
+
+    let registry = BlockRegistry::builtin();
+    let mut t = Terrain::new();
+    for x in range(-1, CHUNK_SIZE + 1) {
+        for y in range(-1, 4) {
+            for z in range(-1, CHUNK_SIZE + 1) {
+                t.set(x, y, z, BlockStone);
+            }
+        }
+    }
+
+    // A flat floor continuing into its neighbors only has a top
+    assert_eq!(quad_counts(&Mesh::gen(t, LightMap::daylight(), &registry).face_ranges), ~[0, 0, 0, 0, 1, 0]);
+
+    // Skirts close off every side
+    assert_eq!(quad_counts(&Mesh::gen_skirted(t, LightMap::daylight(), &registry).face_ranges), ~[1, 1, 1, 1, 1, 1]);
+}
+
+#[test]
+fn test_mesh_uniform() {
+    use terrain::BlockStone;
+
+    let registry = BlockRegistry::builtin();
+    let t = Terrain::uniform(BlockStone);
+    assert!(Mesh::gen(t, LightMap::daylight(), &registry).elements.is_empty());
+    assert_eq!(quad_counts(&Mesh::gen_skirted(t, LightMap::daylight(), &registry).face_ranges), ~[1, 1, 1, 1, 1, 1]);
+}
+
+#[test]
+fn test_mesh_ambient_occlusion() {
+    use terrain::BlockStone;
+
+    let registry = BlockRegistry::builtin();
+    let mut t = Terrain::new();
+    t.set(4, 5, 5, BlockStone);
+    t.set(5, 5, 5, BlockStone);
+    // A wall block up and to the right of the second one
+    t.set(6, 6, 5, BlockStone);
+    let mesh = Mesh::gen(t, LightMap::daylight(), &registry);
+
+    // The two floor tops are shaded differently, so they are not merged
+    assert_eq!(quad_counts(&mesh.face_ranges)[4], 3);
+
+    // The right edge of the second floor top is darkened by the wall
+    let (offset, count) = mesh.face_ranges[4];
+    for &e in mesh.elements.slice(offset, offset + count).iter() {
+        let v = &mesh.vertices[e as uint];
+        let expected = if v.position.y == 6.0 && v.position.x == 6.0 { 2.0 } else { 3.0 };
+        assert_eq!(v.ao, expected);
+    }
+}
+
+#[test]
+fn test_mesh_light() {
+    use terrain::BlockStone;
+
+    let registry = BlockRegistry::builtin();
+    let glowstone = registry.find("glowstone").unwrap();
+    let mut t = Terrain::new();
+    for x in range(0, 8) {
+        t.set(x, 5, 5, BlockStone);
+    }
+    t.set(2, 6, 5, glowstone);
+    let light = LightMap::compute(t, &registry, |_, _| false);
+    let mesh = Mesh::gen(t, light, &registry);
+
+    // The floor tops get darker away from the glowstone, so the ones to its
+    // right cannot merge into one quad
+    let (offset, count) = mesh.face_ranges[4];
+    assert!(count / face_elements.len() > 3);
+    let mut near = 0.0f32;
+    let mut far = 0.0f32;
+    for &e in mesh.elements.slice(offset, offset + count).iter() {
+        let v = &mesh.vertices[e as uint];
+        assert_eq!(v.sky_light, 0.0);
+        if v.position.x <= 3.0 {
+            near = near.max(v.block_light);
+        } else if v.position.x == 8.0 {
+            far = far.max(v.block_light);
+        }
+    }
+    assert!(near >= (registry.light(glowstone) - 3) as f32);
+    assert!(far < near);
+}
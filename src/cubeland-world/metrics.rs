@@ -0,0 +1,461 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Counters, gauges and histograms shared between the game's threads
+//!
+//! Everything records into one registry behind a Metrics handle. A
+//! MetricsDumper appends snapshots of it to a file every so often, as CSV
+//! or JSON lines, so benchmark runs can be compared by script. The handle
+//! also filters log messages by level, which replaces printing a status
+//! line every second.
+
+extern crate getopts;
+extern crate serialize;
+
+use std::cmp;
+use std::vec;
+use std::io::{File, IoResult};
+
+use collections::TreeMap;
+use serialize::json;
+use serialize::json::Json;
+use sync::{Arc, Mutex};
+use time::precise_time_ns;
+
+use ratelimiter::RateLimiter;
+
+/// Histogram buckets. Bucket 0 counts values below 1, and bucket i counts
+/// values from 2^(i-1) up to 2^i.
+static NUM_BUCKETS : uint = 64;
+
+#[deriving(Clone, Eq, Ord, Show)]
+pub enum LogLevel {
+    LogError,
+    LogInfo,
+    LogDebug,
+}
+
+pub fn parse_log_level(s: &str) -> Result<LogLevel, ~str> {
+    match s {
+        "error" => Ok(LogError),
+        "info" => Ok(LogInfo),
+        "debug" => Ok(LogDebug),
+        _ => Err(format!("log-level: expected error, info or debug, not {}", s)),
+    }
+}
+
+/// Distribution of recorded values in power of two buckets
+///
+/// Quantiles are only as precise as the buckets, within a factor of two.
+#[deriving(Clone)]
+pub struct Histogram {
+    count: u64,
+    sum: f64,
+    min: f64,
+    max: f64,
+    priv buckets: ~[u64],
+}
+
+impl Histogram {
+    pub fn new() -> Histogram {
+        Histogram {
+            count: 0,
+            sum: 0.0,
+            min: 0.0,
+            max: 0.0,
+            buckets: vec::from_elem(NUM_BUCKETS, 0u64),
+        }
+    }
+
+    pub fn record(&mut self, x: f64) {
+        if self.count == 0 || x < self.min {
+            self.min = x;
+        }
+        if self.count == 0 || x > self.max {
+            self.max = x;
+        }
+        self.count += 1;
+        self.sum += x;
+        self.buckets[bucket(x)] += 1;
+    }
+
+    pub fn merge(&mut self, other: &Histogram) {
+        if other.count == 0 {
+            return;
+        }
+        if self.count == 0 || other.min < self.min {
+            self.min = other.min;
+        }
+        if self.count == 0 || other.max > self.max {
+            self.max = other.max;
+        }
+        self.count += other.count;
+        self.sum += other.sum;
+        for (a, &b) in self.buckets.mut_iter().zip(other.buckets.iter()) {
+            *a += b;
+        }
+    }
+
+    pub fn mean(&self) -> f64 {
+        if self.count == 0 { 0.0 } else { self.sum / self.count as f64 }
+    }
+
+    /// Upper bound of the bucket holding the q quantile, clamped to the
+    /// range of recorded values
+    pub fn quantile(&self, q: f64) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        let rank = cmp::max((q * self.count as f64).ceil() as u64, 1);
+        let mut seen = 0;
+        for (i, &n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if seen >= rank {
+                let upper = if i == 0 { 1.0 } else { (1u64 << i) as f64 };
+                return upper.max(self.min).min(self.max);
+            }
+        }
+        self.max
+    }
+}
+
+fn bucket(x: f64) -> uint {
+    if x < 1.0 {
+        0
+    } else {
+        cmp::min(x.log2().floor() as uint + 1, NUM_BUCKETS - 1)
+    }
+}
+
+/// Every metric by name, in name order
+#[deriving(Clone)]
+pub struct Registry {
+    counters: TreeMap<~str, u64>,
+    gauges: TreeMap<~str, f64>,
+    histograms: TreeMap<~str, Histogram>,
+}
+
+impl Registry {
+    pub fn new() -> Registry {
+        Registry {
+            counters: TreeMap::new(),
+            gauges: TreeMap::new(),
+            histograms: TreeMap::new(),
+        }
+    }
+
+    /// One row per metric with time in seconds, in the columns of
+    /// CSV_HEADER. Columns that do not apply to a kind of metric are empty.
+    pub fn to_csv(&self, time: f64) -> ~str {
+        let mut s = ~"";
+        for (name, &n) in self.counters.iter() {
+            s.push_str(format!("{},counter,{},{},{},,,,,\n", time, *name, n, n));
+        }
+        for (name, &x) in self.gauges.iter() {
+            s.push_str(format!("{},gauge,{},,{},,,,,\n", time, *name, x));
+        }
+        for (name, h) in self.histograms.iter() {
+            s.push_str(format!("{},histogram,{},{},,{},{},{},{},{}\n", time, *name, h.count,
+                               h.mean(), h.quantile(0.5), h.quantile(0.9), h.quantile(0.99), h.max));
+        }
+        s
+    }
+
+    /// A JSON object with time in seconds and a member for each kind of
+    /// metric
+    pub fn to_json(&self, time: f64) -> Json {
+        let mut counters = ~TreeMap::new();
+        for (name, &n) in self.counters.iter() {
+            counters.insert(name.clone(), json::Number(n as f64));
+        }
+
+        let mut gauges = ~TreeMap::new();
+        for (name, &x) in self.gauges.iter() {
+            gauges.insert(name.clone(), json::Number(x));
+        }
+
+        let mut histograms = ~TreeMap::new();
+        for (name, h) in self.histograms.iter() {
+            let mut fields = ~TreeMap::new();
+            fields.insert(~"count", json::Number(h.count as f64));
+            fields.insert(~"mean", json::Number(h.mean()));
+            fields.insert(~"p50", json::Number(h.quantile(0.5)));
+            fields.insert(~"p90", json::Number(h.quantile(0.9)));
+            fields.insert(~"p99", json::Number(h.quantile(0.99)));
+            fields.insert(~"max", json::Number(h.max));
+            histograms.insert(name.clone(), json::Object(fields));
+        }
+
+        let mut root = ~TreeMap::new();
+        root.insert(~"time", json::Number(time));
+        root.insert(~"counters", json::Object(counters));
+        root.insert(~"gauges", json::Object(gauges));
+        root.insert(~"histograms", json::Object(histograms));
+        json::Object(root)
+    }
+}
+
+pub static CSV_HEADER : &'static str = "time,kind,name,count,value,mean,p50,p90,p99,max\n";
+
+/// Handle to a shared registry and log level; clones record into the same
+/// registry
+#[deriving(Clone)]
+pub struct Metrics {
+    priv registry: Arc<Mutex<Registry>>,
+    priv level: LogLevel,
+}
+
+impl Metrics {
+    pub fn new(level: LogLevel) -> Metrics {
+        Metrics {
+            registry: Arc::new(Mutex::new(Registry::new())),
+            level: level,
+        }
+    }
+
+    pub fn count(&self, name: &str, n: u64) {
+        let mut registry = self.registry.lock();
+        *entry(&mut registry.counters, name, || 0) += n;
+    }
+
+    pub fn set_gauge(&self, name: &str, x: f64) {
+        let mut registry = self.registry.lock();
+        registry.gauges.insert(name.to_owned(), x);
+    }
+
+    pub fn record(&self, name: &str, x: f64) {
+        let mut registry = self.registry.lock();
+        entry(&mut registry.histograms, name, || Histogram::new()).record(x);
+    }
+
+    /// Add values recorded elsewhere to a histogram
+    pub fn merge(&self, name: &str, h: &Histogram) {
+        let mut registry = self.registry.lock();
+        entry(&mut registry.histograms, name, || Histogram::new()).merge(h);
+    }
+
+    pub fn snapshot(&self) -> Registry {
+        self.registry.lock().clone()
+    }
+
+    pub fn enabled(&self, level: LogLevel) -> bool {
+        level <= self.level
+    }
+
+    pub fn log(&self, level: LogLevel, msg: &str) {
+        if self.enabled(level) {
+            println!("{}", msg);
+        }
+    }
+
+    pub fn info(&self, msg: &str) {
+        self.log(LogInfo, msg);
+    }
+
+    pub fn debug(&self, msg: &str) {
+        self.log(LogDebug, msg);
+    }
+}
+
+fn entry<'a, V>(map: &'a mut TreeMap<~str, V>, name: &str, new: || -> V) -> &'a mut V {
+    let key = name.to_owned();
+    if !map.contains_key(&key) {
+        map.insert(key.clone(), new());
+    }
+    map.find_mut(&key).unwrap()
+}
+
+#[deriving(Clone, Eq, Show)]
+pub struct MetricsConfig {
+    log_level: LogLevel,
+    /// File to append snapshots to, as JSON lines if it ends in .json and
+    /// CSV otherwise
+    dump_path: Option<~str>,
+    /// Seconds between snapshots
+    dump_interval: f64,
+}
+
+impl MetricsConfig {
+    pub fn default() -> MetricsConfig {
+        MetricsConfig {
+            log_level: LogInfo,
+            dump_path: None,
+            dump_interval: 1.0,
+        }
+    }
+
+    /// Command line options understood by from_matches
+    pub fn opts() -> ~[getopts::OptGroup] {
+        ~[
+            getopts::optopt("", "log-level", "error, info or debug (default info)", "LEVEL"),
+            getopts::optopt("", "metrics", "write metrics to FILE, as JSON lines if it ends in .json and CSV otherwise", "FILE"),
+            getopts::optopt("", "metrics-interval", "seconds between metrics snapshots (default 1)", "SECONDS"),
+        ]
+    }
+
+    pub fn from_matches(matches: &getopts::Matches) -> Result<MetricsConfig, ~str> {
+        let mut config = MetricsConfig::default();
+
+        match matches.opt_str("log-level") {
+            Some(s) => config.log_level = try!(parse_log_level(s)),
+            None => {},
+        }
+
+        config.dump_path = matches.opt_str("metrics");
+
+        match matches.opt_str("metrics-interval") {
+            Some(s) => {
+                config.dump_interval = match from_str::<f64>(s) {
+                    Some(x) if x > 0.0 => x,
+                    _ => return Err(format!("metrics-interval: invalid interval {}", s)),
+                };
+            },
+            None => {},
+        }
+
+        Ok(config)
+    }
+}
+
+/// Appends a snapshot of the metrics to a file at a fixed interval
+pub struct MetricsDumper {
+    priv file: File,
+    priv json: bool,
+    priv limiter: RateLimiter,
+    priv start: u64,
+}
+
+impl MetricsDumper {
+    /// None if the config names no file
+    pub fn open(config: &MetricsConfig) -> IoResult<Option<MetricsDumper>> {
+        let path = match config.dump_path {
+            Some(ref path) => path.as_slice(),
+            None => return Ok(None),
+        };
+
+        let json = path.ends_with(".json");
+        let mut file = try!(File::create(&Path::new(path)));
+        if !json {
+            try!(file.write_str(CSV_HEADER));
+        }
+
+        Ok(Some(MetricsDumper {
+            file: file,
+            json: json,
+            limiter: RateLimiter::new((config.dump_interval * 1e9) as u64),
+            start: precise_time_ns(),
+        }))
+    }
+
+    /// Write a snapshot if the interval has passed since the last one
+    pub fn tick(&mut self, metrics: &Metrics) -> IoResult<()> {
+        if self.limiter.limit() {
+            self.dump(metrics)
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn dump(&mut self, metrics: &Metrics) -> IoResult<()> {
+        let time = (precise_time_ns() - self.start) as f64 / 1e9;
+        let snapshot = metrics.snapshot();
+        if self.json {
+            try!(self.file.write_line(snapshot.to_json(time).to_str()));
+        } else {
+            try!(self.file.write_str(snapshot.to_csv(time)));
+        }
+        self.file.flush()
+    }
+}
+
+#[test]
+fn test_histogram() {
+    let mut h = Histogram::new();
+    assert_eq!(h.quantile(0.5), 0.0);
+    for x in range(1, 101) {
+        h.record(x as f64);
+    }
+    assert_eq!(h.count, 100);
+    assert_eq!(h.mean(), 50.5);
+    assert_eq!((h.min, h.max), (1.0, 100.0));
+    // 50 falls in the bucket from 32 to 64
+    assert_eq!(h.quantile(0.5), 64.0);
+    assert_eq!(h.quantile(0.99), 100.0);
+
+    let mut other = Histogram::new();
+    other.record(0.25);
+    other.record(1000.0);
+    h.merge(&other);
+    assert_eq!(h.count, 102);
+    assert_eq!((h.min, h.max), (0.25, 1000.0));
+    assert_eq!(h.quantile(0.001), 1.0);
+    assert_eq!(h.quantile(1.0), 1000.0);
+}
+
+#[test]
+fn test_metrics_shared() {
+    let metrics = Metrics::new(LogInfo);
+    let other = metrics.clone();
+    metrics.count("loaded", 2);
+    other.count("loaded", 3);
+    other.set_gauge("inflight", 4.0);
+    other.set_gauge("inflight", 7.0);
+    metrics.record("mesh_us", 10.0);
+    let mut h = Histogram::new();
+    h.record(30.0);
+    other.merge("mesh_us", &h);
+
+    let snapshot = metrics.snapshot();
+    assert_eq!(snapshot.counters.find(&~"loaded"), Some(&5));
+    assert_eq!(snapshot.gauges.find(&~"inflight"), Some(&7.0));
+    assert_eq!(snapshot.histograms.find(&~"mesh_us").unwrap().mean(), 20.0);
+
+    assert!(metrics.enabled(LogError));
+    assert!(metrics.enabled(LogInfo));
+    assert!(!metrics.enabled(LogDebug));
+}
+
+#[test]
+fn test_metrics_dump_formats() {
+    let metrics = Metrics::new(LogInfo);
+    metrics.count("chunk.loaded", 3);
+    metrics.set_gauge("chunk.inflight", 1.5);
+    metrics.record("frame.time_ms", 2.0);
+    metrics.record("frame.time_ms", 6.0);
+    let snapshot = metrics.snapshot();
+
+    assert_eq!(snapshot.to_csv(1.5),
+               ~"1.5,counter,chunk.loaded,3,3,,,,,\n\
+                 1.5,gauge,chunk.inflight,,1.5,,,,,\n\
+                 1.5,histogram,frame.time_ms,2,,4,4,6,6,6\n");
+    // Every row has a value for each column
+    let columns = CSV_HEADER.split(',').count();
+    assert!(snapshot.to_csv(0.0).lines().all(|l| l.split(',').count() == columns));
+
+    let parsed = json::from_str(snapshot.to_json(1.5).to_str()).unwrap();
+    let expected = json::from_str(
+        "{\"time\": 1.5, \
+          \"counters\": {\"chunk.loaded\": 3}, \
+          \"gauges\": {\"chunk.inflight\": 1.5}, \
+          \"histograms\": {\"frame.time_ms\": \
+            {\"count\": 2, \"mean\": 4, \"p50\": 4, \"p90\": 6, \"p99\": 6, \"max\": 6}}}").unwrap();
+    assert_eq!(parsed, expected);
+}
+
+#[test]
+fn test_parse_log_level() {
+    assert_eq!(parse_log_level("debug"), Ok(LogDebug));
+    assert_eq!(parse_log_level("error"), Ok(LogError));
+    assert!(parse_log_level("loud").is_err());
+}
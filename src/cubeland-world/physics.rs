@@ -0,0 +1,284 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate cgmath;
+
+use cgmath::vector::Vector;
+use cgmath::vector::Vec3;
+
+use chunk::BlockSource;
+#[cfg(test)]
+use chunk::TestWorld;
+#[cfg(test)]
+use terrain::BlockStone;
+
+pub static PLAYER_WIDTH : f64 = 0.6;
+pub static PLAYER_HEIGHT : f64 = 1.8;
+pub static EYE_HEIGHT : f64 = 1.6;
+
+static GRAVITY : f64 = 25.0;
+static TERMINAL_VELOCITY : f64 = 50.0;
+static JUMP_SPEED : f64 = 8.0;
+
+// Tolerance for treating a box as touching a block face
+static EPSILON : f64 = 1e-7;
+
+/// Axis-aligned bounding box in world coordinates
+#[deriving(Eq, Show)]
+pub struct Aabb {
+    min: Vec3<f64>,
+    max: Vec3<f64>,
+}
+
+impl Aabb {
+    pub fn translate(&self, d: Vec3<f64>) -> Aabb {
+        Aabb { min: self.min.add_v(&d), max: self.max.add_v(&d) }
+    }
+}
+
+/// A walking body affected by gravity and blocked by opaque blocks
+pub struct Player {
+    /// Center of the bottom face of the bounding box
+    position: Vec3<f64>,
+    velocity: Vec3<f64>,
+    on_ground: bool,
+}
+
+impl Player {
+    pub fn new(position: Vec3<f64>) -> Player {
+        Player {
+            position: position,
+            velocity: Vec3::zero(),
+            on_ground: false,
+        }
+    }
+
+    pub fn aabb(&self) -> Aabb {
+        let r = PLAYER_WIDTH / 2.0;
+        Aabb {
+            min: self.position.add_v(&Vec3::new(-r, 0.0, -r)),
+            max: self.position.add_v(&Vec3::new(r, PLAYER_HEIGHT, r)),
+        }
+    }
+
+    /// Advance the simulation
+    ///
+    /// walk is the desired horizontal velocity in blocks per second. Jumping
+    /// only has an effect while standing on the ground.
+    pub fn tick<S: BlockSource>(&mut self, source: &S, walk: Vec3<f64>, jump: bool, tick_length: f64) {
+        self.velocity.x = walk.x;
+        self.velocity.z = walk.z;
+
+        if jump && self.on_ground {
+            self.velocity.y = JUMP_SPEED;
+        }
+
+        self.velocity.y = (self.velocity.y - GRAVITY * tick_length).max(-TERMINAL_VELOCITY);
+
+        let motion = self.velocity.mul_s(tick_length);
+        let (moved, blocked) = sweep(source, &self.aabb(), motion);
+        self.position.add_self_v(&moved);
+
+        self.on_ground = blocked.y && motion.y < 0.0;
+        if blocked.x {
+            self.velocity.x = 0.0;
+        }
+        if blocked.y {
+            self.velocity.y = 0.0;
+        }
+        if blocked.z {
+            self.velocity.z = 0.0;
+        }
+    }
+}
+
+/// Move a box through the world, stopping at opaque blocks
+///
+/// Axes are resolved one at a time in the order y, x, z so the result does
+/// not depend on frame timing beyond the motion itself. Blocks in chunks that
+/// are not loaded are treated as solid. Returns the motion actually applied
+/// and which axes were blocked.
+pub fn sweep<S: BlockSource>(source: &S, aabb: &Aabb, motion: Vec3<f64>) -> (Vec3<f64>, Vec3<bool>) {
+    let mut b = *aabb;
+    let mut moved = Vec3::zero();
+    let mut blocked = Vec3 { x: false, y: false, z: false };
+
+    for &axis in [1u, 0, 2].iter() {
+        let delta = get_axis(&motion, axis);
+        let clipped = clip_axis(source, &b, axis, delta);
+        set_axis(&mut moved, axis, clipped);
+        if clipped != delta {
+            set_axis(&mut blocked, axis, true);
+        }
+
+        let mut d = Vec3::zero();
+        set_axis(&mut d, axis, clipped);
+        b = b.translate(d);
+    }
+
+    (moved, blocked)
+}
+
+/// Shorten a move along one axis so the box stops at the first solid block
+fn clip_axis<S: BlockSource>(source: &S, b: &Aabb, axis: uint, delta: f64) -> f64 {
+    if delta == 0.0 {
+        return 0.0;
+    }
+
+    let (u, v) = match axis {
+        0 => (1, 2),
+        1 => (0, 2),
+        _ => (0, 1),
+    };
+
+    let u_min = (get_axis(&b.min, u) + EPSILON).floor() as i64;
+    let u_max = (get_axis(&b.max, u) - EPSILON).floor() as i64;
+    let v_min = (get_axis(&b.min, v) + EPSILON).floor() as i64;
+    let v_max = (get_axis(&b.max, v) - EPSILON).floor() as i64;
+
+    let lo = get_axis(&b.min, axis);
+    let hi = get_axis(&b.max, axis);
+
+    // Layers of blocks the leading face passes through, nearest first
+    let (first, last, step) = if delta > 0.0 {
+        ((hi - EPSILON).ceil() as i64, (hi + delta).floor() as i64, 1)
+    } else {
+        ((lo + EPSILON).floor() as i64 - 1, (lo + delta).floor() as i64, -1)
+    };
+
+    let mut i = first;
+    while (step > 0 && i <= last) || (step < 0 && i >= last) {
+        for j in range(u_min, u_max + 1) {
+            for k in range(v_min, v_max + 1) {
+                let mut p = Vec3::new(0i64, 0, 0);
+                set_axis(&mut p, axis, i);
+                set_axis(&mut p, u, j);
+                set_axis(&mut p, v, k);
+                if is_solid(source, p) {
+                    return if step > 0 {
+                        (i as f64 - hi).max(0.0).min(delta)
+                    } else {
+                        ((i + 1) as f64 - lo).min(0.0).max(delta)
+                    };
+                }
+            }
+        }
+        i += step;
+    }
+
+    delta
+}
+
+fn is_solid<S: BlockSource>(source: &S, p: Vec3<i64>) -> bool {
+    match source.get_block(p) {
+        Some(block) => source.registry().is_opaque(block.blocktype),
+        None => true,
+    }
+}
+
+fn get_axis<T: Clone>(v: &Vec3<T>, axis: uint) -> T {
+    match axis {
+        0 => v.x.clone(),
+        1 => v.y.clone(),
+        _ => v.z.clone(),
+    }
+}
+
+fn set_axis<T>(v: &mut Vec3<T>, axis: uint, x: T) {
+    match axis {
+        0 => v.x = x,
+        1 => v.y = x,
+        _ => v.z = x,
+    }
+}
+
+#[cfg(test)]
+fn floor_world() -> TestWorld {
+    let mut world = TestWorld::new([(0, 0, 0), (0, -1, 0)]);
+    for x in range(0i64, 32) {
+        for z in range(0i64, 32) {
+            world.set(Vec3::new(x, 0, z), BlockStone);
+        }
+    }
+    world
+}
+
+#[test]
+fn test_player_falls_to_rest() {
+    let world = floor_world();
+    let mut player = Player::new(Vec3::new(5.5, 20.0, 5.5));
+
+    for _ in range(0, 300) {
+        player.tick(&world, Vec3::zero(), false, 1.0 / 60.0);
+    }
+
+    assert!((player.position.y - 1.0).abs() < 1e-6);
+    assert_eq!(player.position.x, 5.5);
+    assert_eq!(player.position.z, 5.5);
+    assert!(player.on_ground);
+    assert_eq!(player.velocity.y, 0.0);
+}
+
+#[test]
+fn test_player_wall() {
+    let mut world = floor_world();
+    for y in range(1i64, 4) {
+        for z in range(0i64, 32) {
+            world.set(Vec3::new(10, y, z), BlockStone);
+        }
+    }
+
+    let mut player = Player::new(Vec3::new(5.5, 1.0, 5.5));
+    for _ in range(0, 300) {
+        player.tick(&world, Vec3::new(4.0, 0.0, 1.0), false, 1.0 / 60.0);
+    }
+
+    // Stopped by the wall in x but still sliding along it in z
+    assert!((player.position.x - (10.0 - PLAYER_WIDTH / 2.0)).abs() < 1e-6);
+    assert!(player.position.z > 9.0);
+    assert!((player.position.y - 1.0).abs() < 1e-6);
+}
+
+#[test]
+fn test_player_jump() {
+    let mut world = floor_world();
+    let mut player = Player::new(Vec3::new(5.5, 1.0, 5.5));
+    player.tick(&world, Vec3::zero(), false, 1.0 / 60.0);
+    assert!(player.on_ground);
+
+    player.tick(&world, Vec3::zero(), true, 1.0 / 60.0);
+    assert!(!player.on_ground);
+    assert!(player.position.y > 1.0);
+
+    // A ceiling three blocks up cuts the jump short
+    world.set(Vec3::new(5, 4, 5), BlockStone);
+    let mut player = Player::new(Vec3::new(5.5, 1.0, 5.5));
+    player.on_ground = true;
+    let mut max_height = 0.0f64;
+    for i in range(0, 120) {
+        player.tick(&world, Vec3::zero(), i == 0, 1.0 / 60.0);
+        max_height = max_height.max(player.position.y);
+    }
+    assert!((max_height - (4.0 - PLAYER_HEIGHT)).abs() < 1e-6);
+    assert!((player.position.y - 1.0).abs() < 1e-6);
+}
+
+#[test]
+fn test_sweep_unloaded_is_solid() {
+    let world = TestWorld::new([(0, 0, 0)]);
+    let aabb = Aabb { min: Vec3::new(1.0, 1.0, 1.0), max: Vec3::new(2.0, 2.0, 2.0) };
+    let (moved, blocked) = sweep(&world, &aabb, Vec3::new(0.0, -5.0, 0.0));
+    assert_eq!(moved, Vec3::new(0.0, -1.0, 0.0));
+    assert_eq!(blocked, Vec3 { x: false, y: true, z: false });
+}
@@ -0,0 +1,166 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Just enough of a PNG encoder for screenshots
+//!
+//! Images are 8-bit RGBA with unfiltered rows, compressed as a single
+//! IDAT chunk.
+
+extern crate flate;
+
+static SIGNATURE : [u8, ..8] = [137, 80, 78, 71, 13, 10, 26, 10];
+
+static COLOR_TYPE_RGBA : u8 = 6;
+
+/// Encode RGBA pixels, four bytes each, rows from the top down
+pub fn encode(width: uint, height: uint, rgba: &[u8]) -> ~[u8] {
+    assert_eq!(rgba.len(), width * height * 4);
+
+    let mut ihdr = ~[];
+    push_be_u32(&mut ihdr, width as u32);
+    push_be_u32(&mut ihdr, height as u32);
+    // Bit depth, color type, then default compression, filtering and no
+    // interlacing
+    ihdr.push_all([8, COLOR_TYPE_RGBA, 0, 0, 0]);
+
+    // Every row starts with its filter type, which is always none
+    let mut raw = ~[];
+    for row in rgba.chunks(width * 4) {
+        raw.push(0);
+        raw.push_all(row);
+    }
+
+    let mut png = SIGNATURE.to_owned();
+    push_chunk(&mut png, "IHDR", ihdr);
+    push_chunk(&mut png, "IDAT", zlib(raw));
+    push_chunk(&mut png, "IEND", []);
+    png
+}
+
+/// Reverse the order of the rows, as GL reads them from the bottom up
+pub fn flip_rows(width: uint, rgba: &[u8]) -> ~[u8] {
+    let mut flipped = ~[];
+    for row in rgba.chunks(width * 4).rev() {
+        flipped.push_all(row);
+    }
+    flipped
+}
+
+fn push_be_u32(buf: &mut ~[u8], x: u32) {
+    buf.push_all([(x >> 24) as u8, (x >> 16) as u8, (x >> 8) as u8, x as u8]);
+}
+
+/// Length, type, data and the CRC of the type and data
+fn push_chunk(png: &mut ~[u8], kind: &str, data: &[u8]) {
+    push_be_u32(png, data.len() as u32);
+    let start = png.len();
+    png.push_all(kind.as_bytes());
+    png.push_all(data);
+    let crc = crc32(png.slice_from(start));
+    push_be_u32(png, crc);
+}
+
+/// Wrap raw deflate data in a zlib stream
+fn zlib(data: &[u8]) -> ~[u8] {
+    // Deflate with a 32K window and no preset dictionary. The header check
+    // bits make 0x7801 a multiple of 31.
+    let mut stream = ~[0x78u8, 0x01];
+    stream.push_all(flate::deflate_bytes(data).as_slice());
+    push_be_u32(&mut stream, adler32(data));
+    stream
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffffffffu32;
+    for &byte in data.iter() {
+        crc ^= byte as u32;
+        for _ in range(0, 8) {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb88320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let mut a = 1u32;
+    let mut b = 0u32;
+    for &byte in data.iter() {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+fn read_be_u32(buf: &[u8]) -> u32 {
+    (buf[0] as u32 << 24) | (buf[1] as u32 << 16) | (buf[2] as u32 << 8) | buf[3] as u32
+}
+
+#[test]
+fn test_checksums() {
+    assert_eq!(crc32(bytes!("IEND")), 0xae426082);
+    assert_eq!(adler32(bytes!("Wikipedia")), 0x11e60398);
+}
+
+#[test]
+fn test_png_encode() {
+    // 3x2: red, green, blue over white, black, translucent grey
+    let rgba = ~[255u8, 0, 0, 255,  0, 255, 0, 255,  0, 0, 255, 255,
+                 255, 255, 255, 255,  0, 0, 0, 255,  128, 128, 128, 64];
+    let png = encode(3, 2, rgba);
+
+    assert_eq!(png.slice_to(8), SIGNATURE.as_slice());
+
+    // Walk the chunks, checking each CRC
+    let mut chunks = ~[];
+    let mut i = 8;
+    while i < png.len() {
+        let len = read_be_u32(png.slice_from(i)) as uint;
+        let kind = ::std::str::from_utf8(png.slice(i + 4, i + 8)).unwrap().to_owned();
+        let data = png.slice(i + 8, i + 8 + len).to_owned();
+        assert_eq!(read_be_u32(png.slice_from(i + 8 + len)), crc32(png.slice(i + 4, i + 8 + len)));
+        chunks.push((kind, data));
+        i += 12 + len;
+    }
+    assert_eq!(i, png.len());
+
+    let kinds : ~[&str] = chunks.iter().map(|&(ref kind, _)| kind.as_slice()).collect();
+    assert_eq!(kinds, ~["IHDR", "IDAT", "IEND"]);
+
+    let (_, ref ihdr) = chunks[0];
+    assert_eq!(ihdr, &~[0, 0, 0, 3, 0, 0, 0, 2, 8, 6, 0, 0, 0]);
+
+    // The zlib stream holds each row behind a filter type byte
+    let (_, ref idat) = chunks[1];
+    assert_eq!(idat.slice_to(2), [0x78u8, 0x01].as_slice());
+    assert_eq!((idat[0] as uint * 256 + idat[1] as uint) % 31, 0);
+    let raw = flate::inflate_bytes(idat.slice(2, idat.len() - 4));
+    let mut expected = ~[0u8];
+    expected.push_all(rgba.slice(0, 12));
+    expected.push(0);
+    expected.push_all(rgba.slice(12, 24));
+    assert_eq!(raw.as_slice(), expected.as_slice());
+    assert_eq!(read_be_u32(idat.slice_from(idat.len() - 4)), adler32(expected));
+}
+
+#[test]
+fn test_flip_rows() {
+    let rgba = ~[1u8, 1, 1, 1, 2, 2, 2, 2,
+                 3, 3, 3, 3, 4, 4, 4, 4,
+                 5, 5, 5, 5, 6, 6, 6, 6];
+    assert_eq!(flip_rows(2, rgba),
+               ~[5u8, 5, 5, 5, 6, 6, 6, 6,
+                 3, 3, 3, 3, 4, 4, 4, 4,
+                 1, 1, 1, 1, 2, 2, 2, 2]);
+}
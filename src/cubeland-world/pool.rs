@@ -0,0 +1,447 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::cell::Cell;
+use std::comm::Data;
+use std::i64;
+use std::iter::AdditiveIterator;
+use std::mem;
+use std::u64;
+use std::uint;
+use collections::priority_queue::PriorityQueue;
+
+use sync::Arc;
+use sync::Mutex;
+use time::precise_time_ns;
+
+use metrics::Histogram;
+
+/// Does the jobs taken from a JobPool's queue, one at a time
+pub trait Worker<J, R> {
+    /// Mark the end of each stage of the job on the timer so its latency is
+    /// recorded
+    fn run(&mut self, job: J, timer: &mut StageTimer) -> R;
+}
+
+/// Workers with a queue each, which steal from each other when idle
+///
+/// Each job goes to the queue of the worker with the least to do, and each
+/// worker runs the most urgent job in its own queue. A worker whose queue is
+/// empty steals the most urgent job queued for any other worker, so a slow
+/// job never holds up the ones queued behind it for long. Queued jobs can be
+/// reordered or cancelled until a worker takes them. Lower priorities run
+/// first, and jobs with equal priority run in the order they were submitted.
+pub struct JobPool<J, R> {
+    queues: ~[Arc<Mutex<Queue<J>>>],
+    stats: Arc<Mutex<PoolStats>>,
+    results: Receiver<R>,
+    next_seq: Cell<u64>,
+    /// Jobs submitted whose results have not been received or that have
+    /// not been cancelled
+    outstanding: Cell<uint>,
+}
+
+/// One worker's jobs
+struct Queue<J> {
+    jobs: PriorityQueue<Queued<J>>,
+    /// Whether the worker is running a job
+    running: bool,
+    shutdown: bool,
+}
+
+struct Queued<J> {
+    priority: i64,
+    seq: u64,
+    submitted: u64,
+    job: J,
+}
+
+impl<J: Send, R: Send> JobPool<J, R> {
+    pub fn new(workers: ~[~Worker<J, R>:Send]) -> JobPool<J, R> {
+        let queues : ~[Arc<Mutex<Queue<J>>>] = range(0, workers.len()).map(|_| {
+            Arc::new(Mutex::new(Queue {
+                jobs: PriorityQueue::new(),
+                running: false,
+                shutdown: false,
+            }))
+        }).collect();
+        let stats = Arc::new(Mutex::new(PoolStats::new()));
+        let (results_tx, results_rx) = channel();
+
+        for (index, worker) in workers.move_iter().enumerate() {
+            let queues = queues.clone();
+            let stats = stats.clone();
+            let results = results_tx.clone();
+            spawn(proc() {
+                let mut worker = worker;
+                loop {
+                    let queued = match take_job(queues.as_slice(), index) {
+                        Some(queued) => queued,
+                        None => return,
+                    };
+
+                    let mut timer = StageTimer::new();
+                    let queue_latency = timer.last - queued.submitted;
+                    let result = worker.run(queued.job, &mut timer);
+                    queues[index].lock().running = false;
+
+                    {
+                        let mut stats = stats.lock();
+                        stats.completed += 1;
+                        stats.record("queue", queue_latency);
+                        for &(stage, ns) in timer.stages.iter() {
+                            stats.record(stage, ns);
+                        }
+                    }
+
+                    if results.send_opt(result).is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+
+        JobPool {
+            queues: queues,
+            stats: stats,
+            results: results_rx,
+            next_seq: Cell::new(0),
+            outstanding: Cell::new(0),
+        }
+    }
+
+    pub fn num_workers(&self) -> uint {
+        self.queues.len()
+    }
+
+    pub fn submit(&self, job: J, priority: i64) {
+        let seq = self.next_seq.get();
+        self.next_seq.set(seq + 1);
+
+        // Counting the running job sends new work to idle workers first
+        let mut target = 0;
+        let mut target_load = uint::MAX;
+        for (index, queue) in self.queues.iter().enumerate() {
+            let queue = queue.lock();
+            let load = queue.jobs.len() + if queue.running { 1 } else { 0 };
+            if load < target_load {
+                target = index;
+                target_load = load;
+            }
+        }
+
+        let mut queue = self.queues[target].lock();
+        queue.jobs.push(Queued {
+            priority: priority,
+            seq: seq,
+            submitted: precise_time_ns(),
+            job: job,
+        });
+        queue.cond.signal();
+        self.outstanding.set(self.outstanding.get() + 1);
+    }
+
+    /// Give every queued job a new priority, or cancel it by returning None
+    ///
+    /// Returns the cancelled jobs.
+    pub fn reorder(&self, priority: |&J| -> Option<i64>) -> ~[J] {
+        let mut cancelled = ~[];
+        for queue in self.queues.iter() {
+            let mut queue = queue.lock();
+            let old = mem::replace(&mut queue.jobs, PriorityQueue::new()).to_vec();
+
+            let mut kept = ~[];
+            for queued in old.move_iter() {
+                match priority(&queued.job) {
+                    Some(p) => kept.push(Queued { priority: p, ..queued }),
+                    None => cancelled.push(queued.job),
+                }
+            }
+            queue.jobs = PriorityQueue::from_vec(kept);
+        }
+
+        self.stats.lock().cancelled += cancelled.len();
+        self.outstanding.set(self.outstanding.get() - cancelled.len());
+        cancelled
+    }
+
+    /// A finished job's result, if any are waiting
+    pub fn try_recv(&self) -> Option<R> {
+        match self.results.try_recv() {
+            Data(result) => {
+                self.outstanding.set(self.outstanding.get() - 1);
+                Some(result)
+            },
+            _ => None,
+        }
+    }
+
+    /// Jobs queued, running, or finished with results waiting to be
+    /// received
+    pub fn outstanding(&self) -> uint {
+        self.outstanding.get()
+    }
+
+    /// Counters since the last call, and the current queue depth
+    pub fn take_stats(&self) -> PoolStats {
+        let mut stats = mem::replace(&mut *self.stats.lock(), PoolStats::new());
+        stats.queue_depth = self.queues.iter().map(|queue| queue.lock().jobs.len()).sum();
+        stats
+    }
+}
+
+#[unsafe_destructor]
+impl<J: Send, R: Send> Drop for JobPool<J, R> {
+    fn drop(&mut self) {
+        for queue in self.queues.iter() {
+            let mut queue = queue.lock();
+            queue.shutdown = true;
+            queue.cond.broadcast();
+        }
+    }
+}
+
+/// Block until there is a job to run, or return None once the pool is dropped
+///
+/// Jobs only go to a busy worker's queue when no worker is idle, so a worker
+/// that finds nothing to steal can sleep until a job is queued for it.
+fn take_job<J: Send>(queues: &[Arc<Mutex<Queue<J>>>], index: uint) -> Option<Queued<J>> {
+    loop {
+        {
+            let mut queue = queues[index].lock();
+            if queue.shutdown {
+                return None;
+            }
+            match queue.jobs.maybe_pop() {
+                Some(queued) => {
+                    queue.running = true;
+                    return Some(queued);
+                },
+                None => {},
+            }
+        }
+
+        match steal(queues, index) {
+            Some(queued) => {
+                queues[index].lock().running = true;
+                return Some(queued);
+            },
+            None => {},
+        }
+
+        // Checked again under the lock so a job queued since is not missed
+        let mut queue = queues[index].lock();
+        if !queue.shutdown && queue.jobs.is_empty() {
+            queue.cond.wait();
+        }
+    }
+}
+
+/// Take the most urgent job queued for any other worker
+fn steal<J: Send>(queues: &[Arc<Mutex<Queue<J>>>], index: uint) -> Option<Queued<J>> {
+    loop {
+        let mut victim = None;
+        let mut most_urgent = (i64::MAX, u64::MAX);
+        for (i, queue) in queues.iter().enumerate() {
+            if i == index {
+                continue;
+            }
+            let queue = queue.lock();
+            match queue.jobs.maybe_top() {
+                Some(top) if (top.priority, top.seq) < most_urgent => {
+                    victim = Some(i);
+                    most_urgent = (top.priority, top.seq);
+                },
+                _ => {},
+            }
+        }
+
+        match victim {
+            Some(i) => {
+                match queues[i].lock().jobs.maybe_pop() {
+                    Some(queued) => return Some(queued),
+                    // Its owner got there first, so look again
+                    None => {},
+                }
+            },
+            None => return None,
+        }
+    }
+}
+
+impl<J> Eq for Queued<J> {
+    fn eq(&self, other: &Queued<J>) -> bool {
+        self.priority == other.priority && self.seq == other.seq
+    }
+}
+
+// PriorityQueue pops the greatest element first
+impl<J> Ord for Queued<J> {
+    fn lt(&self, other: &Queued<J>) -> bool {
+        (self.priority, self.seq) > (other.priority, other.seq)
+    }
+}
+
+/// Time spent in each stage of a job, measured between calls to mark
+pub struct StageTimer {
+    last: u64,
+    stages: ~[(&'static str, u64)],
+}
+
+impl StageTimer {
+    fn new() -> StageTimer {
+        StageTimer { last: precise_time_ns(), stages: ~[] }
+    }
+
+    /// End the named stage, which started at the previous mark or when the
+    /// job started
+    pub fn mark(&mut self, stage: &'static str) {
+        let now = precise_time_ns();
+        self.stages.push((stage, now - self.last));
+        self.last = now;
+    }
+}
+
+pub struct PoolStats {
+    /// Jobs waiting for a worker
+    queue_depth: uint,
+    completed: uint,
+    cancelled: uint,
+    /// Microseconds per stage, starting with the time spent queued
+    latencies: ~[(&'static str, Histogram)],
+}
+
+impl PoolStats {
+    fn new() -> PoolStats {
+        PoolStats {
+            queue_depth: 0,
+            completed: 0,
+            cancelled: 0,
+            latencies: ~[],
+        }
+    }
+
+    fn record(&mut self, stage: &'static str, ns: u64) {
+        let index = match self.latencies.iter().position(|&(name, _)| name == stage) {
+            Some(index) => index,
+            None => {
+                self.latencies.push((stage, Histogram::new()));
+                self.latencies.len() - 1
+            },
+        };
+        let (_, ref mut latency) = self.latencies[index];
+        latency.record(ns as f64 / 1000.0);
+    }
+}
+
+#[cfg(test)]
+struct GateWorker {
+    started: Sender<()>,
+    gate: Receiver<()>,
+}
+
+#[cfg(test)]
+impl Worker<int, int> for GateWorker {
+    /// Job 0 waits for the gate to open, the rest finish immediately
+    fn run(&mut self, job: int, timer: &mut StageTimer) -> int {
+        if job == 0 {
+            self.started.send(());
+            self.gate.recv();
+        }
+        timer.mark("work");
+        job
+    }
+}
+
+#[cfg(test)]
+fn wait_result(pool: &JobPool<int, int>) -> int {
+    loop {
+        match pool.try_recv() {
+            Some(result) => return result,
+            None => ::std::task::deschedule(),
+        }
+    }
+}
+
+#[test]
+fn test_pool_priority_and_cancel() {
+    let (started_tx, started_rx) = channel();
+    let (gate_tx, gate_rx) = channel();
+    let pool = JobPool::new(~[~GateWorker { started: started_tx, gate: gate_rx } as ~Worker<int, int>:Send]);
+
+    // Occupy the only worker so the rest of the jobs stay queued
+    pool.submit(0, 0);
+    started_rx.recv();
+
+    for &(job, priority) in [(1, 50), (2, 10), (3, 40), (4, 10), (5, 20), (6, 30)].iter() {
+        pool.submit(job, priority);
+    }
+
+    // Cancel the odd jobs and move job 6 to the front
+    let cancelled = pool.reorder(|&job| {
+        if job % 2 == 1 {
+            None
+        } else if job == 6 {
+            Some(0)
+        } else {
+            Some(job as i64)
+        }
+    });
+    let mut cancelled = cancelled;
+    cancelled.sort();
+    assert_eq!(cancelled, ~[1, 3, 5]);
+    assert_eq!(pool.outstanding(), 4);
+
+    gate_tx.send(());
+    let results : ~[int] = range(0, 4).map(|_| wait_result(&pool)).collect();
+    assert_eq!(results, ~[0, 6, 2, 4]);
+    assert_eq!(pool.outstanding(), 0);
+
+    let stats = pool.take_stats();
+    assert_eq!(stats.queue_depth, 0);
+    assert_eq!(stats.completed, 4);
+    assert_eq!(stats.cancelled, 3);
+    assert_eq!(stats.latencies.iter().map(|&(stage, _)| stage).collect::<~[&'static str]>(),
+               ~["queue", "work"]);
+}
+
+#[test]
+fn test_pool_steal() {
+    let (started_tx, started_rx) = channel();
+    let mut gates = ~[];
+    let workers : ~[~Worker<int, int>:Send] = range(0, 2).map(|_| {
+        let (gate_tx, gate_rx) = channel();
+        gates.push(gate_tx);
+        ~GateWorker { started: started_tx.clone(), gate: gate_rx } as ~Worker<int, int>:Send
+    }).collect();
+    let pool = JobPool::new(workers);
+
+    // Whichever worker takes job 0 is stuck on it, so the jobs that were
+    // queued for it have to be stolen by the other
+    pool.submit(0, 0);
+    started_rx.recv();
+    for job in range(1, 9) {
+        pool.submit(job, job as i64);
+    }
+
+    let mut results : ~[int] = range(0, 8).map(|_| wait_result(&pool)).collect();
+    results.sort();
+    assert_eq!(results, range(1, 9).collect::<~[int]>());
+
+    for gate in gates.iter() {
+        gate.send(());
+    }
+    assert_eq!(wait_result(&pool), 0);
+    assert_eq!(pool.outstanding(), 0);
+}
@@ -0,0 +1,428 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Named-scope frame profiler
+//!
+//! Scopes nest within a frame and are identified by their path from the
+//! root, like "render/shadow". CPU times are taken when a scope ends. GPU
+//! times come back from timer queries frames later and are attached to the
+//! frame they were recorded in. All times are nanoseconds passed in by the
+//! caller, so nothing here needs a clock or a GL context.
+
+use std::num::Saturating;
+
+use collections::{HashMap, HashSet, TreeMap};
+use serialize::json;
+use serialize::json::Json;
+
+/// Finished frames kept for traces
+pub static HISTORY : uint = 300;
+
+/// Samples in each rolling average
+pub static AVERAGE_WINDOW : uint = 60;
+
+/// Mean of the last few samples
+pub struct RollingAverage {
+    priv samples: ~[f64],
+    /// Slot the next sample replaces once the window is full
+    priv next: uint,
+    priv window: uint,
+}
+
+impl RollingAverage {
+    pub fn new(window: uint) -> RollingAverage {
+        assert!(window > 0);
+        RollingAverage { samples: ~[], next: 0, window: window }
+    }
+
+    pub fn add(&mut self, x: f64) {
+        if self.samples.len() < self.window {
+            self.samples.push(x);
+        } else {
+            self.samples[self.next] = x;
+        }
+        self.next = (self.next + 1) % self.window;
+    }
+
+    pub fn mean(&self) -> Option<f64> {
+        if self.samples.is_empty() {
+            None
+        } else {
+            Some(self.samples.iter().fold(0.0, |a, &b| a + b) / self.samples.len() as f64)
+        }
+    }
+}
+
+pub struct Scope {
+    name: ~str,
+    /// Names from the root down, joined with '/'
+    path: ~str,
+    depth: uint,
+    cpu_start: u64,
+    /// Zero until the scope ends
+    cpu_end: u64,
+    /// GPU timestamps, once the queries come back
+    gpu: Option<(u64, u64)>,
+}
+
+pub struct Frame {
+    number: u64,
+    start: u64,
+    end: u64,
+    /// In the order they began, so parents come before their children
+    scopes: ~[Scope],
+}
+
+/// One row of the overlay
+#[deriving(Clone, Show)]
+pub struct ScopeSummary {
+    name: ~str,
+    depth: uint,
+    /// Rolling averages in milliseconds
+    cpu_ms: f64,
+    gpu_ms: Option<f64>,
+}
+
+pub struct Profiler {
+    priv current: Option<Frame>,
+    /// Indices of the scopes begun but not ended, innermost last
+    priv open: ~[uint],
+    /// Oldest first
+    priv frames: ~[Frame],
+    priv next_frame: u64,
+    priv frame_time: RollingAverage,
+    /// Keyed by path
+    priv cpu_times: HashMap<~str, RollingAverage>,
+    priv gpu_times: HashMap<~str, RollingAverage>,
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler {
+            current: None,
+            open: ~[],
+            frames: ~[],
+            next_frame: 0,
+            frame_time: RollingAverage::new(AVERAGE_WINDOW),
+            cpu_times: HashMap::new(),
+            gpu_times: HashMap::new(),
+        }
+    }
+
+    /// Returns the frame number, for attaching GPU times later
+    pub fn begin_frame(&mut self, now: u64) -> u64 {
+        assert!(self.current.is_none(), "profiler frame already begun");
+        let number = self.next_frame;
+        self.next_frame += 1;
+        self.current = Some(Frame { number: number, start: now, end: 0, scopes: ~[] });
+        number
+    }
+
+    pub fn end_frame(&mut self, now: u64) {
+        assert!(self.open.is_empty(), "profiler scopes still open at end of frame");
+        let mut frame = self.current.take().expect("profiler frame not begun");
+        frame.end = now;
+        self.frame_time.add((frame.end - frame.start) as f64);
+        self.frames.push(frame);
+        if self.frames.len() > HISTORY {
+            self.frames.remove(0);
+        }
+    }
+
+    /// Open a scope inside the innermost open one
+    ///
+    /// Returns the scope's index within the frame.
+    pub fn begin(&mut self, name: &str, now: u64) -> uint {
+        let frame = self.current.get_mut_ref();
+        let path = match self.open.last() {
+            Some(&parent) => format!("{}/{}", frame.scopes[parent].path, name),
+            None => name.to_owned(),
+        };
+        frame.scopes.push(Scope {
+            name: name.to_owned(),
+            path: path,
+            depth: self.open.len(),
+            cpu_start: now,
+            cpu_end: 0,
+            gpu: None,
+        });
+        let index = frame.scopes.len() - 1;
+        self.open.push(index);
+        index
+    }
+
+    /// Close the innermost open scope, returning its index
+    pub fn end(&mut self, now: u64) -> uint {
+        let index = self.open.pop().expect("no profiler scope open");
+        let scope = &mut self.current.get_mut_ref().scopes[index];
+        scope.cpu_end = now;
+        add_sample(&mut self.cpu_times, scope.path.as_slice(), (scope.cpu_end - scope.cpu_start) as f64);
+        index
+    }
+
+    /// Attach GPU timestamps to a scope of a finished frame
+    ///
+    /// Frames that have fallen out of the history are ignored.
+    pub fn set_gpu_time(&mut self, frame_number: u64, index: uint, start: u64, end: u64) {
+        let frame = match self.frames.mut_iter().find(|f| f.number == frame_number) {
+            Some(frame) => frame,
+            None => return,
+        };
+        let scope = &mut frame.scopes[index];
+        scope.gpu = Some((start, end));
+        add_sample(&mut self.gpu_times, scope.path.as_slice(), end.saturating_sub(start) as f64);
+    }
+
+    /// Finished frames, oldest first
+    pub fn frames<'a>(&'a self) -> &'a [Frame] {
+        self.frames.as_slice()
+    }
+
+    /// Average frame time in milliseconds
+    pub fn frame_ms(&self) -> Option<f64> {
+        self.frame_time.mean().map(|ns| ns / 1e6)
+    }
+
+    /// Rolling averages for the scopes of the last finished frame, in tree
+    /// order
+    ///
+    /// A scope that ran several times in the frame gets one row, averaged
+    /// over each time it ran.
+    pub fn summary(&self) -> ~[ScopeSummary] {
+        let frame = match self.frames.last() {
+            Some(frame) => frame,
+            None => return ~[],
+        };
+
+        let mut seen = HashSet::new();
+        let mut rows = ~[];
+        for scope in frame.scopes.iter() {
+            if !seen.insert(scope.path.clone()) {
+                continue;
+            }
+            let mean = |times: &HashMap<~str, RollingAverage>| {
+                times.find(&scope.path).and_then(|a| a.mean()).map(|ns| ns / 1e6)
+            };
+            rows.push(ScopeSummary {
+                name: scope.name.clone(),
+                depth: scope.depth,
+                cpu_ms: mean(&self.cpu_times).unwrap_or(0.0),
+                gpu_ms: mean(&self.gpu_times),
+            });
+        }
+        rows
+    }
+
+    /// The history as Chrome trace event JSON, for chrome://tracing
+    ///
+    /// CPU scopes go on one track and GPU scopes on another. The GPU clock
+    /// is unrelated to the CPU's, so each frame's GPU scopes are shifted to
+    /// begin where the frame began on the CPU.
+    pub fn chrome_trace(&self) -> ~str {
+        let origin = match self.frames.head() {
+            Some(frame) => frame.start,
+            None => 0,
+        };
+        let micros = |ns: u64| json::Number((ns - origin) as f64 / 1000.0);
+
+        let mut events = ~[
+            thread_name(CPU_TRACK, "CPU"),
+            thread_name(GPU_TRACK, "GPU"),
+        ];
+
+        for frame in self.frames.iter() {
+            let gpu_origin = frame.scopes.iter().filter_map(|s| s.gpu).map(|(start, _)| start).min();
+
+            for scope in frame.scopes.iter() {
+                events.push(trace_event(scope, CPU_TRACK, micros(scope.cpu_start),
+                                        duration(scope.cpu_start, scope.cpu_end)));
+
+                match (scope.gpu, gpu_origin) {
+                    (Some((start, end)), Some(gpu_origin)) => {
+                        events.push(trace_event(scope, GPU_TRACK,
+                                                micros(frame.start + (start - gpu_origin)),
+                                                duration(start, end)));
+                    },
+                    _ => {},
+                }
+            }
+        }
+
+        let mut root = ~TreeMap::new();
+        root.insert(~"traceEvents", json::List(events));
+        root.insert(~"displayTimeUnit", json::String(~"ms"));
+        json::Object(root).to_str()
+    }
+}
+
+static CPU_TRACK : uint = 0;
+static GPU_TRACK : uint = 1;
+
+fn add_sample(times: &mut HashMap<~str, RollingAverage>, path: &str, x: f64) {
+    times.find_or_insert_with(path.to_owned(), |_| RollingAverage::new(AVERAGE_WINDOW)).add(x);
+}
+
+fn duration(start: u64, end: u64) -> Json {
+    json::Number(end.saturating_sub(start) as f64 / 1000.0)
+}
+
+fn trace_event(scope: &Scope, track: uint, ts: Json, dur: Json) -> Json {
+    let mut event = ~TreeMap::new();
+    event.insert(~"name", json::String(scope.name.clone()));
+    event.insert(~"cat", json::String(if track == GPU_TRACK { ~"gpu" } else { ~"cpu" }));
+    event.insert(~"ph", json::String(~"X"));
+    event.insert(~"pid", json::Number(0.0));
+    event.insert(~"tid", json::Number(track as f64));
+    event.insert(~"ts", ts);
+    event.insert(~"dur", dur);
+    let mut args = ~TreeMap::new();
+    args.insert(~"path", json::String(scope.path.clone()));
+    event.insert(~"args", json::Object(args));
+    json::Object(event)
+}
+
+/// Metadata event labelling a track
+fn thread_name(track: uint, name: &str) -> Json {
+    let mut args = ~TreeMap::new();
+    args.insert(~"name", json::String(name.to_owned()));
+    let mut event = ~TreeMap::new();
+    event.insert(~"name", json::String(~"thread_name"));
+    event.insert(~"ph", json::String(~"M"));
+    event.insert(~"pid", json::Number(0.0));
+    event.insert(~"tid", json::Number(track as f64));
+    event.insert(~"args", json::Object(args));
+    json::Object(event)
+}
+
+/// Two frames of render > (shadow, opaque), 1ms per level
+#[cfg(test)]
+fn test_profiler() -> Profiler {
+    let ms = 1000 * 1000;
+    let mut profiler = Profiler::new();
+    for i in range(0u64, 2) {
+        let t = i * 10 * ms;
+        profiler.begin_frame(t);
+        profiler.begin("render", t);
+        profiler.begin("shadow", t + ms);
+        profiler.end(t + 2 * ms);
+        profiler.begin("opaque", t + 2 * ms);
+        profiler.end(t + (3 + i) * ms);
+        profiler.end(t + 5 * ms);
+        profiler.end_frame(t + 6 * ms);
+    }
+    profiler
+}
+
+#[cfg(test)]
+fn field<'a>(event: &'a Json, key: &str) -> &'a Json {
+    match *event {
+        json::Object(ref map) => map.find(&key.to_owned()).expect(key),
+        _ => fail!("not an object"),
+    }
+}
+
+#[cfg(test)]
+fn number_field(event: &Json, key: &str) -> f64 {
+    match *field(event, key) {
+        json::Number(x) => x,
+        _ => fail!("{} is not a number", key),
+    }
+}
+
+#[test]
+fn test_rolling_average() {
+    let mut avg = RollingAverage::new(3);
+    assert_eq!(avg.mean(), None);
+    avg.add(1.0);
+    avg.add(2.0);
+    assert_eq!(avg.mean(), Some(1.5));
+    avg.add(3.0);
+    avg.add(7.0);
+    assert_eq!(avg.mean(), Some(4.0));
+}
+
+#[test]
+fn test_profiler_scopes() {
+    let profiler = test_profiler();
+    assert_eq!(profiler.frames().len(), 2);
+
+    let paths : ~[~str] = profiler.frames()[1].scopes.iter().map(|s| s.path.clone()).collect();
+    assert_eq!(paths, ~[~"render", ~"render/shadow", ~"render/opaque"]);
+
+    let summary = profiler.summary();
+    let rows : ~[(~str, uint, f64)] = summary.iter().map(|r| (r.name.clone(), r.depth, r.cpu_ms)).collect();
+    assert_eq!(rows, ~[(~"render", 0, 5.0), (~"shadow", 1, 1.0), (~"opaque", 1, 1.5)]);
+    assert!(summary.iter().all(|r| r.gpu_ms.is_none()));
+    assert_eq!(profiler.frame_ms(), Some(6.0));
+}
+
+#[test]
+fn test_profiler_repeated_scopes() {
+    let mut profiler = Profiler::new();
+    profiler.begin_frame(0);
+    for i in range(0u64, 3) {
+        profiler.begin("cascade", i * 100);
+        profiler.end(i * 100 + 10 * (i + 1));
+    }
+    profiler.end_frame(1000);
+
+    let summary = profiler.summary();
+    assert_eq!(summary.len(), 1);
+    assert_eq!(summary[0].cpu_ms, 20.0 / 1e6);
+}
+
+#[test]
+fn test_profiler_gpu_times() {
+    let mut profiler = test_profiler();
+    profiler.set_gpu_time(1, 1, 5000, 7000);
+    profiler.set_gpu_time(0, 1, 1000, 2000);
+    // No such frame
+    profiler.set_gpu_time(9, 1, 0, 1000);
+
+    assert_eq!(profiler.frames()[1].scopes[1].gpu, Some((5000, 7000)));
+    assert_eq!(profiler.frames()[1].scopes[2].gpu, None);
+
+    let summary = profiler.summary();
+    assert_eq!(summary[1].gpu_ms, Some(1500.0 / 1e6));
+    assert_eq!(summary[0].gpu_ms, None);
+}
+
+#[test]
+fn test_profiler_chrome_trace() {
+    let mut profiler = test_profiler();
+    profiler.set_gpu_time(1, 0, 50000, 90000);
+    profiler.set_gpu_time(1, 2, 70000, 80000);
+
+    let trace = json::from_str(profiler.chrome_trace()).unwrap();
+    let events = match *field(&trace, "traceEvents") {
+        json::List(ref events) => events.clone(),
+        _ => fail!("traceEvents is not a list"),
+    };
+
+    // Two track names, three CPU scopes per frame and two GPU scopes
+    assert_eq!(events.len(), 2 + 6 + 2);
+
+    let complete : ~[&Json] = events.iter().filter(|e| *field(*e, "ph") == json::String(~"X")).collect();
+    let cpu : ~[(f64, f64)] = complete.iter().
+        filter(|e| number_field(**e, "tid") == CPU_TRACK as f64).
+        map(|e| (number_field(*e, "ts"), number_field(*e, "dur"))).collect();
+    assert_eq!(cpu, ~[(0.0, 5000.0), (1000.0, 1000.0), (2000.0, 1000.0),
+                      (10000.0, 5000.0), (11000.0, 1000.0), (12000.0, 2000.0)]);
+
+    // GPU scopes keep their spacing but start with the frame
+    let gpu : ~[(f64, f64)] = complete.iter().
+        filter(|e| number_field(**e, "tid") == GPU_TRACK as f64).
+        map(|e| (number_field(*e, "ts"), number_field(*e, "dur"))).collect();
+    assert_eq!(gpu, ~[(10000.0, 40.0), (10020.0, 10.0)]);
+}
@@ -0,0 +1,261 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate cgmath;
+
+use std::io;
+use std::io::{BufReader, IoError, IoResult, MemWriter};
+use std::str;
+
+use cgmath::vector::Vec3;
+
+use region::{encode_chunk, decode_chunk};
+use terrain::{BlockType, Terrain};
+
+/// Bumped whenever the encoding of any message changes
+pub static PROTOCOL_VERSION : u32 = 1;
+
+/// Larger frames are rejected before their body is read
+static MAX_FRAME_SIZE : uint = 1024 * 1024;
+
+static TAG_HELLO : u8 = 1;
+static TAG_WELCOME : u8 = 2;
+static TAG_REJECTED : u8 = 3;
+static TAG_REQUEST_CHUNK : u8 = 4;
+static TAG_CHUNK_DATA : u8 = 5;
+static TAG_SET_BLOCK : u8 = 6;
+static TAG_PLAYER_POSITION : u8 = 7;
+static TAG_PLAYER_LEFT : u8 = 8;
+
+/// Everything sent between the server and its clients
+///
+/// On the wire each message is a frame:
+///
+///   length u32 | tag u8 | body
+///
+/// where length covers the tag and body. All integers are big endian. A
+/// connection starts with the client sending Hello and the server answering
+/// Welcome or Rejected.
+#[deriving(Clone)]
+pub enum Message {
+    /// Client's protocol version
+    Hello(u32),
+    /// Player id assigned to the client
+    Welcome(u32),
+    /// Reason the server refused the connection
+    Rejected(~str),
+    RequestChunk(Vec3<i64>),
+    /// Terrain including the padding copied from neighbors, compressed the
+    /// same way as in region files
+    ChunkData(Vec3<i64>, ~Terrain),
+    /// Sent by a client to edit the world, and by the server to every client
+    /// once the edit is applied
+    SetBlock(Vec3<i64>, BlockType),
+    /// The player id is ignored when sent by a client
+    PlayerPosition(u32, Vec3<f64>),
+    PlayerLeft(u32),
+}
+
+pub fn write_message(w: &mut Writer, msg: &Message) -> IoResult<()> {
+    let mut body = MemWriter::new();
+    match *msg {
+        Hello(version) => {
+            try!(body.write_u8(TAG_HELLO));
+            try!(body.write_be_u32(version));
+        },
+        Welcome(player_id) => {
+            try!(body.write_u8(TAG_WELCOME));
+            try!(body.write_be_u32(player_id));
+        },
+        Rejected(ref reason) => {
+            try!(body.write_u8(TAG_REJECTED));
+            try!(body.write_be_u32(reason.len() as u32));
+            try!(body.write_str(reason.as_slice()));
+        },
+        RequestChunk(c) => {
+            try!(body.write_u8(TAG_REQUEST_CHUNK));
+            try!(write_coord(&mut body, c));
+        },
+        ChunkData(c, ref terrain) => {
+            let record = encode_chunk(&**terrain);
+            try!(body.write_u8(TAG_CHUNK_DATA));
+            try!(write_coord(&mut body, c));
+            try!(body.write_be_u32(record.len() as u32));
+            try!(body.write(record));
+        },
+        SetBlock(p, blocktype) => {
+            try!(body.write_u8(TAG_SET_BLOCK));
+            try!(write_coord(&mut body, p));
+            try!(body.write_u8(blocktype));
+        },
+        PlayerPosition(player_id, p) => {
+            try!(body.write_u8(TAG_PLAYER_POSITION));
+            try!(body.write_be_u32(player_id));
+            try!(body.write_be_f64(p.x));
+            try!(body.write_be_f64(p.y));
+            try!(body.write_be_f64(p.z));
+        },
+        PlayerLeft(player_id) => {
+            try!(body.write_u8(TAG_PLAYER_LEFT));
+            try!(body.write_be_u32(player_id));
+        },
+    }
+
+    let body = body.unwrap();
+    try!(w.write_be_u32(body.len() as u32));
+    try!(w.write(body));
+    w.flush()
+}
+
+/// Blocks until a whole message has arrived
+pub fn read_message(r: &mut Reader) -> IoResult<Message> {
+    let length = try!(r.read_be_u32()) as uint;
+    if length == 0 || length > MAX_FRAME_SIZE {
+        return Err(invalid("bad frame length"));
+    }
+
+    let frame = try!(r.read_bytes(length));
+    let mut body = BufReader::new(frame.slice_from(1));
+
+    let msg = match frame[0] {
+        TAG_HELLO => Hello(try!(body.read_be_u32())),
+        TAG_WELCOME => Welcome(try!(body.read_be_u32())),
+        TAG_REJECTED => {
+            let len = try!(body.read_be_u32()) as uint;
+            let bytes = try!(body.read_bytes(len));
+            match str::from_utf8_owned(bytes) {
+                Some(reason) => Rejected(reason),
+                None => return Err(invalid("rejection reason is not UTF-8")),
+            }
+        },
+        TAG_REQUEST_CHUNK => RequestChunk(try!(read_coord(&mut body))),
+        TAG_CHUNK_DATA => {
+            let c = try!(read_coord(&mut body));
+            let len = try!(body.read_be_u32()) as uint;
+            let record = try!(body.read_bytes(len));
+            ChunkData(c, try!(decode_chunk(record)))
+        },
+        TAG_SET_BLOCK => {
+            let p = try!(read_coord(&mut body));
+            SetBlock(p, try!(body.read_u8()))
+        },
+        TAG_PLAYER_POSITION => {
+            let player_id = try!(body.read_be_u32());
+            let x = try!(body.read_be_f64());
+            let y = try!(body.read_be_f64());
+            let z = try!(body.read_be_f64());
+            PlayerPosition(player_id, Vec3::new(x, y, z))
+        },
+        TAG_PLAYER_LEFT => PlayerLeft(try!(body.read_be_u32())),
+        _ => return Err(invalid("unknown message tag")),
+    };
+
+    if !body.eof() {
+        return Err(invalid("trailing bytes after message"));
+    }
+
+    Ok(msg)
+}
+
+fn write_coord(w: &mut Writer, c: Vec3<i64>) -> IoResult<()> {
+    try!(w.write_be_i64(c.x));
+    try!(w.write_be_i64(c.y));
+    w.write_be_i64(c.z)
+}
+
+fn read_coord(r: &mut Reader) -> IoResult<Vec3<i64>> {
+    let x = try!(r.read_be_i64());
+    let y = try!(r.read_be_i64());
+    let z = try!(r.read_be_i64());
+    Ok(Vec3::new(x, y, z))
+}
+
+fn invalid(desc: &'static str) -> IoError {
+    IoError {
+        kind: io::InvalidInput,
+        desc: desc,
+        detail: None,
+    }
+}
+
+#[cfg(test)]
+fn round_trip(msg: &Message) -> Message {
+    let mut w = MemWriter::new();
+    write_message(&mut w, msg).unwrap();
+    let bytes = w.unwrap();
+    let mut r = BufReader::new(bytes.as_slice());
+    let out = read_message(&mut r).unwrap();
+    assert!(r.eof());
+    out
+}
+
+#[test]
+fn test_message_round_trip() {
+    use terrain::BlockStone;
+
+    match round_trip(&Hello(PROTOCOL_VERSION)) {
+        Hello(v) => assert_eq!(v, PROTOCOL_VERSION),
+        _ => fail!(),
+    }
+
+    match round_trip(&Rejected(~"wrong version")) {
+        Rejected(reason) => assert_eq!(reason, ~"wrong version"),
+        _ => fail!(),
+    }
+
+    match round_trip(&SetBlock(Vec3::new(-5, 70, 1 << 40), BlockStone)) {
+        SetBlock(p, blocktype) => {
+            assert_eq!(p, Vec3::new(-5, 70, 1 << 40));
+            assert_eq!(blocktype, BlockStone);
+        },
+        _ => fail!(),
+    }
+
+    match round_trip(&PlayerPosition(3, Vec3::new(1.5, -2.25, 1e9))) {
+        PlayerPosition(id, p) => {
+            assert_eq!(id, 3);
+            assert_eq!(p, Vec3::new(1.5, -2.25, 1e9));
+        },
+        _ => fail!(),
+    }
+
+    let mut t = Terrain::new();
+    t.set(-1, 5, 32, BlockStone);
+    match round_trip(&ChunkData(Vec3::new(1, -2, 3), t)) {
+        ChunkData(c, t) => {
+            assert_eq!(c, Vec3::new(1, -2, 3));
+            assert_eq!(t.get(-1, 5, 32).blocktype, BlockStone);
+            assert_eq!(t.get(0, 5, 32).blocktype, 0);
+        },
+        _ => fail!(),
+    }
+}
+
+#[test]
+fn test_message_invalid() {
+    // Unknown tag
+    let bytes = [0u8, 0, 0, 1, 99];
+    let mut r = BufReader::new(bytes.as_slice());
+    assert!(read_message(&mut r).is_err());
+
+    // Truncated body
+    let bytes = [0u8, 0, 0, 3, TAG_HELLO, 0, 0];
+    let mut r = BufReader::new(bytes.as_slice());
+    assert!(read_message(&mut r).is_err());
+
+    // Frame too large
+    let bytes = [0xffu8, 0xff, 0xff, 0xff];
+    let mut r = BufReader::new(bytes.as_slice());
+    assert!(read_message(&mut r).is_err());
+}
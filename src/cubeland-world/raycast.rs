@@ -0,0 +1,147 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate cgmath;
+
+use std;
+
+use cgmath::vector::EuclideanVector;
+use cgmath::vector::Vec3;
+
+use chunk::BlockSource;
+#[cfg(test)]
+use chunk::TestWorld;
+#[cfg(test)]
+use terrain::{BlockStone, BlockDirt};
+
+#[deriving(Eq, Show)]
+pub struct RaycastHit {
+    /// World position of the block that was hit
+    block: Vec3<i64>,
+    /// Index into mesh::faces of the face the ray entered through
+    face: uint,
+    /// Distance from the ray origin to the point of entry
+    distance: f64,
+}
+
+/// Find the first opaque block along a ray
+///
+/// Uses the voxel traversal from Amanatides and Woo, "A Fast Voxel Traversal
+/// Algorithm for Ray Tracing". The block containing the origin is skipped.
+/// The search gives up at max_distance or when it reaches a chunk that is not
+/// loaded.
+pub fn raycast<S: BlockSource>(source: &S,
+                               origin: Vec3<f64>,
+                               direction: Vec3<f64>,
+                               max_distance: f64) -> Option<RaycastHit> {
+    let d = direction.normalize();
+
+    let mut block = Vec3::new(origin.x.floor() as i64,
+                              origin.y.floor() as i64,
+                              origin.z.floor() as i64);
+
+    let (step_x, mut t_max_x, t_delta_x) = axis_setup(origin.x, d.x);
+    let (step_y, mut t_max_y, t_delta_y) = axis_setup(origin.y, d.y);
+    let (step_z, mut t_max_z, t_delta_z) = axis_setup(origin.z, d.z);
+
+    loop {
+        let (t, face) = if t_max_x <= t_max_y && t_max_x <= t_max_z {
+            block.x += step_x;
+            let t = t_max_x;
+            t_max_x += t_delta_x;
+            (t, if step_x > 0 { 3 } else { 2 })
+        } else if t_max_y <= t_max_z {
+            block.y += step_y;
+            let t = t_max_y;
+            t_max_y += t_delta_y;
+            (t, if step_y > 0 { 5 } else { 4 })
+        } else {
+            block.z += step_z;
+            let t = t_max_z;
+            t_max_z += t_delta_z;
+            (t, if step_z > 0 { 1 } else { 0 })
+        };
+
+        if t > max_distance {
+            return None;
+        }
+
+        match source.get_block(block) {
+            None => return None,
+            Some(b) => {
+                if source.registry().is_opaque(b.blocktype) {
+                    return Some(RaycastHit { block: block, face: face, distance: t });
+                }
+            },
+        }
+    }
+}
+
+/// Returns the step direction, the distance to the first block boundary and
+/// the distance between block boundaries along one axis
+fn axis_setup(o: f64, d: f64) -> (i64, f64, f64) {
+    if d > 0.0 {
+        (1, (o.floor() + 1.0 - o) / d, 1.0 / d)
+    } else if d < 0.0 {
+        (-1, (o - o.floor()) / -d, 1.0 / -d)
+    } else {
+        (0, std::f64::INFINITY, std::f64::INFINITY)
+    }
+}
+
+#[test]
+fn test_raycast_down() {
+    let mut world = TestWorld::new([(0, 0, 0)]);
+    world.set(Vec3::new(0, 0, 0), BlockStone);
+
+    let hit = raycast(&world, Vec3::new(0.5, 10.5, 0.5), Vec3::new(0.0, -1.0, 0.0), 100.0);
+    assert_eq!(hit, Some(RaycastHit { block: Vec3::new(0, 0, 0), face: 4, distance: 9.5 }));
+}
+
+#[test]
+fn test_raycast_across_chunks() {
+    let mut world = TestWorld::new([(0, 0, 0), (1, 0, 0), (0, 0, -1)]);
+    world.set(Vec3::new(40, 5, 5), BlockStone);
+    world.set(Vec3::new(1, 1, -5), BlockDirt);
+
+    let hit = raycast(&world, Vec3::new(30.5, 5.5, 5.5), Vec3::new(1.0, 0.0, 0.0), 100.0);
+    assert_eq!(hit, Some(RaycastHit { block: Vec3::new(40, 5, 5), face: 3, distance: 9.5 }));
+
+    let hit = raycast(&world, Vec3::new(1.5, 1.5, 1.5), Vec3::new(0.0, 0.0, -1.0), 100.0);
+    assert_eq!(hit, Some(RaycastHit { block: Vec3::new(1, 1, -5), face: 0, distance: 5.5 }));
+}
+
+#[test]
+fn test_raycast_diagonal() {
+    let mut world = TestWorld::new([(0, 0, 0)]);
+    world.set(Vec3::new(3, 3, 0), BlockStone);
+
+    // Enters (3, 2, 0) through its left face, then (3, 3, 0) through its bottom face
+    let hit = raycast(&world, Vec3::new(0.5, 0.25, 0.5), Vec3::new(1.0, 1.0, 0.0), 100.0).unwrap();
+    assert_eq!(hit.block, Vec3::new(3, 3, 0));
+    assert_eq!(hit.face, 5);
+    assert!((hit.distance - 2.75 * std::f64::consts::SQRT2).abs() < 1e-9);
+}
+
+#[test]
+fn test_raycast_miss() {
+    let mut world = TestWorld::new([(0, 0, 0)]);
+    world.set(Vec3::new(20, 5, 5), BlockStone);
+
+    // Too far away
+    assert_eq!(raycast(&world, Vec3::new(5.5, 5.5, 5.5), Vec3::new(1.0, 0.0, 0.0), 10.0), None);
+
+    // The ray stops at the edge of the loaded chunks
+    assert_eq!(raycast(&world, Vec3::new(5.5, 5.5, 5.5), Vec3::new(0.0, 0.0, 1.0), 100.0), None);
+}
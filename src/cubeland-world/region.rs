@@ -0,0 +1,373 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate cgmath;
+extern crate flate;
+
+use std::io;
+use std::io::fs;
+use std::io::{File, IoError, IoResult};
+use std::io::{Open, Truncate, ReadWrite, SeekSet, SeekEnd};
+use std::num::Integer;
+
+use cgmath::vector::Vec3;
+
+use CHUNK_SIZE;
+use lru::LruCache;
+use terrain::Terrain;
+
+/// Number of chunks along each axis of a region
+pub static REGION_SIZE : i64 = 16;
+
+/// Version of the region file header layout
+pub static REGION_VERSION : u32 = 1;
+
+static REGION_MAGIC : &'static [u8] = bytes!("CBRG");
+static REGION_CHUNKS : uint = (REGION_SIZE * REGION_SIZE * REGION_SIZE) as uint;
+static HEADER_SIZE : uint = 8;
+static OFFSET_ENTRY_SIZE : uint = 8;
+
+/// Region files kept open at once. Saves are written through, so closing
+/// the least recently used one loses nothing.
+static MAX_OPEN_REGIONS : uint = 32;
+
+// Every chunk record starts with a format byte so that chunks written with an
+// older encoding keep loading after a new one is introduced.
+static CHUNK_FORMAT_DEFLATE : u8 = 1;
+
+static TERRAIN_BYTES : uint = ((CHUNK_SIZE+2) * (CHUNK_SIZE+2) * (CHUNK_SIZE+2)) as uint;
+
+/// Persistent chunk storage
+///
+/// Chunks are grouped into regions of REGION_SIZE^3 chunks, one file per
+/// region. A region file is laid out as:
+///
+///   magic "CBRG" | version u32 | REGION_CHUNKS x (offset u32, length u32) | records
+///
+/// All integers are big endian. A zero length means the chunk was never
+/// saved. A record that no longer fits in its old slot is appended to the end
+/// of the file and the old space is abandoned.
+pub struct RegionStore {
+    dir: Path,
+    regions: LruCache<(i64, i64, i64), Region>,
+}
+
+impl RegionStore {
+    pub fn new(dir: Path) -> IoResult<RegionStore> {
+        if !dir.exists() {
+            try!(fs::mkdir_recursive(&dir, io::UserRWX));
+        }
+
+        Ok(RegionStore {
+            dir: dir,
+            regions: LruCache::new(MAX_OPEN_REGIONS),
+        })
+    }
+
+    pub fn load(&mut self, coord: Vec3<i64>) -> IoResult<Option<~Terrain>> {
+        let (region_coord, index) = region_index(coord);
+        let region = try!(self.region(region_coord));
+        region.load(index)
+    }
+
+    pub fn save(&mut self, coord: Vec3<i64>, terrain: &Terrain) -> IoResult<()> {
+        let (region_coord, index) = region_index(coord);
+        let region = try!(self.region(region_coord));
+        region.save(index, terrain)
+    }
+
+    fn region<'a>(&'a mut self, c: Vec3<i64>) -> IoResult<&'a mut Region> {
+        let key = (c.x, c.y, c.z);
+        if self.regions.contains_key(&key) {
+            self.regions.touch(&key);
+        } else {
+            let path = self.dir.join(format!("r.{}.{}.{}.cbr", c.x, c.y, c.z));
+            let region = try!(Region::open(&path));
+            self.regions.insert(key, region);
+            // Dropping a region closes its file
+            self.regions.evict(|_, _| {});
+        }
+        Ok(self.regions.find_mut(&key).unwrap())
+    }
+}
+
+struct Region {
+    file: File,
+    offsets: ~[(u32, u32)],
+}
+
+impl Region {
+    fn open(path: &Path) -> IoResult<Region> {
+        if path.exists() {
+            let mut file = try!(File::open_mode(path, Open, ReadWrite));
+
+            let magic = try!(file.read_bytes(REGION_MAGIC.len()));
+            if magic.as_slice() != REGION_MAGIC {
+                return Err(invalid("not a region file"));
+            }
+
+            let version = try!(file.read_be_u32());
+            if version > REGION_VERSION {
+                return Err(invalid("region file is from a newer version"));
+            }
+
+            let mut offsets = ~[];
+            for _ in range(0, REGION_CHUNKS) {
+                let offset = try!(file.read_be_u32());
+                let length = try!(file.read_be_u32());
+                offsets.push((offset, length));
+            }
+
+            Ok(Region { file: file, offsets: offsets })
+        } else {
+            let mut file = try!(File::open_mode(path, Truncate, ReadWrite));
+            try!(file.write(REGION_MAGIC));
+            try!(file.write_be_u32(REGION_VERSION));
+            for _ in range(0, REGION_CHUNKS) {
+                try!(file.write_be_u32(0));
+                try!(file.write_be_u32(0));
+            }
+            try!(file.flush());
+
+            Ok(Region { file: file, offsets: ~[(0, 0), ..REGION_CHUNKS] })
+        }
+    }
+
+    fn load(&mut self, index: uint) -> IoResult<Option<~Terrain>> {
+        let (offset, length) = self.offsets[index];
+        if length == 0 {
+            return Ok(None);
+        }
+
+        try!(self.file.seek(offset as i64, SeekSet));
+        let record = try!(self.file.read_bytes(length as uint));
+        decode_chunk(record).map(|t| Some(t))
+    }
+
+    fn save(&mut self, index: uint, terrain: &Terrain) -> IoResult<()> {
+        let record = encode_chunk(terrain);
+        let length = record.len() as u32;
+
+        let (old_offset, old_length) = self.offsets[index];
+        let offset = if old_length != 0 && length <= old_length {
+            old_offset
+        } else {
+            try!(self.file.seek(0, SeekEnd));
+            try!(self.file.tell()) as u32
+        };
+
+        try!(self.file.seek(offset as i64, SeekSet));
+        try!(self.file.write(record));
+
+        let entry_offset = HEADER_SIZE + index * OFFSET_ENTRY_SIZE;
+        try!(self.file.seek(entry_offset as i64, SeekSet));
+        try!(self.file.write_be_u32(offset));
+        try!(self.file.write_be_u32(length));
+        try!(self.file.flush());
+
+        self.offsets[index] = (offset, length);
+        Ok(())
+    }
+}
+
+/// Returns the region containing a chunk and the chunk's index within it
+fn region_index(c: Vec3<i64>) -> (Vec3<i64>, uint) {
+    let region_coord = Vec3::new(c.x.div_floor(&REGION_SIZE),
+                                 c.y.div_floor(&REGION_SIZE),
+                                 c.z.div_floor(&REGION_SIZE));
+    let x = c.x.mod_floor(&REGION_SIZE);
+    let y = c.y.mod_floor(&REGION_SIZE);
+    let z = c.z.mod_floor(&REGION_SIZE);
+    (region_coord, ((x * REGION_SIZE + y) * REGION_SIZE + z) as uint)
+}
+
+/// Compress a chunk into a self-describing record
+///
+/// The same records are stored in region files and streamed by the server.
+pub fn encode_chunk(terrain: &Terrain) -> ~[u8] {
+    let mut raw = ~[];
+    raw.reserve(TERRAIN_BYTES);
+    for x in range(-1, CHUNK_SIZE+1) {
+        for y in range(-1, CHUNK_SIZE+1) {
+            for z in range(-1, CHUNK_SIZE+1) {
+                raw.push(terrain.get(x, y, z).blocktype);
+            }
+        }
+    }
+
+    let compressed = flate::deflate_bytes(raw.as_slice());
+
+    let mut record = ~[CHUNK_FORMAT_DEFLATE];
+    record.push_all(compressed.as_slice());
+    record
+}
+
+pub fn decode_chunk(record: &[u8]) -> IoResult<~Terrain> {
+    if record.is_empty() {
+        return Err(invalid("empty chunk record"));
+    }
+
+    if record[0] == CHUNK_FORMAT_DEFLATE {
+        let raw = flate::inflate_bytes(record.slice_from(1));
+        decode_blocks(raw.as_slice())
+    } else {
+        Err(invalid("unknown chunk record format"))
+    }
+}
+
+fn decode_blocks(raw: &[u8]) -> IoResult<~Terrain> {
+    if raw.len() != TERRAIN_BYTES {
+        return Err(invalid("chunk record has the wrong size"));
+    }
+
+    Ok(Terrain::from_blocks(raw))
+}
+
+fn invalid(desc: &'static str) -> IoError {
+    IoError {
+        kind: io::InvalidInput,
+        desc: desc,
+        detail: None,
+    }
+}
+
+#[cfg(test)]
+fn assert_terrain_eq(a: &Terrain, b: &Terrain) {
+    for x in range(-1, CHUNK_SIZE+1) {
+        for y in range(-1, CHUNK_SIZE+1) {
+            for z in range(-1, CHUNK_SIZE+1) {
+                assert!(a.get(x, y, z).blocktype == b.get(x, y, z).blocktype);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_region_round_trip() {
+    use std::io::TempDir;
+    use cgmath::vector::Vector;
+    use terrain::TerrainGenerator;
+    use registry::BlockRegistry;
+    use config::WorldConfig;
+
+    let dir = TempDir::new("cubeland-region").unwrap();
+    let registry = BlockRegistry::builtin();
+    let terrain_generator = TerrainGenerator::new(&WorldConfig::default(), &registry);
+    let coords = [Vec3::new(0i64, 0, 0), Vec3::new(-1, -1, -1),
+                  Vec3::new(15, 0, -16), Vec3::new(16, -1, 7)];
+    let gen = |c: Vec3<i64>| {
+        let p = Vec3::new(c.x as f64, c.y as f64, c.z as f64).mul_s(CHUNK_SIZE as f64);
+        terrain_generator.gen(p)
+    };
+
+    {
+        let mut store = RegionStore::new(dir.path().clone()).unwrap();
+        for &c in coords.iter() {
+            store.save(c, gen(c)).unwrap();
+        }
+    }
+
+    let mut store = RegionStore::new(dir.path().clone()).unwrap();
+    for &c in coords.iter() {
+        let t = store.load(c).unwrap().expect("chunk was not saved");
+        assert_terrain_eq(gen(c), t);
+    }
+
+    assert!(store.load(Vec3::new(1, 1, 1)).unwrap().is_none());
+}
+
+#[test]
+fn test_region_overwrite() {
+    use std::io::TempDir;
+    use terrain::{BlockAir, BlockStone, BlockWater};
+
+    let dir = TempDir::new("cubeland-region").unwrap();
+    let mut store = RegionStore::new(dir.path().clone()).unwrap();
+    let c = Vec3::new(3i64, -2, 5);
+
+    // Uniform terrain compresses well, so the second save has to grow the record
+    let mut t = Terrain::new();
+    store.save(c, t).unwrap();
+
+    for x in range(-1, CHUNK_SIZE+1) {
+        for y in range(-1, CHUNK_SIZE+1) {
+            for z in range(-1, CHUNK_SIZE+1) {
+                t.set(x, y, z, match (x * 7 + y * 3 + z) % 3 {
+                    0 => BlockAir,
+                    1 => BlockStone,
+                    _ => BlockWater,
+                });
+            }
+        }
+    }
+    store.save(c, t).unwrap();
+    assert_terrain_eq(t, store.load(c).unwrap().unwrap());
+
+    let t = Terrain::new();
+    store.save(c, t).unwrap();
+    assert_terrain_eq(t, store.load(c).unwrap().unwrap());
+}
+
+#[test]
+fn test_region_uniform() {
+    use std::io::TempDir;
+    use terrain::{BlockAir, BlockStone};
+
+    let dir = TempDir::new("cubeland-region").unwrap();
+    let mut store = RegionStore::new(dir.path().clone()).unwrap();
+    let c = Vec3::new(0i64, 0, 0);
+
+    // Uniform chunks come back uniform rather than packed
+    store.save(c, Terrain::new()).unwrap();
+    assert_eq!(store.load(c).unwrap().unwrap().uniform_type(), Some(BlockAir));
+
+    let mut t = Terrain::new();
+    t.set(0, 0, 0, BlockStone);
+    store.save(c, t).unwrap();
+    let loaded = store.load(c).unwrap().unwrap();
+    assert_eq!(loaded.uniform_type(), None);
+    assert_terrain_eq(t, loaded);
+}
+
+#[test]
+fn test_region_open_files() {
+    use std::io::TempDir;
+    use terrain::BlockStone;
+
+    let dir = TempDir::new("cubeland-region").unwrap();
+    let mut store = RegionStore::new(dir.path().clone()).unwrap();
+    let mut t = Terrain::new();
+    t.set(0, 0, 0, BlockStone);
+
+    let coords : ~[Vec3<i64>] = range(0, MAX_OPEN_REGIONS as i64 * 2).map(|i| {
+        Vec3::new(i * REGION_SIZE, 0, 0)
+    }).collect();
+    for &c in coords.iter() {
+        store.save(c, t).unwrap();
+        assert!(store.regions.len() <= MAX_OPEN_REGIONS);
+    }
+
+    // Closed regions are reopened from disk
+    for &c in coords.iter() {
+        assert_terrain_eq(t, store.load(c).unwrap().unwrap());
+    }
+}
+
+#[test]
+fn test_region_index() {
+    assert_eq!(region_index(Vec3::new(0, 0, 0)), (Vec3::new(0, 0, 0), 0));
+    assert_eq!(region_index(Vec3::new(0, 0, 1)), (Vec3::new(0, 0, 0), 1));
+    assert_eq!(region_index(Vec3::new(-1, -1, -1)), (Vec3::new(-1, -1, -1), REGION_CHUNKS - 1));
+    assert_eq!(region_index(Vec3::new(16, -17, 15)), (Vec3::new(1, -2, 0), (15 * 16 + 15) as uint));
+}
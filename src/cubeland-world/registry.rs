@@ -0,0 +1,316 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate serialize;
+
+use std;
+
+use serialize::json;
+use serialize::json::Json;
+
+use config::to_uint;
+use terrain::BlockType;
+
+/// Block ids must be below this. Keep in sync with shaders/main.vs.glsl.
+pub static MAX_BLOCK_TYPES : uint = 32;
+
+static BUILTIN_BLOCKS : &'static str = include_str!("../../data/blocks.json");
+
+#[deriving(Eq, Show)]
+pub enum TransparencyClass {
+    /// Never meshed
+    TransparencyInvisible,
+    /// Drawn in the opaque pass
+    TransparencySolid,
+    /// Drawn in the opaque pass but does not hide its neighbors
+    TransparencyCutout,
+    /// Alpha blended
+    TransparencyTranslucent,
+}
+
+/// Places the block in veins where 3D noise exceeds a threshold
+pub struct OreParams {
+    replace: BlockType,
+    frequency: f64,
+    threshold: f64,
+    max_height: f64,
+}
+
+pub struct BlockInfo {
+    id: BlockType,
+    name: ~str,
+    /// Hides the faces of neighboring blocks and stops movement
+    opaque: bool,
+    transparency: TransparencyClass,
+    color: [f32, ..4],
+    /// Scales of the two noise texture lookups and the mix between them
+    texture: [f32, ..3],
+    /// Emitted light level, 0 to 15
+    light: u8,
+    ore: Option<OreParams>,
+}
+
+/// Properties of every block type, loaded from data/blocks.json
+///
+/// The terrain generator places the block ids defined in terrain.rs (air,
+/// grass, stone, ...) directly, so the file must keep those ids. Any other
+/// block can be added, including ores that the generator places from their
+/// "ore" parameters. Unknown ids are treated as opaque.
+pub struct BlockRegistry {
+    /// In the order they appear in the file
+    blocks: ~[BlockInfo],
+    /// Index into blocks for each id
+    by_id: ~[Option<uint>],
+    opaque: [bool, ..256],
+}
+
+impl BlockRegistry {
+    pub fn load(path: &Path) -> Result<BlockRegistry, ~str> {
+        let src = match std::io::File::open(path).read_to_end() {
+            Ok(src) => src,
+            Err(e) => return Err(format!("{}: {}", path.display(), e)),
+        };
+
+        match std::str::from_utf8(src) {
+            Some(s) => BlockRegistry::from_json_str(s),
+            None => Err(format!("{}: invalid UTF-8", path.display())),
+        }
+    }
+
+    /// The copy of data/blocks.json compiled into the binary
+    pub fn builtin() -> BlockRegistry {
+        match BlockRegistry::from_json_str(BUILTIN_BLOCKS) {
+            Ok(registry) => registry,
+            Err(msg) => fail!("builtin block registry is invalid: {}", msg),
+        }
+    }
+
+    pub fn from_json_str(s: &str) -> Result<BlockRegistry, ~str> {
+        let root = match json::from_str(s) {
+            Ok(root) => root,
+            Err(e) => return Err(format!("{}", e)),
+        };
+
+        let entries = match find(&root, "blocks") {
+            Some(&json::List(ref entries)) => entries,
+            _ => return Err(~"expected a \"blocks\" list"),
+        };
+
+        let mut registry = BlockRegistry {
+            blocks: ~[],
+            by_id: ~[None, ..MAX_BLOCK_TYPES],
+            opaque: [true, ..256],
+        };
+
+        // Ores refer to other blocks by name, so resolve them after every
+        // block has been read
+        let mut ore_names = ~[];
+
+        for entry in entries.iter() {
+            let id = try!(to_uint("id", try!(number(entry, "id"))));
+            let name = try!(string(entry, "name"));
+            if id >= MAX_BLOCK_TYPES {
+                return Err(format!("block {}: id {} is not below {}", name, id, MAX_BLOCK_TYPES));
+            }
+            if registry.by_id[id].is_some() {
+                return Err(format!("block {}: id {} is already used", name, id));
+            }
+
+            let transparency = match try!(string(entry, "transparency")).as_slice() {
+                "invisible" => TransparencyInvisible,
+                "solid" => TransparencySolid,
+                "cutout" => TransparencyCutout,
+                "translucent" => TransparencyTranslucent,
+                other => return Err(format!("block {}: unknown transparency class {}", name, other)),
+            };
+
+            let color = try!(numbers(entry, "color", 4));
+            let texture = try!(numbers(entry, "texture", 3));
+
+            let light = match to_uint("light", try!(number(entry, "light"))) {
+                Ok(light) if light <= 15 => light,
+                _ => return Err(format!("block {}: light must be an integer between 0 and 15", name)),
+            };
+
+            let ore = match find(entry, "ore") {
+                Some(ore) => {
+                    ore_names.push((id, try!(string(ore, "replace"))));
+                    Some(OreParams {
+                        replace: 0,
+                        frequency: try!(number(ore, "frequency")),
+                        threshold: try!(number(ore, "threshold")),
+                        max_height: try!(number(ore, "max_height")),
+                    })
+                },
+                None => None,
+            };
+
+            let opaque = match find(entry, "opaque") {
+                Some(&json::Boolean(b)) => b,
+                _ => return Err(format!("block {}: expected boolean \"opaque\"", name)),
+            };
+
+            registry.opaque[id] = opaque;
+            registry.by_id[id] = Some(registry.blocks.len());
+            registry.blocks.push(BlockInfo {
+                id: id as BlockType,
+                name: name,
+                opaque: opaque,
+                transparency: transparency,
+                color: [color[0] as f32, color[1] as f32, color[2] as f32, color[3] as f32],
+                texture: [texture[0] as f32, texture[1] as f32, texture[2] as f32],
+                light: light as u8,
+                ore: ore,
+            });
+        }
+
+        for &(id, ref replace_name) in ore_names.iter() {
+            let replace = match registry.find(replace_name.as_slice()) {
+                Some(replace) => replace,
+                None => return Err(format!("block {}: ore replaces unknown block {}", id, *replace_name)),
+            };
+            let index = registry.by_id[id].unwrap();
+            registry.blocks[index].ore.get_mut_ref().replace = replace;
+        }
+
+        Ok(registry)
+    }
+
+    pub fn get<'a>(&'a self, id: BlockType) -> Option<&'a BlockInfo> {
+        if (id as uint) < self.by_id.len() {
+            self.by_id[id as uint].map(|i| &self.blocks[i])
+        } else {
+            None
+        }
+    }
+
+    /// Look up a block id by name
+    pub fn find(&self, name: &str) -> Option<BlockType> {
+        self.blocks.iter().find(|info| info.name.as_slice() == name).map(|info| info.id)
+    }
+
+    pub fn is_opaque(&self, id: BlockType) -> bool {
+        self.opaque[id as uint]
+    }
+
+    pub fn transparency(&self, id: BlockType) -> TransparencyClass {
+        match self.get(id) {
+            Some(info) => info.transparency,
+            None => TransparencySolid,
+        }
+    }
+
+    pub fn light(&self, id: BlockType) -> u8 {
+        match self.get(id) {
+            Some(info) => info.light,
+            None => 0,
+        }
+    }
+
+    /// The parts of the registry that change generated terrain: the names
+    /// the decorator looks blocks up by and the ore parameters
+    pub fn generation_key(&self) -> ~str {
+        let mut key = ~"";
+        for info in self.blocks.iter() {
+            key.push_str(format!("{}={}", info.id, info.name));
+            match info.ore {
+                Some(ref ore) => key.push_str(format!("(ore {} {} {} {})", ore.replace,
+                                                      ore.frequency, ore.threshold, ore.max_height)),
+                None => {},
+            }
+            key.push_str(";");
+        }
+        key
+    }
+}
+
+fn find<'a>(j: &'a Json, key: &str) -> Option<&'a Json> {
+    match *j {
+        json::Object(ref obj) => obj.find(&key.to_owned()),
+        _ => None,
+    }
+}
+
+fn number(j: &Json, key: &str) -> Result<f64, ~str> {
+    match find(j, key) {
+        Some(&json::Number(x)) => Ok(x),
+        _ => Err(format!("expected number \"{}\"", key)),
+    }
+}
+
+fn string(j: &Json, key: &str) -> Result<~str, ~str> {
+    match find(j, key) {
+        Some(&json::String(ref s)) => Ok(s.clone()),
+        _ => Err(format!("expected string \"{}\"", key)),
+    }
+}
+
+fn numbers(j: &Json, key: &str, len: uint) -> Result<~[f64], ~str> {
+    let err = format!("expected a list of {} numbers \"{}\"", len, key);
+    match find(j, key) {
+        Some(&json::List(ref list)) if list.len() == len => {
+            let mut out = ~[];
+            for x in list.iter() {
+                match *x {
+                    json::Number(x) => out.push(x),
+                    _ => return Err(err),
+                }
+            }
+            Ok(out)
+        },
+        _ => Err(err),
+    }
+}
+
+#[test]
+fn test_builtin_registry() {
+    use terrain::{BlockAir, BlockGrass, BlockStone, BlockWater};
+
+    let registry = BlockRegistry::builtin();
+    assert_eq!(registry.find("air"), Some(BlockAir));
+    assert_eq!(registry.find("grass"), Some(BlockGrass));
+    assert_eq!(registry.transparency(BlockAir), TransparencyInvisible);
+    assert!(!registry.is_opaque(BlockAir));
+    assert!(registry.is_opaque(BlockStone));
+    assert!(registry.get(BlockWater).is_some());
+
+    let coal = registry.find("coal_ore").unwrap();
+    assert_eq!(registry.get(coal).unwrap().ore.get_ref().replace, BlockStone);
+
+    // Unknown blocks are solid
+    assert!(registry.is_opaque(200));
+    assert_eq!(registry.transparency(200), TransparencySolid);
+}
+
+#[test]
+fn test_registry_errors() {
+    assert!(BlockRegistry::from_json_str("{}").is_err());
+    assert!(BlockRegistry::from_json_str("{\"blocks\": [{\"id\": 1}]}").is_err());
+    assert!(BlockRegistry::from_json_str("{\"blocks\": [
+        {\"id\": 40, \"name\": \"x\", \"opaque\": true, \"transparency\": \"solid\",
+         \"color\": [1, 1, 1, 1], \"texture\": [1, 1, 1], \"light\": 0}]}").is_err());
+    assert!(BlockRegistry::from_json_str("{\"blocks\": [
+        {\"id\": -1, \"name\": \"x\", \"opaque\": true, \"transparency\": \"solid\",
+         \"color\": [1, 1, 1, 1], \"texture\": [1, 1, 1], \"light\": 0}]}").is_err());
+    assert!(BlockRegistry::from_json_str("{\"blocks\": [
+        {\"id\": 1.5, \"name\": \"x\", \"opaque\": true, \"transparency\": \"solid\",
+         \"color\": [1, 1, 1, 1], \"texture\": [1, 1, 1], \"light\": 0}]}").is_err());
+    assert!(BlockRegistry::from_json_str("{\"blocks\": [
+        {\"id\": 3, \"name\": \"x\", \"opaque\": true, \"transparency\": \"solid\",
+         \"color\": [1, 1, 1, 1], \"texture\": [1, 1, 1], \"light\": 7.5}]}").is_err());
+    assert!(BlockRegistry::from_json_str("{\"blocks\": [
+        {\"id\": 3, \"name\": \"x\", \"opaque\": true, \"transparency\": \"solid\",
+         \"color\": [1, 1, 1, 1], \"texture\": [1, 1, 1], \"light\": 0,
+         \"ore\": {\"replace\": \"nothing\", \"frequency\": 1, \"threshold\": 0, \"max_height\": 0}}]}").is_err());
+}
@@ -0,0 +1,575 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate cgmath;
+
+use std::comm::{Data, Empty, Disconnected};
+use std::io::{IoResult, Listener, Acceptor};
+use std::io::net::ip::SocketAddr;
+use std::io::net::tcp::{TcpListener, TcpStream};
+use collections::{HashMap, HashSet};
+
+use cgmath::vector::{Vector, EuclideanVector};
+use cgmath::vector::Vec3;
+
+use CHUNK_SIZE;
+use chunk::{world_to_chunk, in_padded_chunk};
+use config::WorldConfig;
+use lru::LruCache;
+use metrics::{Metrics, LogError};
+use protocol::{Message, Hello, Welcome, Rejected, RequestChunk, ChunkData};
+use protocol::{SetBlock, PlayerPosition, PlayerLeft};
+use protocol::{PROTOCOL_VERSION, read_message, write_message};
+use ratelimiter::RateLimiter;
+use region::RegionStore;
+use registry::BlockRegistry;
+use terrain::{BlockType, Terrain, TerrainGenerator};
+
+/// Chunks the world task keeps in memory
+static CACHE_CHUNKS : uint = 1024;
+
+/// Unsaved chunks that force a save even while events keep arriving
+static MAX_DIRTY_CHUNKS : uint = 64;
+
+/// Blocks farther than this from the origin on any axis are outside the world
+static WORLD_LIMIT : i64 = 1 << 40;
+
+/// Farthest a player can edit from their last reported position
+static MAX_EDIT_DISTANCE : f64 = 128.0;
+
+/// Minimum time between two edits from one player, in nanoseconds
+static EDIT_INTERVAL : u64 = 20*1000*1000;
+
+enum ServerEvent {
+    ClientConnected(u32, Sender<Message>),
+    ClientMessage(u32, Message),
+    ClientDisconnected(u32),
+    Shutdown,
+}
+
+/// Handle to a running server
+///
+/// The authoritative world lives in a single task that generates chunks and
+/// applies edits in the order they arrive, so every client sees edits in the
+/// same order. Chunks are cached in memory and changed ones are saved to the
+/// region store in batches, whenever the task runs out of events. Each
+/// connection has a task reading from the socket and another writing to it.
+pub struct Server {
+    /// Address the server is listening on
+    addr: SocketAddr,
+    events: Sender<ServerEvent>,
+    stop_accepting: Sender<()>,
+}
+
+impl Server {
+    /// Listen on addr and serve the world in background tasks
+    ///
+    /// Port 0 picks a free port, which can be read back from Server::addr.
+    pub fn start(addr: SocketAddr,
+                 config: &WorldConfig,
+                 store: RegionStore,
+                 registry: BlockRegistry,
+                 metrics: Metrics) -> IoResult<Server> {
+        let mut listener = try!(TcpListener::bind(addr));
+        let addr = try!(listener.socket_name());
+        let acceptor = try!(listener.listen());
+
+        let (events_tx, events_rx) = channel();
+        let config = config.clone();
+        let world_metrics = metrics.clone();
+        spawn(proc() {
+            let mut world = ServerWorld {
+                terrain_generator: TerrainGenerator::new(&config, &registry),
+                registry: registry,
+                store: store,
+                cache: LruCache::new(CACHE_CHUNKS),
+                dirty: HashSet::new(),
+                clients: HashMap::new(),
+                positions: HashMap::new(),
+                edit_limiters: HashMap::new(),
+                metrics: world_metrics,
+            };
+            world.run(events_rx);
+        });
+
+        let (stop_tx, stop_rx) = channel();
+        let accept_events = events_tx.clone();
+        spawn(proc() {
+            let mut acceptor = acceptor;
+            let mut next_player_id = 1u32;
+            for stream in acceptor.incoming() {
+                match stop_rx.try_recv() {
+                    Data(()) => break,
+                    _ => {},
+                }
+
+                match stream {
+                    Ok(stream) => {
+                        let player_id = next_player_id;
+                        next_player_id += 1;
+                        let events = accept_events.clone();
+                        spawn(proc() {
+                            handle_connection(stream, player_id, events);
+                        });
+                    },
+                    Err(e) => metrics.log(LogError, format!("failed to accept connection: {}", e)),
+                }
+            }
+        });
+
+        Ok(Server {
+            addr: addr,
+            events: events_tx,
+            stop_accepting: stop_tx,
+        })
+    }
+
+    /// Disconnect every client and stop listening
+    pub fn shutdown(self) {
+        self.events.send(Shutdown);
+        self.stop_accepting.send(());
+        // Wake the acceptor so it sees the stop request
+        let _ = TcpStream::connect(self.addr);
+    }
+}
+
+struct ServerWorld {
+    terrain_generator: TerrainGenerator,
+    registry: BlockRegistry,
+    store: RegionStore,
+    cache: LruCache<(i64, i64, i64), ~Terrain>,
+    /// Cached chunks that differ from the region store
+    dirty: HashSet<(i64, i64, i64)>,
+    clients: HashMap<u32, Sender<Message>>,
+    positions: HashMap<u32, Vec3<f64>>,
+    edit_limiters: HashMap<u32, RateLimiter>,
+    metrics: Metrics,
+}
+
+impl ServerWorld {
+    fn run(&mut self, events: Receiver<ServerEvent>) {
+        loop {
+            let event = match events.try_recv() {
+                Data(event) => event,
+                Empty => {
+                    self.save_dirty();
+                    match events.recv_opt() {
+                        Some(event) => event,
+                        None => break,
+                    }
+                },
+                Disconnected => break,
+            };
+
+            match event {
+                ClientConnected(player_id, tx) => {
+                    self.metrics.info(format!("player {} connected", player_id));
+                    for (&other_id, &p) in self.positions.iter() {
+                        tx.send_opt(PlayerPosition(other_id, p));
+                    }
+                    self.clients.insert(player_id, tx);
+                    self.edit_limiters.insert(player_id, RateLimiter::new(EDIT_INTERVAL));
+                },
+                ClientMessage(player_id, msg) => self.handle_message(player_id, msg),
+                ClientDisconnected(player_id) => {
+                    self.metrics.info(format!("player {} disconnected", player_id));
+                    self.clients.remove(&player_id);
+                    self.edit_limiters.remove(&player_id);
+                    if self.positions.remove(&player_id) {
+                        self.broadcast(PlayerLeft(player_id));
+                    }
+                },
+                // Dropping the senders ends the writer tasks, which close
+                // their sockets
+                Shutdown => break,
+            }
+
+            if self.dirty.len() >= MAX_DIRTY_CHUNKS {
+                self.save_dirty();
+            }
+        }
+
+        self.save_dirty();
+    }
+
+    fn handle_message(&mut self, player_id: u32, msg: Message) {
+        match msg {
+            RequestChunk(c) => {
+                let limit = WORLD_LIMIT / CHUNK_SIZE as i64;
+                if !in_bounds(c, limit) {
+                    self.metrics.info(format!("player {} requested chunk outside the world", player_id));
+                    return;
+                }
+                let terrain = self.load(c).clone();
+                self.send(player_id, ChunkData(c, terrain));
+            },
+            SetBlock(p, blocktype) => {
+                if self.registry.get(blocktype).is_none() {
+                    self.metrics.info(format!("player {} placed unknown block type {}", player_id, blocktype));
+                    return;
+                }
+                if !self.edit_allowed(player_id, p) {
+                    self.correct(player_id, p);
+                    return;
+                }
+                self.set_block(p, blocktype);
+                self.broadcast(SetBlock(p, blocktype));
+            },
+            PlayerPosition(_, p) => {
+                self.positions.insert(player_id, p);
+                for (&id, tx) in self.clients.iter() {
+                    if id != player_id {
+                        tx.send_opt(PlayerPosition(player_id, p));
+                    }
+                }
+            },
+            _ => self.metrics.log(LogError, format!("unexpected message from player {}", player_id)),
+        }
+    }
+
+    /// Whether a player may edit the block at p now
+    ///
+    /// The block must be inside the world and within reach of where the
+    /// player last said they were, and the player must not be editing
+    /// faster than EDIT_INTERVAL allows.
+    fn edit_allowed(&mut self, player_id: u32, p: Vec3<i64>) -> bool {
+        if !in_bounds(p, WORLD_LIMIT) {
+            self.metrics.info(format!("player {} edited a block outside the world", player_id));
+            return false;
+        }
+
+        match self.positions.find(&player_id) {
+            Some(pos) => {
+                let d = Vec3::new(p.x as f64, p.y as f64, p.z as f64).sub_v(pos);
+                if d.length() > MAX_EDIT_DISTANCE {
+                    self.metrics.info(format!("player {} edited a block out of reach", player_id));
+                    return false;
+                }
+            },
+            None => {
+                self.metrics.info(format!("player {} edited a block before sending its position", player_id));
+                return false;
+            },
+        }
+
+        match self.edit_limiters.find_mut(&player_id) {
+            Some(limiter) => limiter.limit(),
+            None => false,
+        }
+    }
+
+    /// Tell a player whose edit was refused what the block really is
+    ///
+    /// The client has already applied the edit to its own copy. Only cached
+    /// chunks are consulted, so a refused edit costs no disk access.
+    fn correct(&self, player_id: u32, p: Vec3<i64>) {
+        let (c, l) = world_to_chunk(p);
+        match self.cache.find(&(c.x, c.y, c.z)) {
+            Some(terrain) => {
+                let blocktype = terrain.get(l.x, l.y, l.z).blocktype;
+                self.send(player_id, SetBlock(p, blocktype));
+            },
+            None => {},
+        }
+    }
+
+    /// Get a chunk from the cache, the region store or the generator, in
+    /// that order
+    ///
+    /// Generated chunks are saved along with edited ones so they are only
+    /// generated once. Chunks pushed out of the cache are saved first if
+    /// they changed.
+    fn load<'a>(&'a mut self, c: Vec3<i64>) -> &'a mut ~Terrain {
+        let key = (c.x, c.y, c.z);
+        if self.cache.contains_key(&key) {
+            self.cache.touch(&key);
+        } else {
+            let saved = match self.store.load(c) {
+                Ok(saved) => saved,
+                Err(e) => {
+                    self.metrics.log(LogError, format!("failed to load chunk ({}, {}, {}): {}", c.x, c.y, c.z, e));
+                    None
+                },
+            };
+            let terrain = match saved {
+                Some(terrain) => terrain,
+                None => {
+                    self.dirty.insert(key);
+                    let p = Vec3::new(c.x as f64, c.y as f64, c.z as f64).mul_s(CHUNK_SIZE as f64);
+                    self.terrain_generator.gen(p)
+                },
+            };
+
+            // Keep the new chunk from being evicted along with the others
+            self.cache.insert(key, terrain);
+            self.cache.pin(&key);
+            let store = &mut self.store;
+            let dirty = &mut self.dirty;
+            let metrics = &self.metrics;
+            self.cache.evict(|k, terrain| {
+                let (x, y, z) = k;
+                if dirty.remove(&k) {
+                    save(&mut *store, metrics, Vec3::new(x, y, z), terrain);
+                }
+            });
+            self.cache.unpin(&key);
+        }
+        self.cache.find_mut(&key).unwrap()
+    }
+
+    /// Write every changed chunk back to the region store
+    fn save_dirty(&mut self) {
+        for &(x, y, z) in self.dirty.iter() {
+            match self.cache.find(&(x, y, z)) {
+                Some(terrain) => save(&mut self.store, &self.metrics, Vec3::new(x, y, z), *terrain),
+                None => {},
+            }
+        }
+        self.dirty.clear();
+    }
+
+    /// Apply an edit to the owning chunk and every neighbor holding a copy
+    /// of the block in its padding
+    fn set_block(&mut self, p: Vec3<i64>, blocktype: BlockType) {
+        let (chunk_coord, _) = world_to_chunk(p);
+        for dx in range(-1i64, 2) {
+            for dy in range(-1i64, 2) {
+                for dz in range(-1i64, 2) {
+                    let c = chunk_coord.add_v(&Vec3::new(dx, dy, dz));
+                    let l = p.sub_v(&c.mul_s(CHUNK_SIZE as i64));
+                    if !in_padded_chunk(l) {
+                        continue;
+                    }
+
+                    self.load(c).set(l.x as int, l.y as int, l.z as int, blocktype);
+                    self.dirty.insert((c.x, c.y, c.z));
+                }
+            }
+        }
+    }
+
+    fn send(&self, player_id: u32, msg: Message) {
+        match self.clients.find(&player_id) {
+            Some(tx) => { tx.send_opt(msg); },
+            None => {},
+        }
+    }
+
+    fn broadcast(&self, msg: Message) {
+        for (_, tx) in self.clients.iter() {
+            tx.send_opt(msg.clone());
+        }
+    }
+}
+
+fn save(store: &mut RegionStore, metrics: &Metrics, c: Vec3<i64>, terrain: &Terrain) {
+    match store.save(c, terrain) {
+        Ok(()) => {},
+        Err(e) => metrics.log(LogError, format!("failed to save chunk ({}, {}, {}): {}", c.x, c.y, c.z, e)),
+    }
+}
+
+fn in_bounds(p: Vec3<i64>, limit: i64) -> bool {
+    p.x.abs() <= limit && p.y.abs() <= limit && p.z.abs() <= limit
+}
+
+/// Handshake with a new client, then forward its messages to the world task
+/// until it disconnects
+fn handle_connection(mut stream: TcpStream, player_id: u32, events: Sender<ServerEvent>) {
+    match read_message(&mut stream) {
+        Ok(Hello(version)) if version == PROTOCOL_VERSION => {},
+        Ok(Hello(version)) => {
+            let reason = format!("server uses protocol version {}, client uses {}",
+                                 PROTOCOL_VERSION, version);
+            let _ = write_message(&mut stream, &Rejected(reason));
+            return;
+        },
+        _ => return,
+    }
+
+    if write_message(&mut stream, &Welcome(player_id)).is_err() {
+        return;
+    }
+
+    let (tx, rx) = channel();
+    if events.send_opt(ClientConnected(player_id, tx)).is_err() {
+        return;
+    }
+
+    let mut writer = stream.clone();
+    spawn(proc() {
+        for msg in rx.iter() {
+            if write_message(&mut writer, &msg).is_err() {
+                break;
+            }
+        }
+        // Unblocks the reader if the world task dropped this client first
+        let _ = writer.close_read();
+    });
+
+    loop {
+        match read_message(&mut stream) {
+            Ok(msg) => {
+                if events.send_opt(ClientMessage(player_id, msg)).is_err() {
+                    break;
+                }
+            },
+            Err(_) => break,
+        }
+    }
+
+    events.send_opt(ClientDisconnected(player_id));
+}
+
+#[test]
+fn test_loopback_edit() {
+    use std::io::TempDir;
+    use std::io::net::ip::Ipv4Addr;
+    use client::Client;
+    use terrain::{BlockStone, BlockDirt};
+
+    let dir = TempDir::new("cubeland-server").unwrap();
+    let store = RegionStore::new(dir.path().clone()).unwrap();
+    let addr = SocketAddr { ip: Ipv4Addr(127, 0, 0, 1), port: 0 };
+    let server = Server::start(addr, &WorldConfig::default(), store, BlockRegistry::builtin(),
+                               Metrics::new(LogError)).unwrap();
+
+    let mut a = Client::connect(server.addr).unwrap();
+    let mut b = Client::connect(server.addr).unwrap();
+    assert!(a.player_id() != b.player_id());
+
+    let fetch = |client: &mut Client, c: Vec3<i64>| -> ~Terrain {
+        client.send(&RequestChunk(c)).unwrap();
+        match client.recv() {
+            Some(ChunkData(coord, terrain)) => {
+                assert_eq!(coord, c);
+                terrain
+            },
+            _ => fail!("expected chunk data"),
+        }
+    };
+
+    // On the border with chunk (-1, 0, 0), which keeps a copy in its padding
+    let p = Vec3::new(0i64, 7, 9);
+    let old = fetch(&mut b, Vec3::new(0, 0, 0)).get(0, 7, 9).blocktype;
+    let new = if old == BlockStone { BlockDirt } else { BlockStone };
+
+    a.send(&PlayerPosition(a.player_id(), Vec3::new(0.0, 8.0, 8.0))).unwrap();
+    a.send(&SetBlock(p, new)).unwrap();
+
+    for client in [&a, &b].iter() {
+        loop {
+            match client.recv() {
+                Some(PlayerPosition(..)) => continue,
+                Some(SetBlock(q, blocktype)) => {
+                    assert_eq!(q, p);
+                    assert_eq!(blocktype, new);
+                },
+                _ => fail!("expected the edit to be relayed"),
+            }
+            break;
+        }
+    }
+
+    assert_eq!(fetch(&mut b, Vec3::new(0, 0, 0)).get(0, 7, 9).blocktype, new);
+    assert_eq!(fetch(&mut b, Vec3::new(-1, 0, 0)).get(CHUNK_SIZE, 7, 9).blocktype, new);
+
+    drop(a);
+    drop(b);
+    server.shutdown();
+}
+
+#[cfg(test)]
+fn test_world(dir: &Path) -> ServerWorld {
+    let registry = BlockRegistry::builtin();
+    ServerWorld {
+        terrain_generator: TerrainGenerator::new(&WorldConfig::default(), &registry),
+        registry: registry,
+        store: RegionStore::new(dir.clone()).unwrap(),
+        cache: LruCache::new(CACHE_CHUNKS),
+        dirty: HashSet::new(),
+        clients: HashMap::new(),
+        positions: HashMap::new(),
+        edit_limiters: HashMap::new(),
+        metrics: Metrics::new(LogError),
+    }
+}
+
+#[test]
+fn test_edit_validation() {
+    use std::i64;
+    use std::io::TempDir;
+    use terrain::{BlockStone, BlockDirt};
+
+    let dir = TempDir::new("cubeland-server").unwrap();
+    let mut world = test_world(dir.path());
+    let (tx, rx) = channel();
+    world.clients.insert(1, tx);
+    world.edit_limiters.insert(1, RateLimiter::new(EDIT_INTERVAL));
+
+    // Edits before the player says where they are are refused
+    world.handle_message(1, SetBlock(Vec3::new(1, 2, 3), BlockStone));
+    match rx.try_recv() {
+        Empty => {},
+        _ => fail!("expected the edit to be refused"),
+    }
+    world.handle_message(1, PlayerPosition(1, Vec3::new(0.0, 0.0, 0.0)));
+
+    // Out of reach, and outside the world, without touching the chunk
+    world.handle_message(1, SetBlock(Vec3::new(1000, 0, 0), BlockStone));
+    world.handle_message(1, SetBlock(Vec3::new(i64::MAX, 0, 0), BlockStone));
+    match rx.try_recv() {
+        Empty => {},
+        _ => fail!("expected the edit to be refused"),
+    }
+    assert!(!world.cache.contains_key(&(1000 / CHUNK_SIZE as i64, 0, 0)));
+
+    world.handle_message(1, SetBlock(Vec3::new(1, 2, 3), BlockStone));
+    match rx.try_recv() {
+        Data(SetBlock(p, BlockStone)) => assert_eq!(p, Vec3::new(1, 2, 3)),
+        _ => fail!("expected the edit to be relayed"),
+    }
+
+    // Too soon after the last edit, so the player is told the block is unchanged
+    world.handle_message(1, SetBlock(Vec3::new(1, 2, 3), BlockDirt));
+    match rx.try_recv() {
+        Data(SetBlock(p, BlockStone)) => assert_eq!(p, Vec3::new(1, 2, 3)),
+        _ => fail!("expected the edit to be corrected"),
+    }
+
+    world.save_dirty();
+    assert!(world.dirty.is_empty());
+    let mut store = RegionStore::new(dir.path().clone()).unwrap();
+    let terrain = store.load(Vec3::new(0, 0, 0)).unwrap().unwrap();
+    assert_eq!(terrain.get(1, 2, 3).blocktype, BlockStone);
+}
+
+#[test]
+fn test_generated_chunks_saved() {
+    use std::io::TempDir;
+
+    let dir = TempDir::new("cubeland-server").unwrap();
+    let mut world = test_world(dir.path());
+    world.cache = LruCache::new(1);
+
+    let c = Vec3::new(0i64, 0, 0);
+    let generated = world.load(c).clone();
+    // Pushing the chunk out of the cache saves it
+    world.load(Vec3::new(1, 0, 0));
+    assert!(!world.cache.contains_key(&(0, 0, 0)));
+
+    let mut store = RegionStore::new(dir.path().clone()).unwrap();
+    let saved = store.load(c).unwrap().unwrap();
+    assert_eq!(saved.get(5, 6, 7).blocktype, generated.get(5, 6, 7).blocktype);
+}
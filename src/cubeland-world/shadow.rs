@@ -0,0 +1,284 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Cascaded shadow map layout
+//!
+//! The view is cut into slices by distance from the camera and each slice
+//! gets its own shadow map, so nearby shadows get most of the texels. Each
+//! map is an orthographic projection along the light direction that covers
+//! the bounding sphere of its slice. The sphere does not change as the
+//! camera turns, and its center is snapped to whole texels, so shadow edges
+//! stay put instead of shimmering while the camera moves.
+//!
+//! Only the matrices are worked out here; the renderer draws the maps.
+
+extern crate getopts;
+extern crate cgmath;
+
+use cgmath::matrix::Mat4;
+use cgmath::vector::EuclideanVector;
+use cgmath::vector::Vector;
+use cgmath::vector::Vec3;
+use cgmath::vector::Vec4;
+
+/// Most cascades the shaders have room for. Keep in sync with
+/// shaders/main.vs.glsl and shaders/main.fs.glsl.
+pub static MAX_CASCADES : uint = 4;
+
+/// How far toward the light, beyond its slice, terrain can still cast a
+/// shadow into a cascade
+static CASTER_DISTANCE : f32 = 256.0;
+
+#[deriving(Clone, Eq, Show)]
+pub struct ShadowConfig {
+    /// Width and height of each shadow map, in texels
+    resolution: uint,
+    /// Distance from the camera where each cascade ends, nearest first. No
+    /// splits turns shadows off.
+    splits: ~[f32],
+}
+
+/// The shadow map for one slice of the view
+pub struct Cascade {
+    /// Distances from the camera along the view direction that it covers
+    near: f32,
+    far: f32,
+    /// From world coordinates to the map's clip space, which holds the
+    /// whole slice and everything toward the light that can shade it
+    matrix: Mat4<f32>,
+}
+
+impl ShadowConfig {
+    pub fn default() -> ShadowConfig {
+        ShadowConfig {
+            resolution: 2048,
+            splits: ~[24.0, 80.0, 256.0],
+        }
+    }
+
+    /// Command line options understood by from_matches
+    pub fn opts() -> ~[getopts::OptGroup] {
+        ~[
+            getopts::optopt("", "shadow-resolution", "shadow map size in texels", "TEXELS"),
+            getopts::optopt("", "shadow-splits", "where each shadow cascade ends, comma separated; empty turns shadows off", "DISTANCES"),
+        ]
+    }
+
+    pub fn from_matches(matches: &getopts::Matches) -> Result<ShadowConfig, ~str> {
+        let mut config = ShadowConfig::default();
+
+        match matches.opt_str("shadow-resolution") {
+            Some(s) => {
+                config.resolution = match from_str::<uint>(s) {
+                    Some(x) if x >= 16 => x,
+                    _ => return Err(format!("shadow-resolution: invalid size {}", s)),
+                };
+            },
+            None => {},
+        }
+
+        match matches.opt_str("shadow-splits") {
+            Some(s) => config.splits = try!(parse_splits(s)),
+            None => {},
+        }
+
+        Ok(config)
+    }
+}
+
+/// Parse a comma separated list of increasing distances
+pub fn parse_splits(s: &str) -> Result<~[f32], ~str> {
+    let mut splits = ~[];
+    for x in s.split(',').map(|x| x.trim()).filter(|x| !x.is_empty()) {
+        let split = match from_str::<f32>(x) {
+            Some(split) => split,
+            None => return Err(format!("shadow-splits: invalid distance {}", x)),
+        };
+        if split <= 0.0 || splits.last().map_or(false, |&last| split <= last) {
+            return Err(~"shadow-splits: distances must be positive and increasing");
+        }
+        splits.push(split);
+    }
+
+    if splits.len() > MAX_CASCADES {
+        return Err(format!("shadow-splits: at most {} cascades", MAX_CASCADES));
+    }
+    Ok(splits)
+}
+
+/// Lay out a cascade for each split
+///
+/// The camera looks along direction with a vertical field of view of fov_y
+/// radians and the given aspect ratio, starting near from its position.
+/// light_direction points the way the light travels.
+pub fn cascades(config: &ShadowConfig,
+                light_direction: Vec3<f32>,
+                camera_position: Vec3<f32>,
+                camera_direction: Vec3<f32>,
+                fov_y: f32,
+                aspect: f32,
+                near: f32) -> ~[Cascade] {
+    let tan_y = (fov_y / 2.0).tan();
+    let tan_x = tan_y * aspect;
+    // Squared distance of a frustum corner from the view axis, per unit of
+    // distance along it
+    let k2 = tan_x * tan_x + tan_y * tan_y;
+
+    let forward = light_direction.normalize();
+    let up_hint = if forward.y.abs() < 0.99 { Vec3::unit_y() } else { Vec3::unit_x() };
+    let right = forward.cross(&up_hint).normalize();
+    let up = right.cross(&forward);
+
+    let camera_direction = camera_direction.normalize();
+    let mut result = ~[];
+    let mut n = near;
+    for &f in config.splits.iter() {
+        // Smallest sphere around the slice, centered on the view axis
+        let c = ((f + n) * (1.0 + k2) / 2.0).min(f);
+        let radius = ((f - c) * (f - c) + f * f * k2).sqrt();
+        let center = camera_position.add_v(&camera_direction.mul_s(c));
+
+        // Moving the map by whole texels keeps each texel over the same
+        // stretch of the world. The map is a texel wider than the sphere on
+        // each side to leave room for it.
+        let texel = 2.0 * radius / (config.resolution - 2) as f32;
+        let half_width = texel * config.resolution as f32 / 2.0;
+        let x = (center.dot(&right) / texel).floor() * texel;
+        let y = (center.dot(&up) / texel).floor() * texel;
+
+        // Depth runs from CASTER_DISTANCE toward the light from the sphere
+        // to its far side
+        let lo = -radius - CASTER_DISTANCE;
+        let hi = radius;
+        let s = 2.0 / (hi - lo);
+        let z = -s * (center.dot(&forward) + lo) - 1.0;
+
+        let matrix = Mat4::from_cols(
+            Vec4::new(right.x / half_width, up.x / half_width, forward.x * s, 0.0),
+            Vec4::new(right.y / half_width, up.y / half_width, forward.y * s, 0.0),
+            Vec4::new(right.z / half_width, up.z / half_width, forward.z * s, 0.0),
+            Vec4::new(-x / half_width, -y / half_width, z, 1.0));
+
+        result.push(Cascade { near: n, far: f, matrix: matrix });
+        n = f;
+    }
+    result
+}
+
+#[cfg(test)]
+fn test_camera() -> (Vec3<f32>, Vec3<f32>) {
+    (Vec3::new(100.5, 40.0, -20.25), Vec3::new(0.3, -0.2, -1.0).normalize())
+}
+
+/// Corners of the slice of the camera's frustum between two distances
+#[cfg(test)]
+fn slice_corners(position: Vec3<f32>, direction: Vec3<f32>, fov_y: f32, aspect: f32,
+                 near: f32, far: f32) -> ~[Vec3<f32>] {
+    let right = direction.cross(&Vec3::unit_y()).normalize();
+    let up = right.cross(&direction);
+    let tan_y = (fov_y / 2.0).tan();
+    let mut corners = ~[];
+    for &d in [near, far].iter() {
+        for &sx in [-1.0f32, 1.0].iter() {
+            for &sy in [-1.0f32, 1.0].iter() {
+                corners.push(position.
+                    add_v(&direction.mul_s(d)).
+                    add_v(&right.mul_s(sx * d * tan_y * aspect)).
+                    add_v(&up.mul_s(sy * d * tan_y)));
+            }
+        }
+    }
+    corners
+}
+
+#[cfg(test)]
+fn to_clip(m: &Mat4<f32>, p: Vec3<f32>) -> Vec3<f32> {
+    use cgmath::matrix::Matrix;
+    let v = m.mul_v(&p.extend(1.0));
+    Vec3::new(v.x / v.w, v.y / v.w, v.z / v.w)
+}
+
+#[cfg(test)]
+fn in_clip_box(v: Vec3<f32>) -> bool {
+    static EPSILON : f32 = 1e-4;
+    v.x.abs() <= 1.0 + EPSILON && v.y.abs() <= 1.0 + EPSILON && v.z.abs() <= 1.0 + EPSILON
+}
+
+#[test]
+fn test_cascades_cover_view() {
+    use std::f32::consts::PI;
+
+    let config = ShadowConfig::default();
+    let light = Vec3::new(0.408248f32, -0.816497, 0.408248);
+    let (position, direction) = test_camera();
+    let fov_y = 75.0 * PI / 180.0;
+    let cascades = cascades(&config, light, position, direction, fov_y, 4.0 / 3.0, 0.1);
+
+    assert_eq!(cascades.len(), config.splits.len());
+    let mut near = 0.1;
+    for (cascade, &split) in cascades.iter().zip(config.splits.iter()) {
+        assert_eq!(cascade.near, near);
+        assert_eq!(cascade.far, split);
+        near = split;
+
+        // Every corner of the slice lands in the map, and so does terrain
+        // up toward the light that shades them
+        for &p in slice_corners(position, direction, fov_y, 4.0 / 3.0, cascade.near, cascade.far).iter() {
+            assert!(in_clip_box(to_clip(&cascade.matrix, p)));
+            let caster = p.sub_v(&light.normalize().mul_s(CASTER_DISTANCE - 1.0));
+            assert!(in_clip_box(to_clip(&cascade.matrix, caster)));
+        }
+
+        // Points along the light direction share a texel, nearer the light
+        // being shallower
+        let p = position.add_v(&direction.mul_s((cascade.near + cascade.far) / 2.0));
+        let a = to_clip(&cascade.matrix, p);
+        let b = to_clip(&cascade.matrix, p.sub_v(&light.normalize().mul_s(10.0)));
+        assert!((a.x - b.x).abs() < 1e-4 && (a.y - b.y).abs() < 1e-4);
+        assert!(b.z < a.z);
+    }
+}
+
+#[test]
+fn test_cascades_snap_to_texels() {
+    use std::f32::consts::PI;
+
+    let config = ShadowConfig::default();
+    let light = Vec3::new(0.408248f32, -0.816497, 0.408248);
+    let (position, direction) = test_camera();
+    let fov_y = 75.0 * PI / 180.0;
+    let a = cascades(&config, light, position, direction, fov_y, 4.0 / 3.0, 0.1);
+    let b = cascades(&config, light, position.add_v(&Vec3::new(0.37, 0.0, 1.21)), direction,
+                     fov_y, 4.0 / 3.0, 0.1);
+
+    // A fixed point moves across the map by whole texels as the camera moves
+    let p = position.add_v(&direction.mul_s(10.0));
+    let texels = config.resolution as f32 / 2.0;
+    for (ca, cb) in a.iter().zip(b.iter()) {
+        let (pa, pb) = (to_clip(&ca.matrix, p), to_clip(&cb.matrix, p));
+        for &d in [(pa.x - pb.x) * texels, (pa.y - pb.y) * texels].iter() {
+            assert!((d - d.round()).abs() < 0.01);
+        }
+    }
+}
+
+#[test]
+fn test_parse_splits() {
+    assert_eq!(parse_splits("16, 64,256"), Ok(~[16.0f32, 64.0, 256.0]));
+    assert_eq!(parse_splits(""), Ok(~[]));
+    assert!(parse_splits("64,16").is_err());
+    assert!(parse_splits("0").is_err());
+    assert!(parse_splits("x").is_err());
+    assert!(parse_splits("1,2,3,4,5").is_err());
+}
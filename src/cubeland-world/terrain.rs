@@ -0,0 +1,753 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate cgmath;
+extern crate noise;
+
+use std;
+use std::mem;
+use std::num::Integer;
+use std::vec;
+
+use cgmath::vector::Vector;
+use cgmath::vector::Vec3;
+
+use noise::sources::Perlin;
+use noise::Source;
+
+use CHUNK_SIZE;
+use biome;
+use biome::Biome;
+use biome::BiomeColumn;
+use biome::BiomeMap;
+use decoration::Decorator;
+use decoration::Ground;
+use decoration::{MAX_FEATURE_HEIGHT, MAX_RADIUS};
+use registry::BlockRegistry;
+use config::WorldConfig;
+
+/// Index into the BlockRegistry
+pub type BlockType = u8;
+
+// Blocks placed directly by the generator. data/blocks.json must use these ids.
+pub static BlockAir : BlockType = 0;
+pub static BlockGrass : BlockType = 1;
+pub static BlockStone : BlockType = 2;
+pub static BlockDirt : BlockType = 3;
+pub static BlockWater : BlockType = 4;
+pub static BlockSand : BlockType = 5;
+pub static BlockSnow : BlockType = 6;
+
+pub struct Block {
+    blocktype: BlockType,
+}
+
+/// A copy of one block that is stored back into its chunk when dropped
+pub struct BlockMut<'a> {
+    priv terrain: &'a mut Terrain,
+    priv x: int,
+    priv y: int,
+    priv z: int,
+    priv block: Block,
+}
+
+pub struct TerrainGenerator {
+    density : Perlin,
+    height : Perlin,
+    biomes : BiomeMap,
+    ores : ~[OreGenerator],
+    decorator : Decorator,
+    cave_threshold : f64,
+    /// No column's surface is lower than this
+    min_height : f64,
+    /// No column's surface or water is higher than this
+    max_height : f64,
+}
+
+struct OreGenerator {
+    blocktype : BlockType,
+    replace : BlockType,
+    threshold : f64,
+    max_height : f64,
+    noise : Perlin,
+}
+
+/// Blocks between density samples along each axis. Samples are taken at
+/// multiples of this in world coordinates.
+static DENSITY_STEP : int = 4;
+
+/// Blocks in a padded chunk
+pub static NUM_PADDED_BLOCKS : uint = ((CHUNK_SIZE+2) * (CHUNK_SIZE+2) * (CHUNK_SIZE+2)) as uint;
+
+/// The blocks of one chunk, with a layer of padding copied from its
+/// neighbors
+///
+/// Blocks are stored as indices into a palette of the distinct blocks in
+/// the chunk, packed into as few bits as the palette needs. A chunk that is
+/// all one block type, padding included, has a palette of one and no
+/// indices at all. Setting a block that is not in the palette yet widens
+/// the indices when the palette outgrows them.
+pub struct Terrain {
+    priv palette: ~[Block],
+    /// NUM_PADDED_BLOCKS indices of bits_per_block bits each, never split
+    /// across words
+    priv indices: ~[u64],
+    /// 0, 1, 2, 4 or 8
+    priv bits_per_block: uint,
+}
+
+impl TerrainGenerator {
+    pub fn new(config: &WorldConfig, registry: &BlockRegistry) -> TerrainGenerator {
+        let seed = config.seed;
+        let mut ores = ~[];
+        for info in registry.blocks.iter() {
+            match info.ore {
+                Some(ref ore) => {
+                    ores.push(OreGenerator {
+                        blocktype: info.id,
+                        replace: ore.replace,
+                        threshold: ore.threshold,
+                        max_height: ore.max_height,
+                        noise: Perlin {
+                            seed: seed as int * 101 + info.id as int,
+                            octaves: 1,
+                            frequency: ore.frequency,
+                            lacunarity: 2.0,
+                            persistence: 0.5,
+                            quality: noise::Fast,
+                        },
+                    });
+                },
+                None => {},
+            }
+        }
+
+        TerrainGenerator {
+            density: config.density.perlin(seed as int, noise::Standard),
+            height: config.height.perlin(seed as int * 71, noise::Best),
+            biomes: BiomeMap::new(config),
+            ores: ores,
+            decorator: Decorator::new(seed, registry),
+            cave_threshold: config.cave_threshold,
+            min_height: biome::min_surface_height(config.height.amplitude_bound()),
+            max_height: biome::max_surface_height(config.height.amplitude_bound()),
+        }
+    }
+
+    pub fn biome_at(&self, x: f64, z: f64) -> Biome {
+        self.biomes.biome_at(x, z)
+    }
+
+    /// Returns the blended biome parameters and the surface height of a column
+    pub fn surface(&self, x: f64, z: f64) -> (BiomeColumn, f64) {
+        let column = self.biomes.column(x, z);
+        let height = column.base_height + self.height.get(x, 0.0, z) * column.height_amplitude;
+        (column, height)
+    }
+
+    /// Lowest and highest height any surface or feature can reach
+    pub fn surface_range(&self) -> (f64, f64) {
+        (self.min_height, self.max_height + MAX_FEATURE_HEIGHT as f64 + 1.0)
+    }
+
+    /// Whether nothing generated in the column at x, z is at y or above
+    ///
+    /// Ignores trees overhanging from neighboring columns and anything a
+    /// player built, so it is only a guess at the sky light coming into a
+    /// chunk before the chunk above it has loaded.
+    pub fn open_to_sky(&self, x: f64, y: f64, z: f64) -> bool {
+        if y >= self.max_height + MAX_FEATURE_HEIGHT as f64 + 1.0 {
+            return true;
+        }
+        let (_, height) = self.surface(x, z);
+        y >= height.ceil() + MAX_FEATURE_HEIGHT as f64
+    }
+
+    pub fn gen(&self, p: Vec3<f64>) -> ~Terrain {
+        self.gen_scaled(p, 1)
+    }
+
+    /// Generate a chunk where each block stands for a cube of scale blocks
+    /// on a side, sampled at its minimum corner
+    ///
+    /// Used for distant terrain, so ores and decorations are left out when
+    /// scale is more than 1. The padding is sampled at the same scale.
+    ///
+    /// Chunks entirely above the surface or entirely stone come back
+    /// uniform, without sampling the density or ore noise per block.
+    pub fn gen_scaled(&self, p: Vec3<f64>, scale: int) -> ~Terrain {
+        // Lowest sample and the top of the highest block, padding included
+        let bottom = p.y - scale as f64;
+        let chunk_top = p.y + ((CHUNK_SIZE + 1) * scale - 1) as f64;
+
+        // Far above any surface not even the height needs sampling
+        if bottom >= self.max_height + MAX_FEATURE_HEIGHT as f64 + 1.0 {
+            return Terrain::uniform(BlockAir);
+        }
+
+        let mut columns = ~[];
+        columns.reserve(((CHUNK_SIZE+2) * (CHUNK_SIZE+2)) as uint);
+        let mut max_height = std::f64::NEG_INFINITY;
+        let mut min_stone_height = std::f64::INFINITY;
+        for block_x in std::iter::range(-1, CHUNK_SIZE+1) {
+            for block_z in std::iter::range(-1, CHUNK_SIZE+1) {
+                let x = p.x + (block_x * scale) as f64;
+                let z = p.z + (block_z * scale) as f64;
+                let (column, height) = self.surface(x, z);
+                max_height = max_height.max(height).max(column.water_level);
+                min_stone_height = min_stone_height.min(
+                    height - column.surface_depth - column.subsurface_depth);
+                columns.push((column, height));
+            }
+        }
+
+        if bottom >= max_height {
+            let mut t = Terrain::uniform(BlockAir);
+            self.decorate(t, p, scale, columns);
+            return t;
+        }
+
+        let (density, min_density, _) = sample_grid(&self.density, p, scale);
+
+        // Ores that reach the chunk, with their noise sampled like density
+        let mut ores = ~[];
+        if scale == 1 {
+            for ore in self.ores.iter() {
+                if bottom < ore.max_height {
+                    let (samples, _, max) = sample_grid(&ore.noise, p, scale);
+                    if max > ore.threshold {
+                        ores.push((ore, samples));
+                    }
+                }
+            }
+        }
+
+        // Block values are interpolated between the samples, so none of
+        // them is below the lowest sample or above the highest, and neither
+        // caves nor ores can reach the chunk. Decorations sit on top of the
+        // ground, above all the stone.
+        if chunk_top <= min_stone_height && min_density >= self.cave_threshold && ores.is_empty() {
+            return Terrain::uniform(BlockStone);
+        }
+
+        let mut t = Terrain::new();
+
+        for block_x in std::iter::range(-1, CHUNK_SIZE+1) {
+            for block_z in std::iter::range(-1, CHUNK_SIZE+1) {
+                let (column, height) = columns[((block_x + 1) * (CHUNK_SIZE + 2) + block_z + 1) as uint];
+
+                for block_y in range(-1, CHUNK_SIZE+1) {
+                    let mut blocktype = BlockAir;
+                    let v = p.add_v(&Vec3::new(block_x as f64, block_y as f64, block_z as f64).mul_s(scale as f64));
+                    // Top of the cube, so thin surface layers still show at
+                    // coarse scales
+                    let top = v.y + (scale - 1) as f64;
+
+                    if v.y < height {
+                        if top > height - column.surface_depth {
+                            blocktype = column.surface;
+                        } else if top > height - column.surface_depth - column.subsurface_depth {
+                            blocktype = column.subsurface;
+                        } else {
+                            blocktype = BlockStone;
+                        }
+                    }
+
+                    if blocktype == BlockAir && v.y < column.water_level {
+                        blocktype = BlockWater;
+                    }
+
+                    if blocktype != BlockAir && blocktype != BlockWater {
+                        if interpolate(density.as_slice(), block_x, block_y, block_z) < self.cave_threshold {
+                            blocktype = BlockAir;
+                        }
+                    }
+
+                    for &(ore, ref samples) in ores.iter() {
+                        if blocktype == ore.replace && v.y < ore.max_height &&
+                                interpolate(samples.as_slice(), block_x, block_y, block_z) > ore.threshold {
+                            blocktype = ore.blocktype;
+                            break;
+                        }
+                    }
+
+                    if blocktype != BlockAir {
+                        t.set(block_x, block_y, block_z, blocktype);
+                    }
+                }
+            }
+        }
+
+        self.decorate(t, p, scale, columns);
+        return t;
+    }
+
+    /// Add the features that reach into a chunk
+    ///
+    /// columns holds the surface of the padded chunk, from gen_scaled. Only
+    /// the ring of columns around it that features can grow from is sampled
+    /// here, and chunks that no feature can reach are skipped.
+    fn decorate(&self, t: &mut Terrain, p: Vec3<f64>, scale: int, columns: &[(BiomeColumn, f64)]) {
+        if scale > 1 {
+            return;
+        }
+
+        let origin = Vec3::new(p.x as i64, p.y as i64, p.z as i64);
+        let s = CHUNK_SIZE as i64;
+        let r = MAX_RADIUS;
+        let width = s + 2 + 2 * r;
+
+        let mut grounds = ~[];
+        grounds.reserve((width * width) as uint);
+        let mut lowest = std::i64::MAX;
+        let mut highest = std::i64::MIN;
+        for dx in range(-1 - r, s + 1 + r) {
+            for dz in range(-1 - r, s + 1 + r) {
+                let (column, height) = if dx >= -1 && dx <= s && dz >= -1 && dz <= s {
+                    columns[((dx + 1) * (s + 2) + dz + 1) as uint]
+                } else {
+                    self.surface((origin.x + dx) as f64, (origin.z + dz) as f64)
+                };
+                let y = height.ceil() as i64 - 1;
+                lowest = std::cmp::min(lowest, y);
+                highest = std::cmp::max(highest, y);
+                grounds.push(Ground { y: y, biome: column.biome, water_level: column.water_level });
+            }
+        }
+
+        // Boulders sink at most MAX_RADIUS - 1 blocks below the ground, and
+        // trees reach at most MAX_FEATURE_HEIGHT above it
+        if origin.y - 1 > highest + MAX_FEATURE_HEIGHT || origin.y + s < lowest + 1 - r {
+            return;
+        }
+
+        self.decorator.decorate(t, origin, |x, z| {
+            grounds[((x - origin.x + 1 + r) * width + z - origin.z + 1 + r) as uint]
+        }, |pos| !self.carved(pos));
+    }
+
+    /// Whether caves carved out the block at a world position, if it was
+    /// ground
+    ///
+    /// Interpolates the density samples the same way gen_scaled does at
+    /// scale 1.
+    fn carved(&self, pos: Vec3<i64>) -> bool {
+        let step = DENSITY_STEP as i64;
+        let x0 = pos.x.div_floor(&step) * step;
+        let y0 = pos.y.div_floor(&step) * step;
+        let z0 = pos.z.div_floor(&step) * step;
+        let fx = (pos.x - x0) as f64 / step as f64;
+        let fy = (pos.y - y0) as f64 / step as f64;
+        let fz = (pos.z - z0) as f64 / step as f64;
+
+        let mut d = 0.0;
+        for &(ix, wx) in [(0i64, 1.0 - fx), (1, fx)].iter() {
+            for &(iy, wy) in [(0i64, 1.0 - fy), (1, fy)].iter() {
+                for &(iz, wz) in [(0i64, 1.0 - fz), (1, fz)].iter() {
+                    let sample = self.density.get((x0 + ix * step) as f64,
+                                                  (y0 + iy * step) as f64,
+                                                  (z0 + iz * step) as f64);
+                    d += sample * wx * wy * wz;
+                }
+            }
+        }
+
+        d < self.cave_threshold
+    }
+}
+
+/// Noise samples along each axis of a padded chunk, with one extra that
+/// blocks on the far edge interpolate towards with zero weight
+static SAMPLES : int = CHUNK_SIZE/DENSITY_STEP + 3;
+
+/// Sample noise every DENSITY_STEP blocks across a padded chunk
+///
+/// Returns the samples along with the lowest and highest of them.
+fn sample_grid(noise: &Perlin, p: Vec3<f64>, scale: int) -> (~[f64], f64, f64) {
+    static S : int = DENSITY_STEP;
+    let mut samples = vec::from_elem((SAMPLES * SAMPLES * SAMPLES) as uint, 0.0);
+    let mut min = std::f64::INFINITY;
+    let mut max = std::f64::NEG_INFINITY;
+    for x in range(-1, CHUNK_SIZE/S+1) {
+        for y in range(-1, CHUNK_SIZE/S+1) {
+            for z in range(-1, CHUNK_SIZE/S+1) {
+                let d = noise.get(p.x + (x * S * scale) as f64,
+                                  p.y + (y * S * scale) as f64,
+                                  p.z + (z * S * scale) as f64);
+                samples[(((x + 1) * SAMPLES + y + 1) * SAMPLES + z + 1) as uint] = d;
+                min = min.min(d);
+                max = max.max(d);
+            }
+        }
+    }
+    (samples, min, max)
+}
+
+/// Trilinear interpolation between the samples around a block
+///
+/// Offset by S so the padding blocks at -1 interpolate the same way as in
+/// the neighboring chunk.
+fn interpolate(samples: &[f64], block_x: int, block_y: int, block_z: int) -> f64 {
+    static S : int = DENSITY_STEP;
+    let fx = ((block_x+S) % S) as f64 / S as f64;
+    let fy = ((block_y+S) % S) as f64 / S as f64;
+    let fz = ((block_z+S) % S) as f64 / S as f64;
+    let x = (block_x+S)/S;
+    let y = (block_y+S)/S;
+    let z = (block_z+S)/S;
+    let at = |x: int, y: int, z: int| samples[((x * SAMPLES + y) * SAMPLES + z) as uint];
+
+    at(x, y, z) * (1.0-fx) * (1.0-fy) * (1.0-fz) +
+        at(x, y, z+1) * (1.0-fx) * (1.0-fy) * fz +
+        at(x, y+1, z) * (1.0-fx) * fy * (1.0-fz) +
+        at(x, y+1, z+1) * (1.0-fx) * fy * fz +
+        at(x+1, y, z) * fx * (1.0-fy) * (1.0-fz) +
+        at(x+1, y, z+1) * fx * (1.0-fy) * fz +
+        at(x+1, y+1, z) * fx * fy * (1.0-fz) +
+        at(x+1, y+1, z+1) * fx * fy * fz
+}
+
+impl Clone for Terrain {
+    fn clone(&self) -> Terrain {
+        Terrain {
+            palette: self.palette.clone(),
+            indices: self.indices.clone(),
+            bits_per_block: self.bits_per_block,
+        }
+    }
+}
+
+impl Clone for Block {
+    fn clone(&self) -> Block {
+        Block { blocktype: self.blocktype }
+    }
+}
+
+impl<'a> Deref<Block> for BlockMut<'a> {
+    fn deref<'b>(&'b self) -> &'b Block {
+        &self.block
+    }
+}
+
+impl<'a> DerefMut<Block> for BlockMut<'a> {
+    fn deref_mut<'b>(&'b mut self) -> &'b mut Block {
+        &mut self.block
+    }
+}
+
+#[unsafe_destructor]
+impl<'a> Drop for BlockMut<'a> {
+    fn drop(&mut self) {
+        let blocktype = self.block.blocktype;
+        self.terrain.set(self.x, self.y, self.z, blocktype);
+    }
+}
+
+impl Terrain {
+    /// All air
+    pub fn new() -> ~Terrain {
+        Terrain::uniform(BlockAir)
+    }
+
+    pub fn uniform(blocktype: BlockType) -> ~Terrain {
+        ~Terrain {
+            palette: ~[Block { blocktype: blocktype }],
+            indices: ~[],
+            bits_per_block: 0,
+        }
+    }
+
+    /// Build a chunk from the types of all NUM_PADDED_BLOCKS blocks, laid
+    /// out x, then y, then z from -1 to CHUNK_SIZE
+    ///
+    /// The palette is found first so the indices are packed once, at their
+    /// final width.
+    pub fn from_blocks(blocktypes: &[BlockType]) -> ~Terrain {
+        assert_eq!(blocktypes.len(), NUM_PADDED_BLOCKS);
+
+        // One more than the palette index of each block type seen
+        let mut lookup = [0u16, ..256];
+        let mut palette = ~[];
+        for &blocktype in blocktypes.iter() {
+            if lookup[blocktype as uint] == 0 {
+                palette.push(Block { blocktype: blocktype });
+                lookup[blocktype as uint] = palette.len() as u16;
+            }
+        }
+
+        let bits_per_block = if palette.len() == 1 {
+            0
+        } else if palette.len() <= 2 {
+            1
+        } else if palette.len() <= 4 {
+            2
+        } else if palette.len() <= 16 {
+            4
+        } else {
+            8
+        };
+
+        let mut indices = ~[];
+        if bits_per_block > 0 {
+            let per_word = 64 / bits_per_block;
+            indices = vec::from_elem((NUM_PADDED_BLOCKS + per_word - 1) / per_word, 0u64);
+            for (i, &blocktype) in blocktypes.iter().enumerate() {
+                let v = (lookup[blocktype as uint] - 1) as u64;
+                indices[i / per_word] |= v << ((i % per_word) * bits_per_block);
+            }
+        }
+
+        ~Terrain {
+            palette: palette,
+            indices: indices,
+            bits_per_block: bits_per_block,
+        }
+    }
+
+    /// The block type of every block if the palette has only one
+    ///
+    /// Blocks are never removed from the palette, so this can be None even
+    /// when all the blocks are the same.
+    pub fn uniform_type(&self) -> Option<BlockType> {
+        if self.palette.len() == 1 {
+            Some(self.palette[0].blocktype)
+        } else {
+            None
+        }
+    }
+
+    pub fn get<'a>(&'a self, x: int, y: int, z: int) -> &'a Block {
+        if self.bits_per_block == 0 {
+            return &self.palette[0];
+        }
+
+        let i = Terrain::index(x, y, z);
+        let per_word = 64 / self.bits_per_block;
+        let word = self.indices[i / per_word];
+        let mask = (1 << self.bits_per_block) - 1;
+        &self.palette[((word >> ((i % per_word) * self.bits_per_block)) & mask) as uint]
+    }
+
+    /// Blocks are packed, so this edits a copy that is written back with set
+    /// when the guard is dropped
+    pub fn get_mut<'a>(&'a mut self, x: int, y: int, z: int) -> BlockMut<'a> {
+        let block = self.get(x, y, z).clone();
+        BlockMut { terrain: self, x: x, y: y, z: z, block: block }
+    }
+
+    pub fn set(&mut self, x: int, y: int, z: int, blocktype: BlockType) {
+        let palette_index = match self.palette.iter().position(|b| b.blocktype == blocktype) {
+            Some(palette_index) => palette_index,
+            None => {
+                self.palette.push(Block { blocktype: blocktype });
+                if self.palette.len() > 1 << self.bits_per_block {
+                    let bits_per_block = if self.bits_per_block == 0 { 1 } else { self.bits_per_block * 2 };
+                    self.repack(bits_per_block);
+                }
+                self.palette.len() - 1
+            },
+        };
+
+        // Uniform and already that type
+        if self.bits_per_block == 0 {
+            return;
+        }
+
+        let i = Terrain::index(x, y, z);
+        let per_word = 64 / self.bits_per_block;
+        let shift = (i % per_word) * self.bits_per_block;
+        let mask = ((1u64 << self.bits_per_block) - 1) << shift;
+        let word = &mut self.indices[i / per_word];
+        *word = (*word & !mask) | ((palette_index as u64) << shift);
+    }
+
+    /// Heap and inline bytes used by this chunk
+    pub fn memory_size(&self) -> uint {
+        mem::size_of::<Terrain>() +
+            self.palette.len() * mem::size_of::<Block>() +
+            self.indices.len() * mem::size_of::<u64>()
+    }
+
+    /// Copy the indices into a wider packing
+    fn repack(&mut self, bits_per_block: uint) {
+        let per_word = 64 / bits_per_block;
+        let mut indices = vec::from_elem((NUM_PADDED_BLOCKS + per_word - 1) / per_word, 0u64);
+
+        if self.bits_per_block > 0 {
+            let old_per_word = 64 / self.bits_per_block;
+            let old_mask = (1u64 << self.bits_per_block) - 1;
+            for i in range(0, NUM_PADDED_BLOCKS) {
+                let v = (self.indices[i / old_per_word] >> ((i % old_per_word) * self.bits_per_block)) & old_mask;
+                indices[i / per_word] |= v << ((i % per_word) * bits_per_block);
+            }
+        }
+
+        self.indices = indices;
+        self.bits_per_block = bits_per_block;
+    }
+
+    fn index(x: int, y: int, z: int) -> uint {
+        static S : int = CHUNK_SIZE + 2;
+        ((x+1) * S * S + (y+1) * S + (z+1)) as uint
+    }
+}
+
+#[test]
+fn test_gen_chunk_borders() {
+    use registry::BlockRegistry;
+
+    let registry = BlockRegistry::builtin();
+    let generator = TerrainGenerator::new(&WorldConfig::default(), &registry);
+
+    // Chunks straddling the surface so decoration and caves both show up
+    let (_, height) = generator.surface(32.0, 16.0);
+    let s = CHUNK_SIZE as f64;
+    let y = (height / s).floor() * s;
+    let a = generator.gen(Vec3::new(0.0, y, 0.0));
+    let b = generator.gen(Vec3::new(s, y, 0.0));
+
+    for y in range(-1, CHUNK_SIZE+1) {
+        for z in range(-1, CHUNK_SIZE+1) {
+            assert_eq!(a.get(CHUNK_SIZE - 1, y, z).blocktype, b.get(-1, y, z).blocktype);
+            assert_eq!(a.get(CHUNK_SIZE, y, z).blocktype, b.get(0, y, z).blocktype);
+        }
+    }
+}
+
+#[test]
+fn test_gen_scaled_chunk_borders() {
+    use registry::BlockRegistry;
+
+    let registry = BlockRegistry::builtin();
+    let generator = TerrainGenerator::new(&WorldConfig::default(), &registry);
+
+    // Neighboring chunks at the same scale agree on their shared padding
+    let scale = 4;
+    let s = (CHUNK_SIZE * scale) as f64;
+    let (_, height) = generator.surface(s, s / 2.0);
+    let y = (height / s).floor() * s;
+    let a = generator.gen_scaled(Vec3::new(0.0, y, 0.0), scale);
+    let b = generator.gen_scaled(Vec3::new(s, y, 0.0), scale);
+
+    for y in range(-1, CHUNK_SIZE+1) {
+        for z in range(-1, CHUNK_SIZE+1) {
+            assert_eq!(a.get(CHUNK_SIZE - 1, y, z).blocktype, b.get(-1, y, z).blocktype);
+            assert_eq!(a.get(CHUNK_SIZE, y, z).blocktype, b.get(0, y, z).blocktype);
+        }
+    }
+}
+
+#[test]
+fn test_gen_uniform_above_surface() {
+    use registry::BlockRegistry;
+
+    let registry = BlockRegistry::builtin();
+    let generator = TerrainGenerator::new(&WorldConfig::default(), &registry);
+    let s = CHUNK_SIZE as f64;
+
+    // Climb from the chunk holding the surface to the first uniform one
+    let (_, height) = generator.surface(0.0, 0.0);
+    let mut y = (height / s).floor() * s;
+    let mut below = generator.gen(Vec3::new(0.0, y, 0.0));
+    loop {
+        assert!(y < 1000.0);
+        let above = generator.gen(Vec3::new(0.0, y + s, 0.0));
+        if above.uniform_type().is_some() {
+            assert_eq!(above.uniform_type(), Some(BlockAir));
+
+            // It agrees with the padding of the generated chunk below
+            for x in range(-1, CHUNK_SIZE+1) {
+                for z in range(-1, CHUNK_SIZE+1) {
+                    assert_eq!(below.get(x, CHUNK_SIZE, z).blocktype, BlockAir);
+                    assert_eq!(below.get(x, CHUNK_SIZE - 1, z).blocktype, above.get(x, -1, z).blocktype);
+                }
+            }
+            break;
+        }
+        below = above;
+        y += s;
+    }
+
+    assert_eq!(generator.gen(Vec3::new(0.0, 10000.0, 0.0)).uniform_type(), Some(BlockAir));
+}
+
+#[test]
+fn test_gen_uniform_deep() {
+    use registry::BlockRegistry;
+
+    // Without caves, and with ores that reach all the way down but never
+    // get past their threshold, nothing breaks up the stone
+    let mut config = WorldConfig::default();
+    config.cave_threshold = -10.0;
+    let mut registry = BlockRegistry::builtin();
+    for info in registry.blocks.mut_iter() {
+        match info.ore {
+            Some(ref mut ore) => ore.threshold = 2.0,
+            None => {},
+        }
+    }
+    let generator = TerrainGenerator::new(&config, &registry);
+    assert_eq!(generator.gen(Vec3::new(0.0, -2048.0, 0.0)).uniform_type(), Some(BlockStone));
+}
+
+#[test]
+fn test_terrain_palette() {
+    let mut t = Terrain::new();
+    assert_eq!(t.uniform_type(), Some(BlockAir));
+
+    // Setting the only type in the palette leaves the chunk uniform
+    t.set(0, 0, 0, BlockAir);
+    assert_eq!(t.uniform_type(), Some(BlockAir));
+
+    // Every block gets a different type from its neighbors, so the packing
+    // widens a few times
+    let types = [BlockStone, BlockDirt, BlockWater, BlockSand, BlockSnow, BlockGrass];
+    let type_at = |x: int, y: int, z: int| types[((x * 7 + y * 3 + z + 20) % 6) as uint];
+    for x in range(-1, CHUNK_SIZE+1) {
+        for y in range(-1, CHUNK_SIZE+1) {
+            for z in range(-1, CHUNK_SIZE+1) {
+                if (x + y + z) % 5 != 0 {
+                    t.set(x, y, z, type_at(x, y, z));
+                }
+            }
+        }
+    }
+
+    for x in range(-1, CHUNK_SIZE+1) {
+        for y in range(-1, CHUNK_SIZE+1) {
+            for z in range(-1, CHUNK_SIZE+1) {
+                let expected = if (x + y + z) % 5 != 0 { type_at(x, y, z) } else { BlockAir };
+                assert_eq!(t.get(x, y, z).blocktype, expected);
+            }
+        }
+    }
+
+    assert_eq!(t.uniform_type(), None);
+    assert!(t.memory_size() < NUM_PADDED_BLOCKS / 2 + 100);
+    assert!(Terrain::new().memory_size() < 100);
+}
+
+#[test]
+fn test_terrain_get_mut() {
+    let mut t = Terrain::new();
+
+    // Reading through the guard leaves the chunk uniform
+    assert_eq!(t.get_mut(1, 2, 3).blocktype, BlockAir);
+    assert_eq!(t.uniform_type(), Some(BlockAir));
+
+    t.get_mut(1, 2, 3).blocktype = BlockStone;
+    assert_eq!(t.get(1, 2, 3).blocktype, BlockStone);
+    assert_eq!(t.get(3, 2, 1).blocktype, BlockAir);
+    assert_eq!(t.uniform_type(), None);
+}
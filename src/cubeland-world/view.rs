@@ -0,0 +1,264 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate cgmath;
+
+use std::mem;
+use collections::HashSet;
+use collections::priority_queue::PriorityQueue;
+
+use cgmath::vector::{Vector, EuclideanVector};
+use cgmath::vector::Vec3;
+
+use chunk::{world_to_chunk, nearby_chunk_coords};
+
+/// The load queue is reordered when the camera turns further than this from
+/// the direction it was last ordered for (cos 30 degrees)
+static REPRIORITIZE_COS : f64 = 0.866;
+
+/// Chunks within the view radius of the camera, updated incrementally
+///
+/// The set is only recomputed when the camera moves into another chunk.
+/// Chunks that enter the set wait in a queue ordered by distance, with
+/// chunks in front of the camera preferred over those behind it.
+pub struct ViewSet {
+    /// Offsets within the radius, nearest first
+    offsets: ~[Vec3<i64>],
+    /// Rows of chunks kept at any distance, with offsets within the given
+    /// radius kept whatever their row
+    band: Option<(i64, i64, i64)>,
+    center: Option<Vec3<i64>>,
+    /// Chunks in the set, nearest first
+    coords: ~[Vec3<i64>],
+    members: HashSet<(i64, i64, i64)>,
+    queue: PriorityQueue<QueuedChunk>,
+    /// Direction the queue was last ordered for
+    queue_direction: Vec3<f64>,
+}
+
+/// Chunks that entered and left the view set in one update
+pub struct ViewChange {
+    entered: ~[Vec3<i64>],
+    left: ~[Vec3<i64>],
+    /// Whether the costs of the chunks in the set changed
+    reprioritized: bool,
+}
+
+impl ViewSet {
+    pub fn new(radius: uint) -> ViewSet {
+        ViewSet {
+            offsets: nearby_chunk_coords(Vec3::new(0.0, 0.0, 0.0), radius),
+            band: None,
+            center: None,
+            coords: ~[],
+            members: HashSet::new(),
+            queue: PriorityQueue::new(),
+            queue_direction: Vec3::new(0.0, 0.0, -1.0),
+        }
+    }
+
+    /// A view set that leaves out chunks outside rows min_y to max_y,
+    /// except for those within near chunks of the center
+    pub fn banded(radius: uint, near: uint, min_y: i64, max_y: i64) -> ViewSet {
+        ViewSet { band: Some((min_y, max_y, near as i64)), ..ViewSet::new(radius) }
+    }
+
+    /// Move the camera
+    ///
+    /// The returned change is empty unless the camera crossed into another
+    /// chunk.
+    pub fn update(&mut self, position: Vec3<f64>, direction: Vec3<f64>) -> ViewChange {
+        let p = Vec3::new(position.x.floor() as i64, position.y.floor() as i64, position.z.floor() as i64);
+        let (center, _) = world_to_chunk(p);
+
+        let mut change = ViewChange { entered: ~[], left: ~[], reprioritized: false };
+
+        if self.center != Some(center) {
+            let band = self.band;
+            let coords : ~[Vec3<i64>] = self.offsets.iter().filter(|o| {
+                match band {
+                    Some((min_y, max_y, near)) => {
+                        let y = center.y + o.y;
+                        (y >= min_y && y <= max_y) || o.dot(*o) <= near*near
+                    },
+                    None => true,
+                }
+            }).map(|o| o.add_v(&center)).collect();
+            let mut members = HashSet::with_capacity(coords.len());
+            for c in coords.iter() {
+                members.insert((c.x, c.y, c.z));
+                if !self.members.contains(&(c.x, c.y, c.z)) {
+                    change.entered.push(*c);
+                }
+            }
+            for c in self.coords.iter() {
+                if !members.contains(&(c.x, c.y, c.z)) {
+                    change.left.push(*c);
+                }
+            }
+
+            self.center = Some(center);
+            self.coords = coords;
+            self.members = members;
+            self.reprioritize(direction, change.entered);
+            change.reprioritized = true;
+        } else if direction.dot(&self.queue_direction) < REPRIORITIZE_COS {
+            self.reprioritize(direction, &[]);
+            change.reprioritized = true;
+        }
+
+        change
+    }
+
+    /// Chunks in the set, nearest first
+    pub fn coords<'a>(&'a self) -> &'a [Vec3<i64>] {
+        self.coords.as_slice()
+    }
+
+    /// Loading order of a chunk; lower goes first
+    pub fn cost(&self, c: Vec3<i64>) -> i64 {
+        chunk_cost(c, self.center.unwrap_or(Vec3::new(0, 0, 0)), self.queue_direction)
+    }
+
+    pub fn contains(&self, c: Vec3<i64>) -> bool {
+        self.members.contains(&(c.x, c.y, c.z))
+    }
+
+    /// Take the most important chunk that entered the set and has not been
+    /// taken yet
+    pub fn pop(&mut self) -> Option<Vec3<i64>> {
+        loop {
+            match self.queue.maybe_pop() {
+                // Chunks that left the set are dropped lazily
+                Some(queued) => if self.contains(queued.coord) {
+                    return Some(queued.coord);
+                },
+                None => return None,
+            }
+        }
+    }
+
+    /// Rebuild the queue with priorities for the current center and the
+    /// given direction, adding new chunks
+    fn reprioritize(&mut self, direction: Vec3<f64>, new: &[Vec3<i64>]) {
+        let old = mem::replace(&mut self.queue, PriorityQueue::new()).to_vec();
+        let center = self.center.unwrap_or(Vec3::new(0, 0, 0));
+
+        let mut items = ~[];
+        for queued in old.iter() {
+            if self.contains(queued.coord) {
+                items.push(QueuedChunk { coord: queued.coord, cost: chunk_cost(queued.coord, center, direction) });
+            }
+        }
+        for &c in new.iter() {
+            items.push(QueuedChunk { coord: c, cost: chunk_cost(c, center, direction) });
+        }
+
+        self.queue = PriorityQueue::from_vec(items);
+        self.queue_direction = direction;
+    }
+}
+
+/// A chunk waiting to be loaded, ordered so the lowest cost pops first
+struct QueuedChunk {
+    coord: Vec3<i64>,
+    cost: i64,
+}
+
+/// Distance from the camera's chunk, tripled for chunks directly behind the
+/// camera, in thousandths of a chunk
+fn chunk_cost(c: Vec3<i64>, center: Vec3<i64>, direction: Vec3<f64>) -> i64 {
+    let o = c.sub_v(&center);
+    let o = Vec3::new(o.x as f64, o.y as f64, o.z as f64);
+    let distance = o.length();
+    let cost = if distance == 0.0 {
+        0.0
+    } else {
+        distance * (2.0 - o.dot(&direction) / distance)
+    };
+    (cost * 1000.0) as i64
+}
+
+impl Eq for QueuedChunk {
+    fn eq(&self, other: &QueuedChunk) -> bool {
+        self.cost == other.cost
+    }
+}
+
+impl Ord for QueuedChunk {
+    fn lt(&self, other: &QueuedChunk) -> bool {
+        self.cost > other.cost
+    }
+}
+
+#[test]
+fn test_view_set_update() {
+    let mut view = ViewSet::new(4);
+    let forward = Vec3::new(0.0, 0.0, -1.0);
+
+    let change = view.update(Vec3::new(1.0, 2.0, 3.0), forward);
+    assert_eq!(change.entered.len(), view.coords().len());
+    assert!(change.left.is_empty());
+    assert_eq!(view.coords()[0], Vec3::new(0, 0, 0));
+
+    // Same chunk
+    let change = view.update(Vec3::new(31.0, 2.0, 3.0), forward);
+    assert!(change.entered.is_empty() && change.left.is_empty());
+
+    // One chunk over, in the negative direction
+    let change = view.update(Vec3::new(-0.5, 2.0, 3.0), forward);
+    assert!(!change.entered.is_empty());
+    assert_eq!(change.entered.len(), change.left.len());
+    assert_eq!(view.coords()[0], Vec3::new(-1, 0, 0));
+    for &c in change.entered.iter() {
+        assert!(view.contains(c));
+    }
+    for &c in change.left.iter() {
+        assert!(!view.contains(c));
+    }
+}
+
+#[test]
+fn test_view_set_priority() {
+    let mut view = ViewSet::new(4);
+    view.update(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+
+    assert_eq!(view.pop(), Some(Vec3::new(0, 0, 0)));
+    assert_eq!(view.pop(), Some(Vec3::new(1, 0, 0)));
+
+    // Turning around puts the chunks behind first
+    view.update(Vec3::new(0.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0));
+    assert_eq!(view.pop(), Some(Vec3::new(-1, 0, 0)));
+
+    let mut n = 3;
+    while view.pop().is_some() {
+        n += 1;
+    }
+    assert_eq!(n, view.coords().len());
+}
+
+#[test]
+fn test_view_set_banded() {
+    let mut view = ViewSet::banded(4, 1, -1, 0);
+    let change = view.update(Vec3::new(0.0, 100.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+    assert_eq!(change.entered.len(), view.coords().len());
+
+    // The center chunk (0, 3, 0) and its neighbors above the band are kept
+    assert!(view.contains(Vec3::new(0, 3, 0)));
+    assert!(view.contains(Vec3::new(0, 2, 0)));
+    assert!(!view.contains(Vec3::new(0, 1, 0)));
+    assert!(view.contains(Vec3::new(1, 0, 0)));
+    assert!(view.contains(Vec3::new(2, 0, 0)));
+    assert!(view.coords().iter().all(|c| c.y <= 0 || c.y >= 2));
+}
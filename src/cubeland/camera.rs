@@ -22,14 +22,27 @@ use cgmath::vector::Vector;
 use cgmath::vector::Vec2;
 use cgmath::vector::Vec3;
 
+use world::chunk::BlockSource;
+use world::physics::Player;
+use world::physics::EYE_HEIGHT;
+
 static CAMERA_SPEED : f64 = 30.0;
 static FAST_MULTIPLIER : f64 = 10.0;
+static WALK_SPEED : f64 = 4.5;
+static RUN_MULTIPLIER : f64 = 2.0;
+
+pub enum CameraMode {
+    FlyMode,
+    WalkMode,
+}
 
 pub struct Camera {
     position : Vec3<f64>,
     velocity : Vec3<f64>,
     angle : Vec2<f64>,
     fast : bool,
+    mode : CameraMode,
+    player : Player,
 }
 
 impl Camera {
@@ -39,9 +52,21 @@ impl Camera {
             velocity: Vec3::zero(),
             angle: Vec2::zero(),
             fast: false,
+            mode: FlyMode,
+            player: Player::new(position.sub_v(&Vec3::new(0.0, EYE_HEIGHT, 0.0))),
         }
     }
 
+    pub fn toggle_mode(&mut self) {
+        self.mode = match self.mode {
+            FlyMode => {
+                self.player = Player::new(self.position.sub_v(&Vec3::new(0.0, EYE_HEIGHT, 0.0)));
+                WalkMode
+            },
+            WalkMode => FlyMode,
+        };
+    }
+
     pub fn accelerate(&mut self, acceleration: Vec3<f64>) {
         self.velocity.add_self_v(&acceleration);
     }
@@ -50,12 +75,33 @@ impl Camera {
         self.fast = fast;
     }
 
+    /// Move and turn the camera directly, as when replaying a recorded path
+    pub fn set_pose(&mut self, position: Vec3<f64>, angle: Vec2<f64>) {
+        self.position = position;
+        self.angle = angle;
+        self.player = Player::new(position.sub_v(&Vec3::new(0.0, EYE_HEIGHT, 0.0)));
+    }
+
     pub fn look(&mut self, cursor: Vec2<f64>) {
         self.angle.x = ((cursor.y * 0.0005) % 1.0) * std::f64::consts::PI * 2.0;
         self.angle.y = ((cursor.x * 0.0005) % 1.0) * std::f64::consts::PI * 2.0;
     }
 
-    pub fn tick(&mut self, tick_length: f64) {
+    /// Unit vector pointing where the camera is looking
+    pub fn direction(&self) -> Vec3<f64> {
+        let inv_rotation_x = Mat3::<f64>::from_angle_x(rad(-self.angle.x));
+        let inv_rotation_y = Mat3::<f64>::from_angle_y(rad(-self.angle.y));
+        inv_rotation_y.mul_m(&inv_rotation_x).mul_v(&Vec3::new(0.0, 0.0, -1.0))
+    }
+
+    pub fn tick<S: BlockSource>(&mut self, source: &S, tick_length: f64) {
+        match self.mode {
+            FlyMode => self.tick_fly(tick_length),
+            WalkMode => self.tick_walk(source, tick_length),
+        }
+    }
+
+    fn tick_fly(&mut self, tick_length: f64) {
         let mut speed = CAMERA_SPEED;
         if self.fast {
             speed *= FAST_MULTIPLIER;
@@ -65,4 +111,20 @@ impl Camera {
         let absolute_camera_velocity = inv_camera_rotation.mul_v(&self.velocity).mul_s(speed).mul_s(tick_length);
         self.position.add_self_v(&absolute_camera_velocity);
     }
+
+    /// Walking ignores pitch: forward input moves along the ground and
+    /// upward input jumps
+    fn tick_walk<S: BlockSource>(&mut self, source: &S, tick_length: f64) {
+        let mut speed = WALK_SPEED;
+        if self.fast {
+            speed *= RUN_MULTIPLIER;
+        }
+
+        let inv_yaw = Mat3::<f64>::from_angle_y(rad(-self.angle.y));
+        let walk = inv_yaw.mul_v(&Vec3::new(self.velocity.x, 0.0, self.velocity.z)).mul_s(speed);
+        let jump = self.velocity.y > 0.0;
+
+        self.player.tick(source, walk, jump, tick_length);
+        self.position = self.player.position.add_v(&Vec3::new(0.0, EYE_HEIGHT, 0.0));
+    }
 }
@@ -0,0 +1,132 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+extern crate gl;
+
+use std;
+use std::io::File;
+
+use gl::types::*;
+
+use cgmath::vector::Vec2;
+use cgmath::vector::Vec3;
+
+use camera::Camera;
+use fbo::FBO;
+use frameprofiler::FrameProfiler;
+use renderer::Renderer;
+use world::chunk::ChunkLoader;
+use world::png;
+
+/// Renders the camera's view offscreen and saves it as a PNG
+///
+/// Only core framebuffer objects are used, so this works on software
+/// renderers like Mesa's llvmpipe and needs no visible window.
+pub struct Capture {
+    fbo : FBO,
+    size : Vec2<u32>,
+}
+
+impl Capture {
+    pub fn new(size : Vec2<u32>) -> Result<Capture, ~str> {
+        let mut max_size = 0;
+        unsafe { gl::GetIntegerv(gl::MAX_TEXTURE_SIZE, &mut max_size); }
+        if size.x == 0 || size.y == 0 || size.x > max_size as u32 || size.y > max_size as u32 {
+            return Err(format!("capture size {}x{} not supported, the limit is {}",
+                               size.x, size.y, max_size));
+        }
+
+        let fbo = FBO::with_size(size.x as GLint, size.y as GLint);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, fbo.fbo);
+        let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        if status != gl::FRAMEBUFFER_COMPLETE {
+            return Err(format!("incomplete capture framebuffer: {:x}", status));
+        }
+
+        Ok(Capture {
+            fbo: fbo,
+            size: size,
+        })
+    }
+
+    /// Render the camera's view and write it to a PNG file
+    pub fn save(
+            &self,
+            renderer : &mut Renderer,
+            chunk_loader : &ChunkLoader,
+            camera : &Camera,
+            profiler : &mut FrameProfiler,
+            path : &Path) -> Result<(), ~str>
+    {
+        profiler.begin("capture");
+        {
+            let (chunks, lod_chunks) = chunk_loader.visible_chunks();
+            renderer.render_to(
+                self.fbo.fbo,
+                self.size,
+                chunks,
+                lod_chunks,
+                Vec3 { x: camera.position.x as f32, y: camera.position.y as f32, z: camera.position.z as f32 },
+                camera.angle,
+                profiler);
+        }
+
+        let (width, height) = (self.size.x as uint, self.size.y as uint);
+        let mut pixels = std::vec::from_elem(width * height * 4, 0u8);
+        gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.fbo.fbo);
+        gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+        unsafe {
+            gl::ReadPixels(0, 0, width as GLsizei, height as GLsizei,
+                           gl::RGBA, gl::UNSIGNED_BYTE,
+                           pixels.as_mut_ptr() as *mut GLvoid);
+        }
+        gl::BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
+        profiler.end();
+
+        // Translucent blocks leave holes in the alpha channel
+        for i in std::iter::range_step(3, pixels.len(), 4) {
+            pixels[i] = 255;
+        }
+
+        let data = png::encode(width, height, png::flip_rows(width, pixels));
+        match File::create(path).write(data) {
+            Ok(()) => Ok(()),
+            Err(e) => Err(format!("{}", e)),
+        }
+    }
+}
+
+/// Parse a capture size like "1920x1080"
+pub fn parse_size(s : &str) -> Option<Vec2<u32>> {
+    let parts : ~[&str] = s.split('x').collect();
+    match parts.as_slice() {
+        [w, h] => match (from_str::<u32>(w), from_str::<u32>(h)) {
+            (Some(x), Some(y)) if x > 0 && y > 0 => Some(Vec2 { x: x, y: y }),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The first of screenshot-1.png, screenshot-2.png, ... that doesn't exist
+pub fn next_screenshot_path() -> Path {
+    let mut n = 1;
+    loop {
+        let path = Path::new(format!("screenshot-{}.png", n));
+        if !path.exists() {
+            return path;
+        }
+        n += 1;
+    }
+}
@@ -26,6 +26,10 @@ pub struct FBO {
 
 impl FBO {
     pub fn new(size: GLint) -> FBO {
+        FBO::with_size(size, size)
+    }
+
+    pub fn with_size(width: GLint, height: GLint) -> FBO {
         //RGBA8 2D texture
         let mut color_tex = 0;
         unsafe { gl::GenTextures(1, &mut color_tex); }
@@ -34,20 +38,22 @@ impl FBO {
         gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::REPEAT as GLint);
         gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as GLint);
         gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as GLint);
-        unsafe { gl::TexImage2D(gl::TEXTURE_2D, 0, gl::RGBA8 as GLint, size, size, 0, gl::BGRA, gl::UNSIGNED_BYTE, std::ptr::null()); }
+        unsafe { gl::TexImage2D(gl::TEXTURE_2D, 0, gl::RGBA8 as GLint, width, height, 0, gl::BGRA, gl::UNSIGNED_BYTE, std::ptr::null()); }
 
         // 24 bit depth texture
         let mut depth_tex = 0;
         unsafe { gl::GenTextures(1, &mut depth_tex); }
         gl::BindTexture(gl::TEXTURE_2D, depth_tex);
-        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::REPEAT as GLint);
-        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::REPEAT as GLint);
-        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as GLint);
-        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as GLint);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
+        // Linear filtering blends neighbouring depth comparisons, softening
+        // shadow edges
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
         gl::TexParameteri(gl::TEXTURE_2D, gl::DEPTH_TEXTURE_MODE, gl::INTENSITY as GLint);
         gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_COMPARE_MODE, gl::COMPARE_R_TO_TEXTURE as GLint);
         gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_COMPARE_FUNC, gl::LEQUAL as GLint);
-        unsafe { gl::TexImage2D(gl::TEXTURE_2D, 0, gl::DEPTH_COMPONENT24 as GLint, size, size, 0, gl::DEPTH_COMPONENT, gl::UNSIGNED_BYTE, std::ptr::null()); }
+        unsafe { gl::TexImage2D(gl::TEXTURE_2D, 0, gl::DEPTH_COMPONENT24 as GLint, width, height, 0, gl::DEPTH_COMPONENT, gl::UNSIGNED_BYTE, std::ptr::null()); }
 
         let mut fbo = 0;
         unsafe { gl::GenFramebuffers(1, &mut fbo); }
@@ -0,0 +1,126 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+extern crate gl;
+
+use std;
+use std::io::IoResult;
+
+use time::precise_time_ns;
+
+use gltimer::GLTimer;
+use world::profiler::{Profiler, ScopeSummary};
+
+/// Times named scopes on the CPU and, with timestamp queries, on the GPU
+///
+/// Queries are double buffered: each frame's are read back two frames
+/// later, once the GPU is done with them, so the CPU never waits. Results
+/// still missing by then are dropped.
+pub struct FrameProfiler {
+    profiler : Profiler,
+    /// Timers of the last two frames, by frame number parity
+    slots : ~[QuerySlot],
+    /// Number of the frame in progress
+    frame : u64,
+    /// Off when the driver lacks timestamp queries
+    gpu_enabled : bool,
+}
+
+struct QuerySlot {
+    frame : Option<u64>,
+    /// Indexed by scope, reused from frame to frame
+    timers : ~[GLTimer],
+    /// Timers started this time around
+    used : uint,
+}
+
+impl FrameProfiler {
+    pub fn new() -> FrameProfiler {
+        let gpu_enabled = gl::QueryCounter::is_loaded() && gl::GetQueryObjectui64v::is_loaded();
+        if !gpu_enabled {
+            println!("Timestamp queries unavailable, profiling the CPU only");
+        }
+
+        FrameProfiler {
+            profiler: Profiler::new(),
+            slots: ~[QuerySlot { frame: None, timers: ~[], used: 0 },
+                     QuerySlot { frame: None, timers: ~[], used: 0 }],
+            frame: 0,
+            gpu_enabled: gpu_enabled,
+        }
+    }
+
+    pub fn begin_frame(&mut self) {
+        self.frame = self.profiler.begin_frame(precise_time_ns());
+
+        let slot = &mut self.slots[self.frame as uint % 2];
+        match slot.frame {
+            Some(frame) => {
+                for (index, timer) in slot.timers.slice_to(slot.used).iter().enumerate() {
+                    if !timer.available() {
+                        continue;
+                    }
+                    let (start, end) = timer.timestamps();
+                    self.profiler.set_gpu_time(frame, index, start, end);
+                }
+            },
+            None => {},
+        }
+        slot.frame = Some(self.frame);
+        slot.used = 0;
+    }
+
+    pub fn end_frame(&mut self) {
+        self.profiler.end_frame(precise_time_ns());
+    }
+
+    /// Open a scope inside the innermost open one
+    pub fn begin(&mut self, name : &str) {
+        let index = self.profiler.begin(name, precise_time_ns());
+        if !self.gpu_enabled {
+            return;
+        }
+
+        let slot = &mut self.slots[self.frame as uint % 2];
+        while slot.timers.len() <= index {
+            slot.timers.push(GLTimer::new());
+        }
+        slot.timers[index].start();
+        slot.used = slot.used.max(index + 1);
+    }
+
+    /// Close the innermost open scope
+    pub fn end(&mut self) {
+        let index = self.profiler.end(precise_time_ns());
+        if !self.gpu_enabled {
+            return;
+        }
+
+        self.slots[self.frame as uint % 2].timers[index].finish();
+    }
+
+    pub fn summary(&self) -> ~[ScopeSummary] {
+        self.profiler.summary()
+    }
+
+    /// Average frame time in milliseconds
+    pub fn frame_ms(&self) -> Option<f64> {
+        self.profiler.frame_ms()
+    }
+
+    /// Write the recorded frames as a Chrome trace
+    pub fn write_trace(&self, path : &Path) -> IoResult<()> {
+        let mut file = try!(std::io::File::create(path));
+        file.write_str(self.profiler.chrome_trace())
+    }
+}
@@ -16,6 +16,7 @@ extern crate gl;
 
 use gl::types::*;
 
+/// Pair of timestamp queries around some GL commands
 pub struct GLTimer {
     before_query : GLuint,
     after_query : GLuint,
@@ -29,20 +30,45 @@ impl GLTimer {
     }
 
     pub fn start(&self) {
-        gl::QueryCounter(gl::TIMESTAMP, self.before_query);
+        gl::QueryCounter(self.before_query, gl::TIMESTAMP);
     }
 
     pub fn finish(&self) {
-        gl::QueryCounter(gl::TIMESTAMP, self.after_query);
+        gl::QueryCounter(self.after_query, gl::TIMESTAMP);
     }
 
-    pub fn elapsed(&self) -> GLuint {
-        let mut before_time : GLuint = 0;
-        let mut after_time : GLuint = 0;
+    /// Whether the results can be read without waiting on the GPU
+    pub fn available(&self) -> bool {
+        let mut available : GLint = 0;
         unsafe {
-            gl::GetQueryObjectuiv(self.before_query, gl::QUERY_RESULT, &mut before_time);
-            gl::GetQueryObjectuiv(self.after_query, gl::QUERY_RESULT, &mut after_time);
+            gl::GetQueryObjectiv(self.after_query, gl::QUERY_RESULT_AVAILABLE, &mut available);
         }
-        before_time - after_time
+        available != 0
+    }
+
+    /// GPU timestamps in nanoseconds when start and finish ran
+    ///
+    /// Blocks until the GPU gets there.
+    pub fn timestamps(&self) -> (u64, u64) {
+        let mut before_time : GLuint64 = 0;
+        let mut after_time : GLuint64 = 0;
+        unsafe {
+            gl::GetQueryObjectui64v(self.before_query, gl::QUERY_RESULT, &mut before_time);
+            gl::GetQueryObjectui64v(self.after_query, gl::QUERY_RESULT, &mut after_time);
+        }
+        (before_time as u64, after_time as u64)
+    }
+
+    /// Nanoseconds between start and finish
+    pub fn elapsed(&self) -> u64 {
+        let (before_time, after_time) = self.timestamps();
+        after_time - before_time
+    }
+}
+
+impl Drop for GLTimer {
+    fn drop(&mut self) {
+        let queries = [self.before_query, self.after_query];
+        unsafe { gl::DeleteQueries(2, &queries[0]) };
     }
 }
@@ -17,12 +17,14 @@
 
 extern crate native;
 extern crate collections;
-extern crate sync;
 extern crate time;
 extern crate glfw;
 extern crate gl;
 extern crate cgmath;
-extern crate noise;
+extern crate getopts;
+extern crate world = "cubeland-world";
+
+use std::io::net::ip::SocketAddr;
 
 use time::precise_time_ns;
 
@@ -35,8 +37,19 @@ use cgmath::vector::Vector;
 use cgmath::vector::Vec2;
 use cgmath::vector::Vec3;
 
-use chunk::Chunk;
-use chunk::ChunkLoader;
+use world::chunk::ChunkLoader;
+use world::client::Client;
+use world::config::WorldConfig;
+use world::mesh;
+use world::raycast::raycast;
+use world::region::RegionStore;
+use world::registry::BlockRegistry;
+use world::terrain::BlockAir;
+use world::terrain::BlockStone;
+use world::ratelimiter::RateLimiter;
+use world::shadow::ShadowConfig;
+use world::metrics::{LogError, Metrics, MetricsConfig, MetricsDumper, Registry};
+use world::camerapath::{CameraPath, PathSample, Playback, FrameTimes};
 
 #[cfg(target_os = "linux")]
 #[link(name="GLU")]
@@ -44,31 +57,123 @@ use chunk::ChunkLoader;
 extern {}
 
 mod offset_of;
-mod chunk;
-mod ratelimiter;
 mod texture;
 mod renderer;
+mod fbo;
+mod gltimer;
+mod frameprofiler;
+mod overlay;
 mod camera;
-mod terrain;
-mod mesh;
+mod capture;
 
-pub static VISIBLE_RADIUS: uint = 8;
-pub static CHUNK_SIZE: int = 32;
-pub static WORLD_SEED: u32 = 42;
+static PICK_DISTANCE: f64 = 64.0;
 
 static DEFAULT_WINDOW_SIZE : Vec2<u32> = Vec2 { x: 800, y: 600 };
 
+/// Where the profiler writes Chrome traces
+static TRACE_PATH : &'static str = "trace.json";
+
+/// Seconds of a recorded camera path covered by each frame of playback
+static PLAYBACK_STEP : f64 = 1.0 / 60.0;
+
+/// Longest to wait for chunks to load before a capture, in nanoseconds
+static SETTLE_TIMEOUT : u64 = 60*1000*1000*1000;
+
 #[start]
 fn start(argc: int, argv: **u8) -> int {
     native::start(argc, argv, main)
 }
 
 fn main() {
+    let args = std::os::args();
+    let mut opts = WorldConfig::opts();
+    opts.push_all_move(ShadowConfig::opts());
+    opts.push_all_move(MetricsConfig::opts());
+    opts.push(getopts::optopt("", "connect", "play on a server instead of a local world", "ADDR:PORT"));
+    opts.push(getopts::optopt("", "record-path", "record the camera's path to FILE", "FILE"));
+    opts.push(getopts::optopt("", "play-path", "fly the camera along a recorded path", "FILE"));
+    opts.push(getopts::optflag("", "benchmark", "with --play-path, draw as fast as possible, quit at the end of the path and report frame times"));
+    opts.push(getopts::optopt("", "screenshot", "save the starting view as a PNG and quit", "FILE"));
+    opts.push(getopts::optopt("", "capture-dir", "with --play-path, save every frame along the path as DIR/frame-NNNNN.png and quit", "DIR"));
+    opts.push(getopts::optopt("", "capture-size", "resolution of captures, defaults to the window's", "WxH"));
+    opts.push(getopts::optflag("h", "help", "show this help"));
+    let brief = format!("Usage: {} [options]", args[0]);
+
+    let matches = match getopts::getopts(args.tail(), opts) {
+        Ok(m) => m,
+        Err(f) => fail!("{}\n\n{}", f.to_err_msg(), getopts::usage(brief, opts)),
+    };
+
+    if matches.opt_present("h") {
+        println!("{}", getopts::usage(brief, opts));
+        return;
+    }
+
+    let config = match WorldConfig::from_matches(&matches) {
+        Ok(config) => config,
+        Err(msg) => fail!("Error in world config: {}", msg),
+    };
+
+    let shadow_config = match ShadowConfig::from_matches(&matches) {
+        Ok(config) => config,
+        Err(msg) => fail!("Error in shadow config: {}", msg),
+    };
+
+    let metrics_config = match MetricsConfig::from_matches(&matches) {
+        Ok(config) => config,
+        Err(msg) => fail!("Error in metrics config: {}", msg),
+    };
+    let metrics = Metrics::new(metrics_config.log_level);
+    let mut metrics_dumper = match MetricsDumper::open(&metrics_config) {
+        Ok(dumper) => dumper,
+        Err(e) => fail!("Error opening metrics file: {}", e),
+    };
+
+    let camera_path = matches.opt_str("play-path").map(|s| {
+        match CameraPath::load(&Path::new(s)) {
+            Ok(path) if !path.samples().is_empty() => path,
+            Ok(_) => fail!("Camera path is empty"),
+            Err(msg) => fail!("Error loading camera path: {}", msg),
+        }
+    });
+    let benchmark = matches.opt_present("benchmark");
+    if benchmark && camera_path.is_none() {
+        fail!("--benchmark needs --play-path");
+    }
+    let record_path = matches.opt_str("record-path").map(|s| Path::new(s));
+
+    let screenshot_path = matches.opt_str("screenshot").map(|s| Path::new(s));
+    let capture_dir = matches.opt_str("capture-dir").map(|s| Path::new(s));
+    if capture_dir.is_some() && camera_path.is_none() {
+        fail!("--capture-dir needs --play-path");
+    }
+    let capture_size = matches.opt_str("capture-size").map(|s| {
+        match capture::parse_size(s) {
+            Some(size) => size,
+            None => fail!("Invalid capture size: {}", s),
+        }
+    });
+    // Nothing is shown when capturing from the command line
+    let headless = screenshot_path.is_some() || capture_dir.is_some();
+
+    let server_addr = matches.opt_str("connect").map(|s| {
+        match from_str::<SocketAddr>(s) {
+            Some(addr) => addr,
+            None => fail!("Invalid server address: {}", s),
+        }
+    });
+
    let (glfw, errors) = glfw::init().unwrap();
    glfw::fail_on_error(&errors);
 
    if true {
-        glfw.window_hint(glfw::Samples(8));
+        // Captures are drawn offscreen without multisampling, which software
+        // renderers under Xvfb may not offer for windows anyway
+        if headless {
+            glfw.window_hint(glfw::Visible(false));
+        } else {
+            glfw.window_hint(glfw::Samples(8));
+        }
 
         let (window, events) = glfw.create_window(
             DEFAULT_WINDOW_SIZE.x, DEFAULT_WINDOW_SIZE.y,
@@ -81,39 +186,120 @@ fn main() {
 
         gl::load_with(|x| glfw.get_proc_address(x));
 
-        glfw.set_swap_interval(1);
-
-        let mut renderer = renderer::Renderer::new(DEFAULT_WINDOW_SIZE);
-
-        let mut chunk_loader = ChunkLoader::new(WORLD_SEED);
+        // Benchmarks measure how fast frames can be drawn, not the refresh rate
+        glfw.set_swap_interval(if benchmark || headless { 0 } else { 1 });
+
+        let registry = match BlockRegistry::load(&Path::new("data/blocks.json")) {
+            Ok(registry) => registry,
+            Err(msg) => fail!("Error loading block registry: {}", msg),
+        };
+
+        let mut renderer = renderer::Renderer::new(DEFAULT_WINDOW_SIZE, &registry, &shadow_config);
+        let mut window_size = DEFAULT_WINDOW_SIZE;
+
+        let mut profiler = frameprofiler::FrameProfiler::new();
+        let mut overlay = match overlay::Overlay::new() {
+            Ok(overlay) => overlay,
+            Err(msg) => fail!("Error loading overlay: {}", msg),
+        };
+
+        let mut chunk_loader = match server_addr {
+            Some(addr) => {
+                let client = match Client::connect(addr) {
+                    Ok(client) => client,
+                    Err(e) => fail!("Error connecting to {}: {}", addr, e),
+                };
+                metrics.info(format!("Connected to {} as player {}", addr, client.player_id()));
+                ChunkLoader::new_remote(&config, client, registry)
+            },
+            None => {
+                let store = match RegionStore::new(Path::new(format!("world/{}", config.storage_name(&registry)))) {
+                    Ok(store) => store,
+                    Err(e) => fail!("Error opening world storage: {}", e),
+                };
+                ChunkLoader::new(&config, store, registry)
+            },
+        };
+
+        chunk_loader.set_metrics(metrics.clone());
+        renderer.set_view_distance(chunk_loader.view_distance() as f32);
 
         let mut camera = camera::Camera::new(Vec3::new(0.0, 20.0, 00.0));
+        match camera_path {
+            Some(ref path) => {
+                let (position, angle) = path.sample_at(0.0).unwrap();
+                camera.set_pose(position, angle);
+            },
+            None => {},
+        }
+        let mut playback = camera_path.map(|path| Playback::new(path, PLAYBACK_STEP));
+        let mut recording = CameraPath::new();
+        let mut frame_times = FrameTimes::new();
+
+        let mut sequence = capture_dir.map(|dir| {
+            match std::io::fs::mkdir_recursive(&dir, std::io::UserDir) {
+                Ok(()) => {},
+                Err(e) => fail!("Error creating {}: {}", dir.display(), e),
+            }
+            match capture::Capture::new(capture_size.unwrap_or(DEFAULT_WINDOW_SIZE)) {
+                Ok(capture) => (dir, capture, 0u),
+                Err(msg) => fail!("Error setting up capture: {}", msg),
+            }
+        });
 
-        let mut fps_display_limiter = ratelimiter::RateLimiter::new(1000*1000*1000);
+        let mut fps_display_limiter = RateLimiter::new(1000*1000*1000);
+        let mut position_limiter = RateLimiter::new(100*1000*1000);
         let mut fps_frame_counter = 0;
 
-        let mut last_tick = precise_time_ns();
-
         let mut grabbed = true;
 
+        // Kept between F2 presses and rebuilt when the capture size changes
+        let mut screenshot_capture : Option<capture::Capture> = None;
+        let mut screenshot_requested = false;
+
         // Preload chunks
         {
             let deadline = precise_time_ns() + 1000*1000*100;
-            request_nearby_chunks(&mut chunk_loader, camera.position);
+            chunk_loader.set_view(camera.position, camera.direction());
             while precise_time_ns() < deadline {
                 chunk_loader.work();
                 std::task::deschedule();
             }
-            println!("Preloaded {} chunks", chunk_loader.cache.len());
+            metrics.info(format!("Preloaded {} chunks", chunk_loader.cache.len()));
         }
 
+        match screenshot_path {
+            Some(ref path) => {
+                settle(&mut chunk_loader, &camera, &metrics);
+                let capture = match capture::Capture::new(capture_size.unwrap_or(DEFAULT_WINDOW_SIZE)) {
+                    Ok(capture) => capture,
+                    Err(msg) => fail!("Error setting up capture: {}", msg),
+                };
+                profiler.begin_frame();
+                match capture.save(&mut renderer, &chunk_loader, &camera, &mut profiler, path) {
+                    Ok(()) => metrics.info(format!("Saved screenshot to {}", path.display())),
+                    Err(msg) => fail!("Error saving screenshot: {}", msg),
+                }
+                profiler.end_frame();
+                check_gl("screenshot");
+                window.set_should_close(true);
+            },
+            None => {},
+        }
+
+        let mut last_tick = precise_time_ns();
+        let start_time = last_tick;
+
         while !window.should_close() {
+            profiler.begin_frame();
+
             glfw.poll_events();
             glfw::fail_on_error(&errors);
             for (_, event) in glfw::flush_messages(&events) {
                 match event {
                     glfw::FramebufferSizeEvent(w, h) => {
-                        renderer.set_window_size(Vec2 { x: w as u32, y: h as u32 });
+                        window_size = Vec2 { x: w as u32, y: h as u32 };
+                        renderer.set_window_size(window_size);
                     },
                     glfw::KeyEvent(key, _, action, _) => {
                         match (action, key) {
@@ -162,6 +348,36 @@ fn main() {
                             (glfw::Press, glfw::KeyL) => {
                                 renderer.toggle_wireframe_mode();
                             },
+                            (glfw::Press, glfw::KeyF) => {
+                                camera.toggle_mode();
+                            },
+                            (glfw::Press, glfw::KeyP) => {
+                                overlay.toggle();
+                            },
+                            (glfw::Press, glfw::KeyF2) => {
+                                screenshot_requested = true;
+                            },
+                            (glfw::Press, glfw::KeyT) => {
+                                let path = Path::new(TRACE_PATH);
+                                match profiler.write_trace(&path) {
+                                    Ok(()) => metrics.info(format!("Wrote profiler trace to {}", path.display())),
+                                    Err(e) => metrics.log(LogError, format!("Error writing profiler trace: {}", e)),
+                                }
+                            },
+                            _ => {},
+                        }
+                    },
+                    glfw::MouseButtonEvent(button, glfw::Press, _) if grabbed => {
+                        let hit = raycast(&chunk_loader, camera.position, camera.direction(), PICK_DISTANCE);
+                        match (button, hit) {
+                            (glfw::MouseButtonLeft, Some(hit)) => {
+                                chunk_loader.set_block(hit.block, BlockAir);
+                            },
+                            (glfw::MouseButtonRight, Some(hit)) => {
+                                let n = mesh::faces[hit.face].normal;
+                                let p = hit.block.add_v(&Vec3::new(n.x as i64, n.y as i64, n.z as i64));
+                                chunk_loader.set_block(p, BlockStone);
+                            },
                             _ => {},
                         }
                     },
@@ -169,7 +385,7 @@ fn main() {
                 }
             }
 
-            if grabbed {
+            if grabbed && playback.is_none() {
                 let (cursor_x, cursor_y) = window.get_cursor_pos();
                 camera.look(Vec2 { x: cursor_x, y: cursor_y });
             }
@@ -177,70 +393,193 @@ fn main() {
             let now = precise_time_ns();
             let tick_length = (now - last_tick) as f64 / (1000.0 * 1000.0 * 1000.0);
             last_tick = now;
+            metrics.record("frame.time_ms", tick_length * 1000.0);
+
+            let mut playback_done = false;
+            match playback {
+                Some(ref mut playback) => {
+                    frame_times.push(tick_length * 1000.0);
+                    match playback.next() {
+                        Some((position, angle)) => camera.set_pose(position, angle),
+                        None => playback_done = true,
+                    }
+                },
+                None => camera.tick(&chunk_loader, tick_length),
+            }
+            if !playback_done {
+                match sequence {
+                    Some((ref dir, ref capture, ref mut frame)) => {
+                        settle(&mut chunk_loader, &camera, &metrics);
+                        let path = dir.join(format!("frame-{:05u}.png", *frame));
+                        match capture.save(&mut renderer, &chunk_loader, &camera, &mut profiler, &path) {
+                            Ok(()) => {},
+                            Err(msg) => fail!("Error saving {}: {}", path.display(), msg),
+                        }
+                        *frame += 1;
+                    },
+                    None => {},
+                }
+            }
+            if playback_done {
+                if benchmark || sequence.is_some() {
+                    window.set_should_close(true);
+                } else {
+                    // Hand the camera back
+                    playback = None;
+                }
+            }
 
-            camera.tick(tick_length);
+            if record_path.is_some() {
+                recording.push(PathSample {
+                    time: (now - start_time) as f64 / (1000.0 * 1000.0 * 1000.0),
+                    position: camera.position,
+                    angle: camera.angle,
+                });
+            }
+
+            if position_limiter.limit() {
+                chunk_loader.send_position(camera.position);
+            }
 
             {
-                let chunks = find_nearby_chunks(&chunk_loader, camera.position);
+                let (chunks, lod_chunks) = chunk_loader.visible_chunks();
 
+                profiler.begin("render");
                 renderer.render(
                     chunks,
+                    lod_chunks,
                     Vec3 { x: camera.position.x as f32, y: camera.position.y as f32, z: camera.position.z as f32 },
-                    camera.angle)
+                    camera.angle,
+                    &mut profiler);
+                profiler.end();
+                metrics.record("frame.draw_calls", renderer.draw_calls() as f64);
+            }
+
+            if screenshot_requested {
+                screenshot_requested = false;
+                let size = capture_size.unwrap_or(window_size);
+                let stale = match screenshot_capture {
+                    Some(ref capture) => capture.size != size,
+                    None => true,
+                };
+                if stale {
+                    screenshot_capture = match capture::Capture::new(size) {
+                        Ok(capture) => Some(capture),
+                        Err(msg) => {
+                            metrics.log(LogError, format!("Error setting up capture: {}", msg));
+                            None
+                        },
+                    };
+                }
+                match screenshot_capture {
+                    Some(ref capture) => {
+                        let path = capture::next_screenshot_path();
+                        match capture.save(&mut renderer, &chunk_loader, &camera, &mut profiler, &path) {
+                            Ok(()) => metrics.info(format!("Saved screenshot to {}", path.display())),
+                            Err(msg) => metrics.log(LogError, format!("Error saving screenshot: {}", msg)),
+                        }
+                    },
+                    None => {},
+                }
             }
 
+            profiler.begin("overlay");
+            overlay.draw(window_size, profiler.frame_ms(), profiler.summary());
+            profiler.end();
+
+            profiler.begin("swap");
             window.swap_buffers();
+            profiler.end();
 
-            request_nearby_chunks(&mut chunk_loader, camera.position);
+            profiler.begin("chunks");
+            chunk_loader.set_view(camera.position, camera.direction());
             chunk_loader.work();
+            profiler.end();
 
             check_gl("main loop");
 
+            profiler.end_frame();
+
+            metrics.count("frame.count", 1);
             fps_frame_counter += 1;
             if fps_display_limiter.limit() {
-                println!("{} frames per second", fps_frame_counter);
+                metrics.debug(format!("{} frames per second", fps_frame_counter));
                 fps_frame_counter = 0;
             }
+
+            match metrics_dumper {
+                Some(ref mut dumper) => match dumper.tick(&metrics) {
+                    Ok(()) => {},
+                    Err(e) => fail!("Error writing metrics: {}", e),
+                },
+                None => {},
+            }
         }
-    }
-}
 
-fn nearby_chunk_coords(p: Vec3<f64>) -> ~[Vec3<i64>] {
-    let cur_chunk_coord = Vec3::new(p.x as i64, p.y as i64, p.z as i64).div_s(CHUNK_SIZE as i64);
-    let r = VISIBLE_RADIUS as i64;
+        chunk_loader.save_all();
 
-    let mut coords = ~[];
+        match record_path {
+            Some(ref path) => match recording.save(path) {
+                Ok(()) => metrics.info(format!("Recorded {} camera samples to {}", recording.samples().len(), path.display())),
+                Err(msg) => metrics.log(LogError, format!("Error saving camera path: {}", msg)),
+            },
+            None => {},
+        }
 
-    for x in range(-r, r+1) {
-        for y in range(-r, r+1) {
-            for z in range(-r, r+1) {
-                let c = Vec3::new(x, y, z);
-                if c.dot(&c) < r*r {
-                    coords.push(c);
-                }
-            }
+        match sequence {
+            Some((ref dir, _, frame)) => {
+                metrics.info(format!("Saved {} frames to {}", frame, dir.display()));
+            },
+            None => {},
         }
-    }
 
-    coords.sort_by(|b,a| b.dot(b).cmp(&a.dot(a)));
+        if benchmark {
+            println!("Frame times: {}", frame_times.summary());
+            print_load_stats(&metrics.snapshot());
+        }
 
-    for c in coords.mut_iter() {
-        c.add_self_v(&cur_chunk_coord);
+        // The last interval is usually cut short
+        match metrics_dumper {
+            Some(ref mut dumper) => match dumper.dump(&metrics) {
+                Ok(()) => {},
+                Err(e) => fail!("Error writing metrics: {}", e),
+            },
+            None => {},
+        }
     }
-
-    coords
 }
 
-fn find_nearby_chunks<'a>(chunk_loader: &'a ChunkLoader, p: Vec3<f64>) -> ~[&'a ~Chunk] {
-    let coords = nearby_chunk_coords(p);
-    coords.iter().
-        filter_map(|&c| chunk_loader.get(c)).
-        collect()
+/// Load everything in view of the camera, so captures don't depend on how
+/// fast chunks happen to arrive
+fn settle(chunk_loader : &mut ChunkLoader, camera : &camera::Camera, metrics : &Metrics) {
+    let deadline = precise_time_ns() + SETTLE_TIMEOUT;
+    chunk_loader.set_view(camera.position, camera.direction());
+    chunk_loader.send_position(camera.position);
+    loop {
+        chunk_loader.work();
+        if chunk_loader.is_idle() {
+            break;
+        }
+        if precise_time_ns() > deadline {
+            metrics.info("Gave up waiting for chunks to load before capturing");
+            break;
+        }
+        std::task::deschedule();
+    }
 }
 
-fn request_nearby_chunks(chunk_loader: &mut ChunkLoader, p: Vec3<f64>) {
-    let coords = nearby_chunk_coords(p);
-    chunk_loader.request(coords);
+/// Chunk counts and the latency of each stage of the chunk jobs
+fn print_load_stats(registry : &Registry) {
+    let counter = |name : &str| registry.counters.find(&name.to_owned()).map_or(0, |&n| n);
+    let gauge = |name : &str| registry.gauges.find(&name.to_owned()).map_or(0.0, |&x| x);
+    println!("Chunks: {} loaded, {} remeshed, {} cached",
+             counter("chunk.loaded"), counter("chunk.remeshed"), gauge("chunk.cache_size"));
+    for (name, h) in registry.histograms.iter() {
+        if name.starts_with("job.") {
+            println!("  {}: {} jobs, mean {:.0f}, p99 {:.0f}, max {:.0f}",
+                     *name, h.count, h.mean(), h.quantile(0.99), h.max);
+        }
+    }
 }
 
 extern "C" {
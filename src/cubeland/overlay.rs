@@ -0,0 +1,262 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+extern crate gl;
+extern crate hgl;
+
+use std;
+use std::ptr;
+
+use gl::types::*;
+
+use cgmath::vector::Vec2;
+
+use renderer::{compile_shader, link_program};
+use world::profiler::ScopeSummary;
+
+/// Screen pixels per font pixel
+static SCALE : f32 = 2.0;
+
+/// Glyphs are 3x5 font pixels with a one pixel gap between them
+static ADVANCE : f32 = 4.0 * SCALE;
+static LINE_HEIGHT : f32 = 7.0 * SCALE;
+
+static MARGIN : f32 = 8.0;
+
+/// Width of the name column, in characters
+static NAME_WIDTH : uint = 16;
+
+/// Bar length per millisecond, in pixels
+static BAR_SCALE : f32 = 20.0;
+
+/// Widest a bar gets, at 20ms
+static BAR_WIDTH : f32 = 20.0 * BAR_SCALE;
+
+static BACKGROUND_COLOR : [f32, ..4] = [0.0, 0.0, 0.0, 0.6];
+static TEXT_COLOR : [f32, ..4] = [1.0, 1.0, 1.0, 1.0];
+static CPU_COLOR : [f32, ..4] = [1.0, 0.6, 0.1, 1.0];
+static GPU_COLOR : [f32, ..4] = [0.3, 0.9, 0.3, 1.0];
+
+/// Profiler results drawn over the top left of the screen: a row per
+/// scope with its average CPU and GPU milliseconds and a bar for each
+pub struct Overlay {
+    program : GLuint,
+    vertex_shader : GLuint,
+    fragment_shader : GLuint,
+    uniform_window_size : GLint,
+    attr_position : GLuint,
+    attr_color : GLuint,
+    visible : bool,
+}
+
+impl Overlay {
+    pub fn new() -> Result<Overlay, ~str> {
+        let vs_src = std::io::fs::File::open_mode(&std::path::Path::new("shaders/overlay.vs.glsl"), std::io::Open, std::io::Read).unwrap().read_to_end().unwrap();
+        let vs = match compile_shader(vs_src, gl::VERTEX_SHADER) {
+            Ok(vs) => vs,
+            Err(msg) => { return Err("overlay vertex shader " + msg) },
+        };
+
+        let fs_src = std::io::fs::File::open_mode(&std::path::Path::new("shaders/overlay.fs.glsl"), std::io::Open, std::io::Read).unwrap().read_to_end().unwrap();
+        let fs = match compile_shader(fs_src, gl::FRAGMENT_SHADER) {
+            Ok(fs) => fs,
+            Err(msg) => { return Err("overlay fragment shader " + msg) },
+        };
+
+        let program = match link_program(vs, fs) {
+            Ok(program) => program,
+            Err(msg) => { return Err("linking overlay program " + msg) },
+        };
+
+        let uniform_window_size = unsafe { "window_size".with_c_str(|ptr| gl::GetUniformLocation(program, ptr)) };
+        let attr_position = unsafe { "position".with_c_str(|ptr| gl::GetAttribLocation(program, ptr) as GLuint) };
+        assert!(attr_position as u32 != gl::INVALID_VALUE);
+        let attr_color = unsafe { "color".with_c_str(|ptr| gl::GetAttribLocation(program, ptr) as GLuint) };
+        assert!(attr_color as u32 != gl::INVALID_VALUE);
+
+        Ok(Overlay {
+            program: program,
+            vertex_shader: vs,
+            fragment_shader: fs,
+            uniform_window_size: uniform_window_size,
+            attr_position: attr_position,
+            attr_color: attr_color,
+            visible: false,
+        })
+    }
+
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+    }
+
+    pub fn draw(&self, window_size : Vec2<u32>, frame_ms : Option<f64>, rows : &[ScopeSummary]) {
+        if !self.visible {
+            return;
+        }
+
+        let mut lines = ~[];
+        let frame = match frame_ms {
+            Some(ms) => format!("frame {:.1f}", ms),
+            None => ~"frame -",
+        };
+        lines.push(format!("{:<16s}{:>7s}{:>7s}", frame, "cpu", "gpu"));
+        for row in rows.iter() {
+            let name = format!("{}{}", " ".repeat(row.depth), row.name);
+            let gpu = match row.gpu_ms {
+                Some(ms) => format!("{:.2f}", ms),
+                None => ~"-",
+            };
+            lines.push(format!("{:<16s}{:>7.2f}{:>7s}", name, row.cpu_ms, gpu));
+        }
+
+        let text_width = (NAME_WIDTH + 14) as f32 * ADVANCE;
+        let bars_x = MARGIN * 2.0 + text_width;
+
+        let mut verts = ~[];
+        quad(&mut verts, MARGIN, MARGIN,
+             text_width + BAR_WIDTH + MARGIN * 3.0, lines.len() as f32 * LINE_HEIGHT + MARGIN * 2.0,
+             BACKGROUND_COLOR);
+
+        for (i, line) in lines.iter().enumerate() {
+            let y = MARGIN * 2.0 + i as f32 * LINE_HEIGHT;
+            text(&mut verts, MARGIN * 2.0, y, line.as_slice());
+
+            // The header has no bars
+            if i == 0 {
+                continue;
+            }
+            let row = &rows[i - 1];
+            let bar = |ms : f64| (ms as f32 * BAR_SCALE).min(BAR_WIDTH);
+            quad(&mut verts, bars_x, y, bar(row.cpu_ms), 2.0 * SCALE, CPU_COLOR);
+            match row.gpu_ms {
+                Some(ms) => quad(&mut verts, bars_x, y + 3.0 * SCALE, bar(ms), 2.0 * SCALE, GPU_COLOR),
+                None => {},
+            }
+        }
+
+        gl::UseProgram(self.program);
+        gl::Uniform2f(self.uniform_window_size, window_size.x as f32, window_size.y as f32);
+        gl::Enable(gl::BLEND);
+        gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+        gl::Disable(gl::DEPTH_TEST);
+
+        let vbo = hgl::Vbo::from_data(verts, hgl::StreamDraw);
+        vbo.bind();
+        gl::EnableVertexAttribArray(self.attr_position);
+        gl::EnableVertexAttribArray(self.attr_color);
+        unsafe {
+            gl::VertexAttribPointer(self.attr_position, 2, gl::FLOAT,
+                                    gl::FALSE as GLboolean,
+                                    (VERTEX_FLOATS * std::mem::size_of::<f32>()) as GLint,
+                                    ptr::null());
+            gl::VertexAttribPointer(self.attr_color, 4, gl::FLOAT,
+                                    gl::FALSE as GLboolean,
+                                    (VERTEX_FLOATS * std::mem::size_of::<f32>()) as GLint,
+                                    std::cast::transmute(2 * std::mem::size_of::<f32>()));
+        }
+        gl::DrawArrays(gl::TRIANGLES, 0, (verts.len() / VERTEX_FLOATS) as GLint);
+
+        gl::DisableVertexAttribArray(self.attr_position);
+        gl::DisableVertexAttribArray(self.attr_color);
+        gl::Disable(gl::BLEND);
+        gl::UseProgram(0);
+    }
+}
+
+impl Drop for Overlay {
+    fn drop(&mut self) {
+        gl::DeleteProgram(self.program);
+        gl::DeleteShader(self.vertex_shader);
+        gl::DeleteShader(self.fragment_shader);
+    }
+}
+
+/// Pixel position followed by color
+static VERTEX_FLOATS : uint = 6;
+
+/// Two triangles filling a rectangle, in pixels from the top left
+fn quad(verts : &mut ~[f32], x : f32, y : f32, w : f32, h : f32, color : [f32, ..4]) {
+    let corners = [(x, y), (x + w, y), (x + w, y + h),
+                   (x, y), (x + w, y + h), (x, y + h)];
+    for &(cx, cy) in corners.iter() {
+        verts.push(cx);
+        verts.push(cy);
+        verts.push_all(color);
+    }
+}
+
+fn text(verts : &mut ~[f32], x : f32, y : f32, s : &str) {
+    for (i, c) in s.chars().enumerate() {
+        let rows = glyph(c);
+        for (row, &bits) in rows.iter().enumerate() {
+            for col in range(0u, 3) {
+                if bits & (4 >> col) != 0 {
+                    quad(verts,
+                         x + i as f32 * ADVANCE + col as f32 * SCALE,
+                         y + row as f32 * SCALE,
+                         SCALE, SCALE, TEXT_COLOR);
+                }
+            }
+        }
+    }
+}
+
+/// Rows of a 3x5 glyph, top first, leftmost pixel in the high bit
+///
+/// Letters are drawn in one case. Anything without a glyph is blank.
+fn glyph(c : char) -> [u8, ..5] {
+    match c.to_lowercase() {
+        '0' => [7, 5, 5, 5, 7],
+        '1' => [2, 6, 2, 2, 7],
+        '2' => [7, 1, 7, 4, 7],
+        '3' => [7, 1, 7, 1, 7],
+        '4' => [5, 5, 7, 1, 1],
+        '5' => [7, 4, 7, 1, 7],
+        '6' => [7, 4, 7, 5, 7],
+        '7' => [7, 1, 1, 1, 1],
+        '8' => [7, 5, 7, 5, 7],
+        '9' => [7, 5, 7, 1, 7],
+        'a' => [2, 5, 7, 5, 5],
+        'b' => [6, 5, 6, 5, 6],
+        'c' => [3, 4, 4, 4, 3],
+        'd' => [6, 5, 5, 5, 6],
+        'e' => [7, 4, 6, 4, 7],
+        'f' => [7, 4, 6, 4, 4],
+        'g' => [3, 4, 5, 5, 3],
+        'h' => [5, 5, 7, 5, 5],
+        'i' => [7, 2, 2, 2, 7],
+        'j' => [1, 1, 1, 5, 2],
+        'k' => [5, 5, 6, 5, 5],
+        'l' => [4, 4, 4, 4, 7],
+        'm' => [5, 7, 7, 5, 5],
+        'n' => [6, 5, 5, 5, 5],
+        'o' => [2, 5, 5, 5, 2],
+        'p' => [6, 5, 6, 4, 4],
+        'q' => [2, 5, 5, 6, 3],
+        'r' => [6, 5, 6, 5, 5],
+        's' => [3, 4, 2, 1, 6],
+        't' => [7, 2, 2, 2, 2],
+        'u' => [5, 5, 5, 5, 7],
+        'v' => [5, 5, 5, 5, 2],
+        'w' => [5, 5, 7, 7, 5],
+        'x' => [5, 5, 2, 5, 5],
+        'y' => [5, 5, 2, 2, 2],
+        'z' => [7, 1, 2, 4, 7],
+        '.' => [0, 0, 0, 0, 2],
+        ':' => [0, 2, 0, 2, 0],
+        '/' => [1, 1, 2, 4, 4],
+        '-' => [0, 0, 7, 0, 0],
+        '_' => [0, 0, 0, 0, 7],
+        _ => [0, 0, 0, 0, 0],
+    }
+}
@@ -14,14 +14,18 @@
 
 extern crate native;
 extern crate gl;
+extern crate hgl;
 extern crate cgmath;
 extern crate noise;
 
 use std;
+use std::cell::Cell;
 use std::ptr;
 use std::str;
 use std::vec;
 
+use collections::HashMap;
+
 use gl::types::*;
 
 use cgmath::matrix::Matrix;
@@ -37,15 +41,33 @@ use cgmath::angle::{rad, deg};
 use cgmath::ptr::Ptr;
 
 use check_gl;
-use chunk;
-use mesh;
-use mesh::Mesh;
-use chunk::Chunk;
-use CHUNK_SIZE;
 use texture;
+use fbo::FBO;
+use frameprofiler::FrameProfiler;
+use world::chunk;
+use world::lod::{LodChunk, lod_scale};
+use world::mesh;
+use world::mesh::Mesh;
+use world::registry::BlockRegistry;
+use world::registry::MAX_BLOCK_TYPES;
+use world::shadow;
+use world::shadow::{Cascade, ShadowConfig, MAX_CASCADES};
+use world::CHUNK_SIZE;
 
 static LIGHT_DIRECTION : Vec3<f32> = Vec3 { x: 0.408248, y: -0.816497, z: 0.408248 };
 
+/// Vertical field of view, in degrees
+static FIELD_OF_VIEW : f32 = 75.0;
+
+static NEAR_PLANE : f32 = 0.1;
+
+/// Terrain drops away with distance as if on a planet this big
+static PLANET_RADIUS : f32 = 6371000.0 / 5000.0;
+
+/// Fog density times the view distance, so the fog thins out as more
+/// terrain is drawn
+static FOG_DENSITY_DISTANCE : f32 = 0.768;
+
 enum RenderMode {
     RenderModeNormal,
     RenderModeWireframe,
@@ -55,10 +77,72 @@ pub struct Renderer {
     res : Resources,
     window_size : Vec2<u32>,
     mode : RenderMode,
+    /// Flattened vec4 color and vec3 texture parameters per block id
+    block_colors : ~[f32],
+    block_textures : ~[f32],
+    /// GPU copies of chunk meshes, keyed by level and chunk coordinate
+    buffers : HashMap<(uint, i64, i64, i64), GpuMesh>,
+    /// Distance to the farthest terrain, in blocks
+    view_distance : f32,
+    shadow_config : ShadowConfig,
+    /// One per cascade, drawn at the start of every frame
+    shadow_maps : ~[FBO],
+    /// Made in the last frame, counting the shadow pass
+    draw_calls : Cell<uint>,
+    /// Target of the main pass, 0 for the window
+    framebuffer : GLuint,
+}
+
+/// A mesh to draw, from a full resolution or a downsampled chunk
+struct DrawChunk<'a> {
+    level : uint,
+    coord : Vec3<i64>,
+    mesh : &'a Mesh,
+    mesh_version : uint,
+}
+
+impl<'a> DrawChunk<'a> {
+    fn key(&self) -> (uint, i64, i64, i64) {
+        (self.level, self.coord.x, self.coord.y, self.coord.z)
+    }
+
+    /// Width in blocks
+    fn size(&self) -> f32 {
+        (CHUNK_SIZE * lod_scale(self.level)) as f32
+    }
+
+    /// World position of the minimum corner
+    fn position(&self) -> Vec3<f32> {
+        Vec3::new(self.coord.x as f32, self.coord.y as f32, self.coord.z as f32).mul_s(self.size())
+    }
+}
+
+/// Vertex and element buffers uploaded from one version of a chunk's mesh
+///
+/// Empty meshes have no buffers.
+struct GpuMesh {
+    version : uint,
+    buffers : Option<(hgl::Vbo, hgl::Ebo)>,
+}
+
+impl GpuMesh {
+    fn upload(mesh : &Mesh, version : uint) -> GpuMesh {
+        let buffers = if mesh.elements.is_empty() {
+            None
+        } else {
+            Some((hgl::Vbo::from_data(mesh.vertices, hgl::StaticDraw),
+                  hgl::Ebo::from_indices(mesh.elements)))
+        };
+
+        GpuMesh {
+            version: version,
+            buffers: buffers,
+        }
+    }
 }
 
 impl Renderer {
-    pub fn new(window_size : Vec2<u32>) -> Renderer {
+    pub fn new(window_size : Vec2<u32>, registry : &BlockRegistry, shadow_config : &ShadowConfig) -> Renderer {
         let res = match Resources::load() {
             Ok(x) => x,
             Err(msg) => fail!("Error loading graphics resources: {}", msg),
@@ -66,19 +150,82 @@ impl Renderer {
 
         check_gl("after loading graphics resources");
 
+        // Unknown blocks are drawn red
+        let mut block_colors = ~[];
+        let mut block_textures = ~[];
+        for id in range(0, MAX_BLOCK_TYPES) {
+            match registry.get(id as u8) {
+                Some(info) => {
+                    block_colors.push_all(info.color);
+                    block_textures.push_all(info.texture);
+                },
+                None => {
+                    block_colors.push_all([1.0, 0.0, 0.0, 1.0]);
+                    block_textures.push_all([16.0, 16.0, 0.5]);
+                },
+            }
+        }
+
+        let shadow_maps = shadow_config.splits.iter().map(|_| {
+            FBO::new(shadow_config.resolution as GLint)
+        }).collect();
+        check_gl("after creating shadow maps");
+
         Renderer {
             res: res,
             window_size: window_size,
             mode: RenderModeNormal,
+            block_colors: block_colors,
+            block_textures: block_textures,
+            buffers: HashMap::new(),
+            view_distance: 1000.0,
+            shadow_config: shadow_config.clone(),
+            shadow_maps: shadow_maps,
+            draw_calls: Cell::new(0),
+            framebuffer: 0,
         }
     }
 
+    pub fn set_view_distance(&mut self, view_distance : f32) {
+        self.view_distance = view_distance;
+    }
+
     pub fn render(
-            &self,
+            &mut self,
             chunks : &[&~chunk::Chunk],
+            lod_chunks : &[&~LodChunk],
             camera_position : Vec3<f32>,
-            camera_angle : Vec2<f64>)
+            camera_angle : Vec2<f64>,
+            profiler : &mut FrameProfiler)
     {
+        self.draw_calls.set(0);
+
+        let mut draws = ~[];
+        for chunk in chunks.iter() {
+            draws.push(DrawChunk { level: 0, coord: chunk.coord, mesh: &*chunk.mesh, mesh_version: chunk.mesh_version });
+        }
+        for chunk in lod_chunks.iter() {
+            draws.push(DrawChunk { level: chunk.level, coord: chunk.coord, mesh: &*chunk.mesh, mesh_version: chunk.mesh_version });
+        }
+
+        profiler.begin("upload");
+        self.upload_meshes(draws);
+        profiler.end();
+
+        let aspect_ratio = self.window_size.x as f32 / self.window_size.y as f32;
+
+        let inv_rotation_x = Mat3::<f32>::from_angle_x(rad(-camera_angle.x as f32));
+        let inv_rotation_y = Mat3::<f32>::from_angle_y(rad(-camera_angle.y as f32));
+        let camera_direction = inv_rotation_y.mul_m(&inv_rotation_x).mul_v(&Vec3::new(0.0, 0.0, -1.0));
+
+        let cascades = shadow::cascades(&self.shadow_config, LIGHT_DIRECTION,
+                                        camera_position, camera_direction,
+                                        FIELD_OF_VIEW * std::f32::consts::PI / 180.0,
+                                        aspect_ratio, NEAR_PLANE);
+        profiler.begin("shadow");
+        self.render_shadow_maps(draws, cascades, camera_position);
+        profiler.end();
+
         gl::Enable(gl::TEXTURE_2D);
         gl::Enable(gl::DEPTH_TEST);
 
@@ -97,29 +244,39 @@ impl Renderer {
         gl::ActiveTexture(gl::TEXTURE0);
         gl::Uniform1i(self.res.uniform_texture, 0);
         gl::BindTexture(gl::TEXTURE_2D, self.res.texture);
+        self.bind_shadow_maps(cascades);
         gl::EnableVertexAttribArray(self.res.attr_position);
         gl::EnableVertexAttribArray(self.res.attr_blocktype);
+        gl::EnableVertexAttribArray(self.res.attr_ao);
+        gl::EnableVertexAttribArray(self.res.attr_light);
 
+        gl::BindFramebuffer(gl::FRAMEBUFFER, self.framebuffer);
         gl::Viewport(0, 0, self.window_size.x as GLint, self.window_size.y as GLint);
 
         gl::ClearColor(0.0, 0.75, 1.0, 1.0);
         gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
 
-        let aspect_ratio = self.window_size.x as f32 / self.window_size.y as f32;
-
         let projection = cgmath::projection::perspective(
-            deg(75.0f32),
+            deg(FIELD_OF_VIEW),
             aspect_ratio,
-            0.1f32, 1000.0f32);
+            NEAR_PLANE, self.view_distance * 2.0);
 
         unsafe {
             gl::UniformMatrix4fv(self.res.uniform_projection, 1, gl::FALSE, projection.ptr());
         }
 
+        unsafe {
+            gl::Uniform4fv(self.res.uniform_block_colors, MAX_BLOCK_TYPES as GLsizei, self.block_colors.as_ptr());
+            gl::Uniform3fv(self.res.uniform_block_textures, MAX_BLOCK_TYPES as GLsizei, self.block_textures.as_ptr());
+        }
+
         unsafe {
             gl::Uniform3fv(self.res.uniform_light_direction, 1, LIGHT_DIRECTION.ptr());
         }
 
+        gl::Uniform1f(self.res.uniform_planet_radius, PLANET_RADIUS);
+        gl::Uniform1f(self.res.uniform_fog_density, FOG_DENSITY_DISTANCE / self.view_distance);
+
         let camera_translation = Mat4::<f32>::from_cols(
             Vec4::<f32>::unit_x(),
             Vec4::<f32>::unit_y(),
@@ -136,64 +293,189 @@ impl Renderer {
 
         let clip_transform = projection.mul_m(&camera);
 
-        let camera_chunk_coord = Vec3::new(camera_position.x as i64,
-                                           camera_position.y as i64,
-                                           camera_position.z as i64).
-                                       div_s(CHUNK_SIZE as i64);
-
-        for chunk in chunks.iter() {
-            let mut chunk_pos = Vec3::new(chunk.coord.x as f32,
-                                          chunk.coord.y as f32,
-                                          chunk.coord.z as f32).
-                                      mul_s(CHUNK_SIZE as f32);
-
-            /* Calculate drop due to surface curvature */
-            static planet_radius : f32 = 6371000.0f32 / 5000.0f32;
-            let horiz_dist = (Vec3 { x: camera_position.x, y: 0.0f32, z: camera_position.z }).
-                sub_v(&Vec3::new(chunk_pos.x, 0.0f32, chunk_pos.z)).length();
-            let adj_horiz_dist = (horiz_dist - 100f32).max(0.0f32);
-            let drop = planet_radius - (planet_radius.powf(&2.0f32) - adj_horiz_dist.powf(&2.0f32)).sqrt();
-            chunk_pos.y -= drop;
-
-            if view_frustum_cull(&clip_transform, &chunk_pos.extend(0.0f32)) {
+        let mut visible_chunks = ~[];
+        for draw in draws.iter() {
+            let chunk_pos = draw.position();
+            let (box_min, box_size) = cull_box(camera_position, chunk_pos, draw.size());
+            if view_frustum_cull(&clip_transform, box_min, box_size) {
                 continue;
             }
 
-            let mesh : &Mesh = chunk.mesh;
-            self.bind_mesh(mesh);
+            visible_chunks.push((draw, chunk_pos));
+        }
 
+        profiler.begin("opaque");
+        for &(draw, chunk_pos) in visible_chunks.iter() {
+            self.draw_chunk(draw, chunk_pos, &draw.mesh.face_ranges, camera_position);
+        }
+        profiler.end();
+
+        // Translucent geometry is blended over everything else, farthest
+        // chunk first. It is depth tested but does not write depth so
+        // translucent faces behind it in the same chunk still show.
+        visible_chunks.sort_by(|&(a, a_pos), &(b, b_pos)| {
+            let da = a_pos.add_s(a.size() / 2.0).sub_v(&camera_position).length2();
+            let db = b_pos.add_s(b.size() / 2.0).sub_v(&camera_position).length2();
+            if da > db { Less } else if da < db { Greater } else { Equal }
+        });
+
+        profiler.begin("translucent");
+        gl::Enable(gl::BLEND);
+        gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+        gl::DepthMask(gl::FALSE);
+
+        for &(draw, chunk_pos) in visible_chunks.iter() {
+            self.draw_chunk(draw, chunk_pos, &draw.mesh.translucent_face_ranges, camera_position);
+        }
+
+        gl::DepthMask(gl::TRUE);
+        gl::Disable(gl::BLEND);
+        profiler.end();
+
+        gl::UseProgram(0);
+        for i in range(0, MAX_CASCADES) {
+            gl::ActiveTexture(gl::TEXTURE1 + i as GLenum);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+        }
+        gl::ActiveTexture(gl::TEXTURE0);
+        gl::BindTexture(gl::TEXTURE_2D, 0);
+        gl::PolygonMode(gl::FRONT_AND_BACK, gl::FILL);
+        gl::Disable(gl::CULL_FACE);
+        gl::Disable(gl::TEXTURE_2D);
+        gl::Disable(gl::DEPTH_TEST);
+    }
+
+    /// Draw the depth of the terrain seen from the light into each
+    /// cascade's shadow map
+    fn render_shadow_maps(&self, draws : &[DrawChunk], cascades : &[Cascade], camera_position : Vec3<f32>) {
+        if cascades.is_empty() {
+            return;
+        }
+
+        gl::UseProgram(self.res.shadow_program);
+        gl::EnableVertexAttribArray(self.res.shadow_attr_position);
+        gl::Enable(gl::DEPTH_TEST);
+        gl::Disable(gl::CULL_FACE);
+        gl::ColorMask(gl::FALSE, gl::FALSE, gl::FALSE, gl::FALSE);
+        // Keeps lit faces from shadowing themselves
+        gl::Enable(gl::POLYGON_OFFSET_FILL);
+        gl::PolygonOffset(2.0, 4.0);
+
+        let resolution = self.shadow_config.resolution as GLint;
+        gl::Viewport(0, 0, resolution, resolution);
+
+        unsafe {
+            gl::Uniform3fv(self.res.shadow_uniform_camera_position, 1, camera_position.ptr());
+        }
+        gl::Uniform1f(self.res.shadow_uniform_planet_radius, PLANET_RADIUS);
+
+        for (cascade, fbo) in cascades.iter().zip(self.shadow_maps.iter()) {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, fbo.fbo);
+            gl::Clear(gl::DEPTH_BUFFER_BIT);
             unsafe {
-                gl::Uniform3fv(self.res.uniform_chunk_position, 1, chunk_pos.ptr());
+                gl::UniformMatrix4fv(self.res.shadow_uniform_matrix, 1, gl::FALSE, cascade.matrix.ptr());
             }
 
-            for face in mesh::faces.iter() {
-                if !face_visible(face, chunk.coord, camera_chunk_coord) {
+            for draw in draws.iter() {
+                let chunk_pos = draw.position();
+                let (box_min, box_size) = cull_box(camera_position, chunk_pos, draw.size());
+                if view_frustum_cull(&cascade.matrix, box_min, box_size) {
                     continue;
                 }
 
-                unsafe {
-                    gl::Uniform3fv(self.res.uniform_normal, 1, face.normal.ptr());
+                // Translucent blocks do not cast shadows
+                let face_ranges = &draw.mesh.face_ranges;
+                if face_ranges.iter().all(|&(_, count)| count == 0) {
+                    continue;
+                }
+
+                match self.buffers.find(&draw.key()) {
+                    Some(&GpuMesh { buffers: Some((ref vbo, ref ebo)), .. }) => {
+                        vbo.bind();
+                        unsafe {
+                            gl::VertexAttribPointer(self.res.shadow_attr_position, 3, gl::FLOAT,
+                                                    gl::FALSE as GLboolean,
+                                                    std::mem::size_of::<mesh::VertexData>() as GLint,
+                                                    ptr::null());
+                        }
+                        ebo.bind();
+                    },
+                    _ => continue,
                 }
 
-                let (offset, count) = mesh.face_ranges[face.index];
                 unsafe {
-                    gl::DrawElements(
-                        gl::TRIANGLES,
-                        count as i32,
-                        gl::UNSIGNED_INT,
-                        std::cast::transmute(
-                            offset *
-                            std::mem::size_of::<GLuint>()));
+                    gl::Uniform3fv(self.res.shadow_uniform_chunk_position, 1, chunk_pos.ptr());
+                }
+                gl::Uniform1f(self.res.shadow_uniform_chunk_scale, lod_scale(draw.level) as f32);
+
+                for &(offset, count) in face_ranges.iter() {
+                    if count == 0 {
+                        continue;
+                    }
+                    self.draw_calls.set(self.draw_calls.get() + 1);
+                    unsafe {
+                        gl::DrawElements(
+                            gl::TRIANGLES,
+                            count as i32,
+                            gl::UNSIGNED_INT,
+                            std::cast::transmute(
+                                offset *
+                                std::mem::size_of::<GLuint>()));
+                    }
                 }
             }
         }
 
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        gl::Disable(gl::POLYGON_OFFSET_FILL);
+        gl::ColorMask(gl::TRUE, gl::TRUE, gl::TRUE, gl::TRUE);
+        gl::DisableVertexAttribArray(self.res.shadow_attr_position);
         gl::UseProgram(0);
-        gl::BindTexture(gl::TEXTURE_2D, 0);
-        gl::PolygonMode(gl::FRONT_AND_BACK, gl::FILL);
-        gl::Disable(gl::CULL_FACE);
-        gl::Disable(gl::TEXTURE_2D);
-        gl::Disable(gl::DEPTH_TEST);
+    }
+
+    /// Point the main program's shadow samplers at the cascades' maps,
+    /// on the texture units after the noise texture
+    fn bind_shadow_maps(&self, cascades : &[Cascade]) {
+        let units : ~[GLint] = range(0, MAX_CASCADES).map(|i| (i + 1) as GLint).collect();
+        let matrices : ~[Mat4<f32>] = cascades.iter().map(|c| c.matrix).collect();
+        let fars : ~[f32] = cascades.iter().map(|c| c.far).collect();
+
+        unsafe {
+            gl::Uniform1iv(self.res.uniform_shadow_maps, MAX_CASCADES as GLsizei, units.as_ptr());
+            if !cascades.is_empty() {
+                gl::UniformMatrix4fv(self.res.uniform_shadow_matrices, cascades.len() as GLsizei,
+                                     gl::FALSE, matrices.as_ptr() as *f32);
+                gl::Uniform1fv(self.res.uniform_cascade_far, cascades.len() as GLsizei, fars.as_ptr());
+            }
+        }
+        gl::Uniform1i(self.res.uniform_num_cascades, cascades.len() as GLint);
+
+        for (i, fbo) in self.shadow_maps.iter().enumerate() {
+            gl::ActiveTexture(gl::TEXTURE1 + i as GLenum);
+            gl::BindTexture(gl::TEXTURE_2D, fbo.depth_tex);
+        }
+        gl::ActiveTexture(gl::TEXTURE0);
+    }
+
+    /// Upload new and remeshed chunks and free the buffers of chunks that
+    /// are no longer loaded
+    fn upload_meshes(&mut self, chunks : &[DrawChunk]) {
+        let mut buffers = HashMap::with_capacity(chunks.len());
+        for chunk in chunks.iter() {
+            let key = chunk.key();
+            let gpu_mesh = match self.buffers.pop(&key) {
+                Some(gpu_mesh) => {
+                    if gpu_mesh.version == chunk.mesh_version {
+                        gpu_mesh
+                    } else {
+                        GpuMesh::upload(chunk.mesh, chunk.mesh_version)
+                    }
+                },
+                None => GpuMesh::upload(chunk.mesh, chunk.mesh_version),
+            };
+            buffers.insert(key, gpu_mesh);
+        }
+        self.buffers = buffers;
     }
 
     pub fn reload_resources(&mut self) {
@@ -212,26 +494,109 @@ impl Renderer {
         };
     }
 
+    /// Render the main pass into an offscreen framebuffer of the given size
+    /// instead of the window
+    pub fn render_to(
+            &mut self,
+            framebuffer : GLuint,
+            size : Vec2<u32>,
+            chunks : &[&~chunk::Chunk],
+            lod_chunks : &[&~LodChunk],
+            camera_position : Vec3<f32>,
+            camera_angle : Vec2<f64>,
+            profiler : &mut FrameProfiler)
+    {
+        let window_size = self.window_size;
+        self.window_size = size;
+        self.framebuffer = framebuffer;
+        self.render(chunks, lod_chunks, camera_position, camera_angle, profiler);
+        self.window_size = window_size;
+        self.framebuffer = 0;
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        gl::Viewport(0, 0, self.window_size.x as GLint, self.window_size.y as GLint);
+    }
+
+    pub fn draw_calls(&self) -> uint {
+        self.draw_calls.get()
+    }
+
     pub fn set_window_size(&mut self, window_size: Vec2<u32>) {
         self.window_size = window_size;
     }
 
-    fn bind_mesh(&self, mesh: &Mesh) {
-        match (&mesh.vertex_buffer, &mesh.element_buffer) {
-            (&Some(ref vbo), &Some(ref ebo)) => unsafe {
-                vbo.bind();
-                gl::VertexAttribPointer(self.res.attr_position, 3, gl::FLOAT,
-                                        gl::FALSE as GLboolean,
-                                        std::mem::size_of::<mesh::VertexData>() as GLint,
-                                        ptr::null());
-                gl::VertexAttribPointer(self.res.attr_blocktype, 1, gl::FLOAT,
-                                        gl::FALSE as GLboolean,
-                                        std::mem::size_of::<mesh::VertexData>() as GLint,
-                                        std::cast::transmute(offset_of!(mesh::VertexData, blocktype)));
-
-                ebo.bind();
-            },
-        _ => {}
+    fn draw_chunk(&self,
+                  chunk: &DrawChunk,
+                  chunk_pos: Vec3<f32>,
+                  face_ranges: &[(uint, uint), ..mesh::NUM_FACES],
+                  camera_position: Vec3<f32>) {
+        if face_ranges.iter().all(|&(_, count)| count == 0) {
+            return;
+        }
+
+        match self.buffers.find(&chunk.key()) {
+            Some(&GpuMesh { buffers: Some((ref vbo, ref ebo)), .. }) => self.bind_buffers(vbo, ebo),
+            _ => return,
+        }
+
+        unsafe {
+            gl::Uniform3fv(self.res.uniform_chunk_position, 1, chunk_pos.ptr());
+        }
+        gl::Uniform1f(self.res.uniform_chunk_scale, lod_scale(chunk.level) as f32);
+
+        let camera_chunk_coord = Vec3::new(camera_position.x as i64,
+                                           camera_position.y as i64,
+                                           camera_position.z as i64).
+                                       div_s(chunk.size() as i64);
+
+        for face in mesh::faces.iter() {
+            if !face_visible(face, chunk.coord, camera_chunk_coord) {
+                continue;
+            }
+
+            let (offset, count) = face_ranges[face.index];
+            if count == 0 {
+                continue;
+            }
+
+            unsafe {
+                gl::Uniform3fv(self.res.uniform_normal, 1, face.normal.ptr());
+            }
+
+            self.draw_calls.set(self.draw_calls.get() + 1);
+            unsafe {
+                gl::DrawElements(
+                    gl::TRIANGLES,
+                    count as i32,
+                    gl::UNSIGNED_INT,
+                    std::cast::transmute(
+                        offset *
+                        std::mem::size_of::<GLuint>()));
+            }
+        }
+    }
+
+    fn bind_buffers(&self, vbo: &hgl::Vbo, ebo: &hgl::Ebo) {
+        unsafe {
+            vbo.bind();
+            gl::VertexAttribPointer(self.res.attr_position, 3, gl::FLOAT,
+                                    gl::FALSE as GLboolean,
+                                    std::mem::size_of::<mesh::VertexData>() as GLint,
+                                    ptr::null());
+            gl::VertexAttribPointer(self.res.attr_blocktype, 1, gl::FLOAT,
+                                    gl::FALSE as GLboolean,
+                                    std::mem::size_of::<mesh::VertexData>() as GLint,
+                                    std::cast::transmute(offset_of!(mesh::VertexData, blocktype)));
+            gl::VertexAttribPointer(self.res.attr_ao, 1, gl::FLOAT,
+                                    gl::FALSE as GLboolean,
+                                    std::mem::size_of::<mesh::VertexData>() as GLint,
+                                    std::cast::transmute(offset_of!(mesh::VertexData, ao)));
+            // Sky and block light, next to each other
+            gl::VertexAttribPointer(self.res.attr_light, 2, gl::FLOAT,
+                                    gl::FALSE as GLboolean,
+                                    std::mem::size_of::<mesh::VertexData>() as GLint,
+                                    std::cast::transmute(offset_of!(mesh::VertexData, sky_light)));
+
+            ebo.bind();
         }
     }
 }
@@ -245,11 +610,32 @@ struct Resources {
     uniform_projection: GLint,
     uniform_camera_position: GLint,
     uniform_chunk_position: GLint,
+    uniform_chunk_scale: GLint,
+    uniform_planet_radius: GLint,
+    uniform_fog_density: GLint,
     uniform_texture: GLint,
     uniform_light_direction: GLint,
     uniform_normal: GLint,
+    uniform_block_colors: GLint,
+    uniform_block_textures: GLint,
+    uniform_shadow_maps: GLint,
+    uniform_shadow_matrices: GLint,
+    uniform_cascade_far: GLint,
+    uniform_num_cascades: GLint,
     attr_position: GLuint,
     attr_blocktype: GLuint,
+    attr_ao: GLuint,
+    attr_light: GLuint,
+    /// Draws only depth, into the shadow maps
+    shadow_program: GLuint,
+    shadow_vertex_shader: GLuint,
+    shadow_fragment_shader: GLuint,
+    shadow_uniform_matrix: GLint,
+    shadow_uniform_camera_position: GLint,
+    shadow_uniform_chunk_position: GLint,
+    shadow_uniform_chunk_scale: GLint,
+    shadow_uniform_planet_radius: GLint,
+    shadow_attr_position: GLuint,
 }
 
 impl Resources {
@@ -271,20 +657,58 @@ impl Resources {
             Err(msg) => { return Err("linking " + msg) },
         };
 
+        let shadow_vs_src = std::io::fs::File::open_mode(&std::path::Path::new("shaders/shadow.vs.glsl"), std::io::Open, std::io::Read).unwrap().read_to_end().unwrap();
+        let shadow_vs = match compile_shader(shadow_vs_src, gl::VERTEX_SHADER) {
+            Ok(vs) => vs,
+            Err(msg) => { return Err("shadow vertex shader " + msg) },
+        };
+
+        let shadow_fs_src = std::io::fs::File::open_mode(&std::path::Path::new("shaders/shadow.fs.glsl"), std::io::Open, std::io::Read).unwrap().read_to_end().unwrap();
+        let shadow_fs = match compile_shader(shadow_fs_src, gl::FRAGMENT_SHADER) {
+            Ok(fs) => fs,
+            Err(msg) => { return Err("shadow fragment shader " + msg) },
+        };
+
+        let shadow_program = match link_program(shadow_vs, shadow_fs) {
+            Ok(program) => program,
+            Err(msg) => { return Err("linking shadow program " + msg) },
+        };
+
         let texture = texture::make_noise_texture();
 
         let uniform_view = unsafe { "view".with_c_str(|ptr| gl::GetUniformLocation(program, ptr)) };
         let uniform_projection = unsafe { "projection".with_c_str(|ptr| gl::GetUniformLocation(program, ptr)) };
         let uniform_camera_position = unsafe { "camera_position".with_c_str(|ptr| gl::GetUniformLocation(program, ptr)) };
         let uniform_chunk_position = unsafe { "chunk_position".with_c_str(|ptr| gl::GetUniformLocation(program, ptr)) };
+        let uniform_chunk_scale = unsafe { "chunk_scale".with_c_str(|ptr| gl::GetUniformLocation(program, ptr)) };
+        let uniform_planet_radius = unsafe { "planet_radius".with_c_str(|ptr| gl::GetUniformLocation(program, ptr)) };
+        let uniform_fog_density = unsafe { "fog_density".with_c_str(|ptr| gl::GetUniformLocation(program, ptr)) };
         let uniform_texture = unsafe { "texture".with_c_str(|ptr| gl::GetUniformLocation(program, ptr)) };
         let uniform_light_direction = unsafe { "light_direction".with_c_str(|ptr| gl::GetUniformLocation(program, ptr)) };
         let uniform_normal = unsafe { "normal".with_c_str(|ptr| gl::GetUniformLocation(program, ptr)) };
+        let uniform_block_colors = unsafe { "block_colors".with_c_str(|ptr| gl::GetUniformLocation(program, ptr)) };
+        let uniform_block_textures = unsafe { "block_textures".with_c_str(|ptr| gl::GetUniformLocation(program, ptr)) };
+        let uniform_shadow_maps = unsafe { "shadow_maps".with_c_str(|ptr| gl::GetUniformLocation(program, ptr)) };
+        let uniform_shadow_matrices = unsafe { "shadow_matrices".with_c_str(|ptr| gl::GetUniformLocation(program, ptr)) };
+        let uniform_cascade_far = unsafe { "cascade_far".with_c_str(|ptr| gl::GetUniformLocation(program, ptr)) };
+        let uniform_num_cascades = unsafe { "num_cascades".with_c_str(|ptr| gl::GetUniformLocation(program, ptr)) };
 
         let attr_position = unsafe { "position".with_c_str(|ptr| gl::GetAttribLocation(program, ptr) as GLuint) };
         assert!(attr_position as u32 != gl::INVALID_VALUE);
         let attr_blocktype = unsafe { "blocktype".with_c_str(|ptr| gl::GetAttribLocation(program, ptr) as GLuint) };
         assert!(attr_blocktype as u32 != gl::INVALID_VALUE);
+        let attr_ao = unsafe { "ao".with_c_str(|ptr| gl::GetAttribLocation(program, ptr) as GLuint) };
+        assert!(attr_ao as u32 != gl::INVALID_VALUE);
+        let attr_light = unsafe { "light".with_c_str(|ptr| gl::GetAttribLocation(program, ptr) as GLuint) };
+        assert!(attr_light as u32 != gl::INVALID_VALUE);
+
+        let shadow_uniform_matrix = unsafe { "shadow_matrix".with_c_str(|ptr| gl::GetUniformLocation(shadow_program, ptr)) };
+        let shadow_uniform_camera_position = unsafe { "camera_position".with_c_str(|ptr| gl::GetUniformLocation(shadow_program, ptr)) };
+        let shadow_uniform_chunk_position = unsafe { "chunk_position".with_c_str(|ptr| gl::GetUniformLocation(shadow_program, ptr)) };
+        let shadow_uniform_chunk_scale = unsafe { "chunk_scale".with_c_str(|ptr| gl::GetUniformLocation(shadow_program, ptr)) };
+        let shadow_uniform_planet_radius = unsafe { "planet_radius".with_c_str(|ptr| gl::GetUniformLocation(shadow_program, ptr)) };
+        let shadow_attr_position = unsafe { "position".with_c_str(|ptr| gl::GetAttribLocation(shadow_program, ptr) as GLuint) };
+        assert!(shadow_attr_position as u32 != gl::INVALID_VALUE);
 
         Ok(Resources {
             program: program,
@@ -295,11 +719,31 @@ impl Resources {
             uniform_projection: uniform_projection,
             uniform_camera_position: uniform_camera_position,
             uniform_chunk_position: uniform_chunk_position,
+            uniform_chunk_scale: uniform_chunk_scale,
+            uniform_planet_radius: uniform_planet_radius,
+            uniform_fog_density: uniform_fog_density,
             uniform_texture: uniform_texture,
             uniform_light_direction: uniform_light_direction,
             uniform_normal: uniform_normal,
+            uniform_block_colors: uniform_block_colors,
+            uniform_block_textures: uniform_block_textures,
+            uniform_shadow_maps: uniform_shadow_maps,
+            uniform_shadow_matrices: uniform_shadow_matrices,
+            uniform_cascade_far: uniform_cascade_far,
+            uniform_num_cascades: uniform_num_cascades,
             attr_position: attr_position,
             attr_blocktype: attr_blocktype,
+            attr_ao: attr_ao,
+            attr_light: attr_light,
+            shadow_program: shadow_program,
+            shadow_vertex_shader: shadow_vs,
+            shadow_fragment_shader: shadow_fs,
+            shadow_uniform_matrix: shadow_uniform_matrix,
+            shadow_uniform_camera_position: shadow_uniform_camera_position,
+            shadow_uniform_chunk_position: shadow_uniform_chunk_position,
+            shadow_uniform_chunk_scale: shadow_uniform_chunk_scale,
+            shadow_uniform_planet_radius: shadow_uniform_planet_radius,
+            shadow_attr_position: shadow_attr_position,
         })
     }
 }
@@ -310,24 +754,55 @@ impl Drop for Resources {
         gl::DeleteProgram(self.program);
         gl::DeleteShader(self.vertex_shader);
         gl::DeleteShader(self.fragment_shader);
+        gl::DeleteProgram(self.shadow_program);
+        gl::DeleteShader(self.shadow_vertex_shader);
+        gl::DeleteShader(self.shadow_fragment_shader);
     }
 }
 
-fn view_frustum_cull(m : &Mat4<f32>, p: &Vec4<f32>) -> bool {
-    static L : f32 = CHUNK_SIZE as f32;
-
-    static vertices : [Vec4<f32>, ..8] = [
-        Vec4 { x: 0.0, y: 0.0, z: L,   w: 1.0 }, /* front bottom left */
-        Vec4 { x: L,   y: 0.0, z: L,   w: 1.0 }, /* front bottom right */
-        Vec4 { x: 0.0, y: L,   z: L,   w: 1.0 }, /* front top left */
-        Vec4 { x: L,   y: L,   z: L,   w: 1.0 }, /* front top right */
-        Vec4 { x: L,   y: 0.0, z: 0.0, w: 1.0 }, /* back bottom right */
-        Vec4 { x: 0.0, y: 0.0, z: 0.0, w: 1.0 }, /* back bottom left */
-        Vec4 { x: L,   y: L,   z: 0.0, w: 1.0 }, /* back top right */
-        Vec4 { x: 0.0, y: L,   z: 0.0, w: 1.0 }, /* back top left */
+/// How far the terrain at a horizontal distance from the camera is lowered
+///
+/// Must match the vertex shaders.
+fn curvature_drop(horiz_dist : f32) -> f32 {
+    let d = (horiz_dist - 100.0).max(0.0);
+    d * d / (2.0 * PLANET_RADIUS)
+}
+
+/// Box to cull a chunk against
+///
+/// The vertex shader lowers terrain for the planet's curvature, so the box
+/// stretches down over the whole range the chunk is lowered by.
+fn cull_box(camera_position : Vec3<f32>, chunk_pos : Vec3<f32>, size : f32) -> (Vec3<f32>, Vec3<f32>) {
+    let (near_x, far_x) = axis_distance(camera_position.x, chunk_pos.x, size);
+    let (near_z, far_z) = axis_distance(camera_position.z, chunk_pos.z, size);
+    let min_drop = curvature_drop(Vec2::new(near_x, near_z).length());
+    let max_drop = curvature_drop(Vec2::new(far_x, far_z).length());
+    (Vec3::new(chunk_pos.x, chunk_pos.y - max_drop, chunk_pos.z),
+     Vec3::new(size, size + max_drop - min_drop, size))
+}
+
+/// Nearest and farthest distance along one axis from a point to the span
+/// [min, min + size]
+fn axis_distance(p : f32, min : f32, size : f32) -> (f32, f32) {
+    let near = if p < min { min - p } else if p > min + size { p - min - size } else { 0.0 };
+    let far = (p - min).abs().max((p - min - size).abs());
+    (near, far)
+}
+
+/// Whether a box is entirely outside the view frustum
+fn view_frustum_cull(m : &Mat4<f32>, min: Vec3<f32>, size: Vec3<f32>) -> bool {
+    static vertices : [Vec3<f32>, ..8] = [
+        Vec3 { x: 0.0, y: 0.0, z: 1.0 }, /* front bottom left */
+        Vec3 { x: 1.0, y: 0.0, z: 1.0 }, /* front bottom right */
+        Vec3 { x: 0.0, y: 1.0, z: 1.0 }, /* front top left */
+        Vec3 { x: 1.0, y: 1.0, z: 1.0 }, /* front top right */
+        Vec3 { x: 1.0, y: 0.0, z: 0.0 }, /* back bottom right */
+        Vec3 { x: 0.0, y: 0.0, z: 0.0 }, /* back bottom left */
+        Vec3 { x: 1.0, y: 1.0, z: 0.0 }, /* back top right */
+        Vec3 { x: 0.0, y: 1.0, z: 0.0 }, /* back top left */
     ];
 
-    let clip_vertices: ~[Vec4<f32>] = vertices.iter().map(|v| m.mul_v(&p.add_v(v))).collect();
+    let clip_vertices: ~[Vec4<f32>] = vertices.iter().map(|v| m.mul_v(&min.add_v(&v.mul_v(&size)).extend(1.0))).collect();
 
     if clip_vertices.iter().all(|v| v.x < -v.w) {
         return true;
@@ -370,7 +845,7 @@ fn face_visible(face : &mesh::Face, a: Vec3<i64>, b: Vec3<i64>) -> bool {
     }
 }
 
-fn compile_shader(src: &[u8], ty: GLenum) -> Result<GLuint,~str> {
+pub fn compile_shader(src: &[u8], ty: GLenum) -> Result<GLuint,~str> {
     let shader = gl::CreateShader(ty);
     unsafe {
         // Attempt to compile the shader
@@ -395,7 +870,7 @@ fn compile_shader(src: &[u8], ty: GLenum) -> Result<GLuint,~str> {
     Ok(shader)
 }
 
-fn link_program(vs: GLuint, fs: GLuint) -> Result<GLuint, ~str> {
+pub fn link_program(vs: GLuint, fs: GLuint) -> Result<GLuint, ~str> {
     let program = gl::CreateProgram();
     gl::AttachShader(program, vs);
     gl::AttachShader(program, fs);
@@ -0,0 +1,180 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate getopts;
+extern crate time;
+extern crate cgmath;
+extern crate world = "cubeland-world";
+
+use std::os;
+use std::mem;
+
+use time::precise_time_ns;
+
+use cgmath::vector::Vector;
+use cgmath::vector::Vec3;
+
+use world::chunk::nearby_chunk_coords;
+use world::config::WorldConfig;
+use world::registry::BlockRegistry;
+use world::terrain::{Block, BlockType, BlockAir, Terrain, TerrainGenerator, NUM_PADDED_BLOCKS};
+use world::CHUNK_SIZE;
+
+/// Full passes over every chunk when timing access
+static PASSES : uint = 5;
+
+/// The layout Terrain had before palette compression, for comparison
+struct FlatTerrain {
+    blocks: [[[Block, ..CHUNK_SIZE+2], ..CHUNK_SIZE+2], ..CHUNK_SIZE+2],
+}
+
+impl FlatTerrain {
+    fn new() -> ~FlatTerrain {
+        ~FlatTerrain {
+            blocks: [[[Block { blocktype: BlockAir }, ..CHUNK_SIZE+2], ..CHUNK_SIZE+2], ..CHUNK_SIZE+2],
+        }
+    }
+
+    fn from_terrain(t: &Terrain) -> ~FlatTerrain {
+        let mut flat = FlatTerrain::new();
+        for x in range(-1, CHUNK_SIZE+1) {
+            for y in range(-1, CHUNK_SIZE+1) {
+                for z in range(-1, CHUNK_SIZE+1) {
+                    flat.blocks[x+1][y+1][z+1] = *t.get(x, y, z);
+                }
+            }
+        }
+        flat
+    }
+
+    fn get<'a>(&'a self, x: int, y: int, z: int) -> &'a Block {
+        &self.blocks[x+1][y+1][z+1]
+    }
+
+    fn set(&mut self, x: int, y: int, z: int, blocktype: BlockType) {
+        self.blocks[x+1][y+1][z+1].blocktype = blocktype;
+    }
+}
+
+/// Nanoseconds per block to read every block of every chunk PASSES times,
+/// and a checksum of the blocks so the reads are not optimized out
+fn time_reads<T>(chunks: &[T], get: |&T, int, int, int| -> BlockType) -> (f64, uint) {
+    let start_time = precise_time_ns();
+    let mut sum = 0;
+    for _ in range(0, PASSES) {
+        for t in chunks.iter() {
+            for x in range(-1, CHUNK_SIZE+1) {
+                for y in range(-1, CHUNK_SIZE+1) {
+                    for z in range(-1, CHUNK_SIZE+1) {
+                        sum += get(t, x, y, z) as uint;
+                    }
+                }
+            }
+        }
+    }
+    let blocks = PASSES * chunks.len() * NUM_PADDED_BLOCKS;
+    ((precise_time_ns() - start_time) as f64 / blocks as f64, sum)
+}
+
+/// Nanoseconds per block to build a copy of every chunk one block at a
+/// time
+fn time_writes<T>(flat_chunks: &[~FlatTerrain], new: || -> T, set: |&mut T, int, int, int, BlockType|) -> f64 {
+    let start_time = precise_time_ns();
+    let mut written = ~[];
+    for flat in flat_chunks.iter() {
+        let mut t = new();
+        for x in range(-1, CHUNK_SIZE+1) {
+            for y in range(-1, CHUNK_SIZE+1) {
+                for z in range(-1, CHUNK_SIZE+1) {
+                    set(&mut t, x, y, z, flat.get(x, y, z).blocktype);
+                }
+            }
+        }
+        written.push(t);
+    }
+    (precise_time_ns() - start_time) as f64 / (flat_chunks.len() * NUM_PADDED_BLOCKS) as f64
+}
+
+fn main() {
+    let args = os::args();
+    let mut opts = WorldConfig::opts();
+    opts.push(getopts::optflag("h", "help", "show this help"));
+    let brief = format!("Usage: {} [options]\n\n\
+                         Compares the memory and access speed of palette compressed\n\
+                         terrain with a flat array of blocks.",
+                        args[0]);
+
+    let matches = match getopts::getopts(args.tail(), opts) {
+        Ok(m) => m,
+        Err(f) => {
+            println!("{}\n\n{}", f.to_err_msg(), getopts::usage(brief, opts));
+            os::set_exit_status(1);
+            return;
+        }
+    };
+
+    if matches.opt_present("h") {
+        println!("{}", getopts::usage(brief, opts));
+        return;
+    }
+
+    let config = match WorldConfig::from_matches(&matches) {
+        Ok(config) => config,
+        Err(msg) => {
+            println!("error in world config: {}", msg);
+            os::set_exit_status(1);
+            return;
+        }
+    };
+
+    let registry = match BlockRegistry::load(&Path::new("data/blocks.json")) {
+        Ok(registry) => registry,
+        Err(msg) => {
+            println!("failed to load block registry: {}", msg);
+            os::set_exit_status(1);
+            return;
+        }
+    };
+
+    let terrain_generator = TerrainGenerator::new(&config, &registry);
+    let mut chunks = ~[];
+    for &c in nearby_chunk_coords(Vec3::new(0.0, 0.0, 0.0), config.visible_radius).iter() {
+        let p = Vec3::new(c.x as f64, c.y as f64, c.z as f64).mul_s(CHUNK_SIZE as f64);
+        chunks.push(terrain_generator.gen(p));
+    }
+    let flat_chunks : ~[~FlatTerrain] = chunks.iter().map(|t| FlatTerrain::from_terrain(*t)).collect();
+
+    let palette_bytes = chunks.iter().fold(0, |a, t| a + t.memory_size());
+    let flat_bytes = chunks.len() * mem::size_of::<FlatTerrain>();
+    let uniform = chunks.iter().filter(|t| t.uniform_type().is_some()).count();
+
+    println!("{} chunks, {} uniform", chunks.len(), uniform);
+    println!("memory per chunk:");
+    println!("  flat    : {} bytes", flat_bytes / chunks.len());
+    println!("  palette : {} bytes", palette_bytes / chunks.len());
+
+    let (flat_read, flat_sum) = time_reads(flat_chunks, |t, x, y, z| t.get(x, y, z).blocktype);
+    let (palette_read, palette_sum) = time_reads(chunks, |t, x, y, z| t.get(x, y, z).blocktype);
+    assert_eq!(flat_sum, palette_sum);
+
+    let flat_write = time_writes(flat_chunks, || FlatTerrain::new(), |t, x, y, z, b| t.set(x, y, z, b));
+    let palette_write = time_writes(flat_chunks, || Terrain::new(), |t, x, y, z, b| t.set(x, y, z, b));
+
+    println!("read time per block:");
+    println!("  flat    : {:.2}ns", flat_read);
+    println!("  palette : {:.2}ns", palette_read);
+    println!("write time per block:");
+    println!("  flat    : {:.2}ns", flat_write);
+    println!("  palette : {:.2}ns", palette_write);
+}
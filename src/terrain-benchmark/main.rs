@@ -15,67 +15,95 @@
 #[feature(globs)];
 #[feature(macro_rules)];
 
+extern crate getopts;
 extern crate time;
 extern crate cgmath;
-extern crate noise;
+extern crate world = "cubeland-world";
+
+use std::os;
 
 use time::precise_time_ns;
 
 use cgmath::vector::Vector;
 use cgmath::vector::Vec3;
 
-use terrain::TerrainGenerator;
+use world::chunk::nearby_chunk_coords;
+use world::config::WorldConfig;
+use world::registry::BlockRegistry;
+use world::terrain::TerrainGenerator;
+use world::CHUNK_SIZE;
 
-mod terrain;
+fn main() {
+    let args = os::args();
+    let mut opts = WorldConfig::opts();
+    opts.push(getopts::optflag("h", "help", "show this help"));
+    let brief = format!("Usage: {} [options]", args[0]);
+
+    let matches = match getopts::getopts(args.tail(), opts) {
+        Ok(m) => m,
+        Err(f) => {
+            println!("{}\n\n{}", f.to_err_msg(), getopts::usage(brief, opts));
+            os::set_exit_status(1);
+            return;
+        }
+    };
 
-pub static NUM_ITERS: uint = 1;
-pub static VISIBLE_RADIUS: uint = 8;
-pub static CHUNK_SIZE: int = 32;
-pub static WORLD_SEED: u32 = 42;
+    if matches.opt_present("h") {
+        println!("{}", getopts::usage(brief, opts));
+        return;
+    }
+
+    let config = match WorldConfig::from_matches(&matches) {
+        Ok(config) => config,
+        Err(msg) => {
+            println!("error in world config: {}", msg);
+            os::set_exit_status(1);
+            return;
+        }
+    };
+
+    let registry = match BlockRegistry::load(&Path::new("data/blocks.json")) {
+        Ok(registry) => registry,
+        Err(msg) => {
+            println!("failed to load block registry: {}", msg);
+            os::set_exit_status(1);
+            return;
+        }
+    };
 
-fn main() {
     let mut times = ~[];
-    let terrain_generator = TerrainGenerator::new(WORLD_SEED);
+    let mut uniform_times = ~[];
+    let terrain_generator = TerrainGenerator::new(&config, &registry);
 
     let camera_position = Vec3::new(0.0, 0.0, 0.0);
-    for &c in nearby_chunk_coords(camera_position).iter() {
+    for &c in nearby_chunk_coords(camera_position, config.visible_radius).iter() {
         let p = Vec3::new(c.x as f64, c.y as f64, c.z as f64).mul_s(CHUNK_SIZE as f64);
         let start_time = precise_time_ns();
-        terrain_generator.gen(p);
+        let t = terrain_generator.gen(p);
         let end_time = precise_time_ns();
-        times.push((end_time - start_time)/1000);
+        if t.uniform_type().is_some() {
+            uniform_times.push((end_time - start_time)/1000);
+        } else {
+            times.push((end_time - start_time)/1000);
+        }
     }
 
-    times.sort();
-
-    println!("{} chunks generated", times.len());
-    println!("minimum : {}us", times[0]);
-    println!("median  : {}us", times[times.len()/2]);
-    println!("maximum : {}us", times[times.len()-1]);
+    println!("{} chunks generated, {} uniform", times.len() + uniform_times.len(), uniform_times.len());
+    print_times("mixed", times);
+    print_times("uniform", uniform_times);
 }
 
-fn nearby_chunk_coords(p: Vec3<f64>) -> ~[Vec3<i64>] {
-    let cur_chunk_coord = Vec3::new(p.x as i64, p.y as i64, p.z as i64).div_s(CHUNK_SIZE as i64);
-    let r = VISIBLE_RADIUS as i64;
-
-    let mut coords = ~[];
-
-    for x in range(-r, r+1) {
-        for y in range(-r, r+1) {
-            for z in range(-r, r+1) {
-                let c = Vec3::new(x, y, z);
-                if c.dot(&c) < r*r {
-                    coords.push(c);
-                }
-            }
-        }
+fn print_times(name: &str, mut times: ~[u64]) {
+    if times.is_empty() {
+        return;
     }
 
-    coords.sort_by(|b,a| b.dot(b).cmp(&a.dot(a)));
-
-    for c in coords.mut_iter() {
-        c.add_self_v(&cur_chunk_coord);
-    }
+    times.sort();
 
-    coords
+    let total = times.iter().fold(0, |a, &b| a + b);
+    println!("{} chunks:", name);
+    println!("  minimum : {}us", times[0]);
+    println!("  median  : {}us", times[times.len()/2]);
+    println!("  maximum : {}us", times[times.len()-1]);
+    println!("  total   : {}ms", total / 1000);
 }
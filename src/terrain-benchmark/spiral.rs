@@ -1 +0,0 @@
-../cubeland/spiral.rs
\ No newline at end of file
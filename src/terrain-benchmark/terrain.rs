@@ -1 +0,0 @@
-../cubeland/terrain.rs
\ No newline at end of file
@@ -0,0 +1,413 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate serialize;
+extern crate collections;
+
+use std::f32;
+use std::io::IoResult;
+use std::io::MemWriter;
+
+use collections::TreeMap;
+use serialize::base64::{ToBase64, STANDARD};
+use serialize::json;
+use serialize::json::Json;
+
+use ExportMesh;
+use world::registry::BlockRegistry;
+
+fn block_name(registry: &BlockRegistry, blocktype: u8) -> ~str {
+    match registry.get(blocktype) {
+        Some(info) => info.name.clone(),
+        None => format!("unknown{}", blocktype),
+    }
+}
+
+// Unknown blocks are red, as in the game
+fn block_color(registry: &BlockRegistry, blocktype: u8) -> [f32, ..4] {
+    match registry.get(blocktype) {
+        Some(info) => info.color,
+        None => [1.0, 0.0, 0.0, 1.0],
+    }
+}
+
+/// Wavefront OBJ with one material per block type, defined in a separate MTL file
+pub fn write_obj(m: &ExportMesh, registry: &BlockRegistry, mtl_name: &str, w: &mut Writer) -> IoResult<()> {
+    try!(writeln!(w, "# cubeland terrain export"));
+    try!(writeln!(w, "mtllib {}", mtl_name));
+
+    for p in m.positions.iter() {
+        try!(writeln!(w, "v {} {} {}", p.x, p.y, p.z));
+    }
+
+    for n in m.normals.iter() {
+        try!(writeln!(w, "vn {} {} {}", n.x, n.y, n.z));
+    }
+
+    for &blocktype in m.used_blocktypes().iter() {
+        try!(writeln!(w, "usemtl {}", block_name(registry, blocktype)));
+        for tri in m.indices_for(blocktype).chunks(3) {
+            // OBJ indices start at 1
+            let (a, b, c) = (tri[0] + 1, tri[1] + 1, tri[2] + 1);
+            try!(writeln!(w, "f {}//{} {}//{} {}//{}", a, a, b, b, c, c));
+        }
+    }
+
+    Ok(())
+}
+
+pub fn write_mtl(m: &ExportMesh, registry: &BlockRegistry, w: &mut Writer) -> IoResult<()> {
+    try!(writeln!(w, "# cubeland terrain export"));
+    for &blocktype in m.used_blocktypes().iter() {
+        let color = block_color(registry, blocktype);
+        try!(writeln!(w, "newmtl {}", block_name(registry, blocktype)));
+        try!(writeln!(w, "Kd {} {} {}", color[0], color[1], color[2]));
+        try!(writeln!(w, "d {}", color[3]));
+        try!(writeln!(w, "illum 1"));
+    }
+    Ok(())
+}
+
+/// Binary PLY with the block type and its color as vertex properties
+pub fn write_ply(m: &ExportMesh, registry: &BlockRegistry, w: &mut Writer) -> IoResult<()> {
+    try!(writeln!(w, "ply"));
+    try!(writeln!(w, "format binary_little_endian 1.0"));
+    try!(writeln!(w, "comment cubeland terrain export"));
+    try!(writeln!(w, "element vertex {}", m.positions.len()));
+    try!(writeln!(w, "property float x"));
+    try!(writeln!(w, "property float y"));
+    try!(writeln!(w, "property float z"));
+    try!(writeln!(w, "property float nx"));
+    try!(writeln!(w, "property float ny"));
+    try!(writeln!(w, "property float nz"));
+    try!(writeln!(w, "property uchar blocktype"));
+    try!(writeln!(w, "property uchar red"));
+    try!(writeln!(w, "property uchar green"));
+    try!(writeln!(w, "property uchar blue"));
+    try!(writeln!(w, "element face {}", m.indices.len() / 3));
+    try!(writeln!(w, "property list uchar uint vertex_indices"));
+    try!(writeln!(w, "end_header"));
+
+    for i in range(0, m.positions.len()) {
+        let p = m.positions[i];
+        let n = m.normals[i];
+        let blocktype = m.blocktypes[i];
+        let color = block_color(registry, blocktype);
+        try!(w.write_le_f32(p.x));
+        try!(w.write_le_f32(p.y));
+        try!(w.write_le_f32(p.z));
+        try!(w.write_le_f32(n.x));
+        try!(w.write_le_f32(n.y));
+        try!(w.write_le_f32(n.z));
+        try!(w.write_u8(blocktype));
+        for c in color.slice(0, 3).iter() {
+            try!(w.write_u8((*c * 255.0) as u8));
+        }
+    }
+
+    for tri in m.indices.chunks(3) {
+        try!(w.write_u8(3));
+        for &e in tri.iter() {
+            try!(w.write_le_u32(e));
+        }
+    }
+
+    Ok(())
+}
+
+static GLB_MAGIC : u32 = 0x46546C67;
+static GLB_VERSION : u32 = 2;
+static GLB_CHUNK_JSON : u32 = 0x4E4F534A;
+static GLB_CHUNK_BIN : u32 = 0x004E4942;
+
+static GL_FLOAT : uint = 5126;
+static GL_UNSIGNED_INT : uint = 5125;
+static GL_ARRAY_BUFFER : uint = 34962;
+static GL_ELEMENT_ARRAY_BUFFER : uint = 34963;
+static GL_TRIANGLES : uint = 4;
+
+/// glTF 2.0 as JSON, with the buffer embedded as a base64 data URI
+pub fn write_gltf(m: &ExportMesh, registry: &BlockRegistry, w: &mut Writer) -> IoResult<()> {
+    let (mut fields, bin) = try!(gltf(m, registry));
+    let uri = format!("data:application/octet-stream;base64,{}", bin.to_base64(STANDARD));
+    fields.push(("buffers", json::List(~[object(~[
+        ("byteLength", number(bin.len())),
+        ("uri", string(uri.as_slice())),
+    ])])));
+    w.write_str(object(fields).to_str())
+}
+
+/// Binary glTF 2.0, with the JSON and the buffer in one file
+pub fn write_glb(m: &ExportMesh, registry: &BlockRegistry, w: &mut Writer) -> IoResult<()> {
+    let (mut fields, bin) = try!(gltf(m, registry));
+    fields.push(("buffers", json::List(~[object(~[("byteLength", number(bin.len()))])])));
+    let root = object(fields);
+
+    // Chunks must be 4-byte aligned: JSON is padded with spaces, binary data with zeros
+    let mut json_bytes = root.to_str().into_bytes();
+    while json_bytes.len() % 4 != 0 {
+        json_bytes.push(' ' as u8);
+    }
+    let bin_padding = (4 - bin.len() % 4) % 4;
+
+    let total_length = 12 + 8 + json_bytes.len() + 8 + bin.len() + bin_padding;
+
+    try!(w.write_le_u32(GLB_MAGIC));
+    try!(w.write_le_u32(GLB_VERSION));
+    try!(w.write_le_u32(total_length as u32));
+
+    try!(w.write_le_u32(json_bytes.len() as u32));
+    try!(w.write_le_u32(GLB_CHUNK_JSON));
+    try!(w.write(json_bytes));
+
+    try!(w.write_le_u32((bin.len() + bin_padding) as u32));
+    try!(w.write_le_u32(GLB_CHUNK_BIN));
+    try!(w.write(bin));
+    for _ in range(0, bin_padding) {
+        try!(w.write_u8(0));
+    }
+
+    Ok(())
+}
+
+/// The top level glTF fields except buffers, and the contents of the one
+/// buffer, with one primitive and material per block type
+///
+/// The block type is also stored in each material's extras.
+fn gltf(m: &ExportMesh, registry: &BlockRegistry) -> IoResult<(~[(&'static str, Json)], ~[u8])> {
+    let mut bin = MemWriter::new();
+    let mut buffer_views = ~[];
+    let mut accessors = ~[];
+
+    let mut min = [f32::INFINITY, ..3];
+    let mut max = [f32::NEG_INFINITY, ..3];
+    for p in m.positions.iter() {
+        min = [min[0].min(p.x), min[1].min(p.y), min[2].min(p.z)];
+        max = [max[0].max(p.x), max[1].max(p.y), max[2].max(p.z)];
+    }
+
+    let positions_offset = bin.get_ref().len();
+    for p in m.positions.iter() {
+        try!(bin.write_le_f32(p.x));
+        try!(bin.write_le_f32(p.y));
+        try!(bin.write_le_f32(p.z));
+    }
+    buffer_views.push(buffer_view(positions_offset, bin.get_ref().len() - positions_offset, GL_ARRAY_BUFFER));
+    accessors.push(object(~[
+        ("bufferView", number(0)),
+        ("componentType", number(GL_FLOAT)),
+        ("count", number(m.positions.len())),
+        ("type", string("VEC3")),
+        ("min", json::List(min.iter().map(|&x| number(x)).collect())),
+        ("max", json::List(max.iter().map(|&x| number(x)).collect())),
+    ]));
+
+    let normals_offset = bin.get_ref().len();
+    for n in m.normals.iter() {
+        try!(bin.write_le_f32(n.x));
+        try!(bin.write_le_f32(n.y));
+        try!(bin.write_le_f32(n.z));
+    }
+    buffer_views.push(buffer_view(normals_offset, bin.get_ref().len() - normals_offset, GL_ARRAY_BUFFER));
+    accessors.push(object(~[
+        ("bufferView", number(1)),
+        ("componentType", number(GL_FLOAT)),
+        ("count", number(m.normals.len())),
+        ("type", string("VEC3")),
+    ]));
+
+    let mut primitives = ~[];
+    let mut materials = ~[];
+    for &blocktype in m.used_blocktypes().iter() {
+        let indices = m.indices_for(blocktype);
+        let indices_offset = bin.get_ref().len();
+        for &e in indices.iter() {
+            try!(bin.write_le_u32(e));
+        }
+        buffer_views.push(buffer_view(indices_offset, bin.get_ref().len() - indices_offset, GL_ELEMENT_ARRAY_BUFFER));
+        accessors.push(object(~[
+            ("bufferView", number(buffer_views.len() - 1)),
+            ("componentType", number(GL_UNSIGNED_INT)),
+            ("count", number(indices.len())),
+            ("type", string("SCALAR")),
+        ]));
+
+        primitives.push(object(~[
+            ("attributes", object(~[("POSITION", number(0)), ("NORMAL", number(1))])),
+            ("indices", number(accessors.len() - 1)),
+            ("material", number(materials.len())),
+            ("mode", number(GL_TRIANGLES)),
+        ]));
+
+        let color = block_color(registry, blocktype);
+        materials.push(object(~[
+            ("name", string(block_name(registry, blocktype).as_slice())),
+            ("pbrMetallicRoughness", object(~[
+                ("baseColorFactor", json::List(color.iter().map(|&x| number(x)).collect())),
+                ("metallicFactor", number(0)),
+                ("roughnessFactor", number(1)),
+            ])),
+            ("extras", object(~[("blocktype", number(blocktype))])),
+        ]));
+    }
+
+    let fields = ~[
+        ("asset", object(~[("version", string("2.0")), ("generator", string("cubeland terrain-export"))])),
+        ("scene", number(0)),
+        ("scenes", json::List(~[object(~[("nodes", json::List(~[number(0)]))])])),
+        ("nodes", json::List(~[object(~[("mesh", number(0))])])),
+        ("meshes", json::List(~[object(~[("primitives", json::List(primitives))])])),
+        ("materials", json::List(materials)),
+        ("accessors", json::List(accessors)),
+        ("bufferViews", json::List(buffer_views)),
+    ];
+
+    Ok((fields, bin.unwrap()))
+}
+
+fn buffer_view(offset: uint, length: uint, target: uint) -> Json {
+    object(~[
+        ("buffer", number(0)),
+        ("byteOffset", number(offset)),
+        ("byteLength", number(length)),
+        ("target", number(target)),
+    ])
+}
+
+fn object(fields: ~[(&'static str, Json)]) -> Json {
+    let mut map = ~TreeMap::new();
+    for (k, v) in fields.move_iter() {
+        map.insert(k.to_owned(), v);
+    }
+    json::Object(map)
+}
+
+fn number<T: ToPrimitive>(x: T) -> Json {
+    json::Number(x.to_f64().unwrap())
+}
+
+fn string(s: &str) -> Json {
+    json::String(s.to_owned())
+}
+
+/// A quad of stone and a triangle of grass
+#[cfg(test)]
+fn test_mesh() -> ExportMesh {
+    use cgmath::vector::Vec3;
+    use world::terrain::{BlockStone, BlockGrass};
+
+    let up = Vec3::new(0.0f32, 1.0, 0.0);
+    ExportMesh {
+        positions: ~[Vec3::new(0.0f32, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0),
+                     Vec3::new(1.0, 0.0, 1.0), Vec3::new(0.0, 0.0, 1.0),
+                     Vec3::new(2.0, 1.0, 0.0), Vec3::new(3.0, 1.0, 0.0),
+                     Vec3::new(3.0, 1.0, 1.0)],
+        normals: ~[up, up, up, up, up, up, up],
+        blocktypes: ~[BlockStone, BlockStone, BlockStone, BlockStone,
+                      BlockGrass, BlockGrass, BlockGrass],
+        indices: ~[0, 1, 2, 0, 2, 3, 4, 5, 6],
+    }
+}
+
+#[cfg(test)]
+fn json_list_len(root: &Json, key: &str) -> uint {
+    root.find(&key.to_owned()).and_then(|j| j.as_list()).map_or(0, |l| l.len())
+}
+
+#[test]
+fn test_write_obj() {
+    let m = test_mesh();
+    let registry = BlockRegistry::builtin();
+    let mut w = MemWriter::new();
+    write_obj(&m, &registry, "terrain.mtl", &mut w).unwrap();
+    let obj = ::std::str::from_utf8_owned(w.unwrap()).unwrap();
+
+    let count = |prefix: &str| obj.lines().filter(|l| l.starts_with(prefix)).len();
+    assert_eq!(count("mtllib terrain.mtl"), 1);
+    assert_eq!(count("v "), 7);
+    assert_eq!(count("vn "), 7);
+    assert_eq!(count("usemtl "), 2);
+    assert_eq!(count("f "), 3);
+    // Indices start at 1
+    assert!(obj.contains("f 1//1 2//2 3//3"));
+}
+
+#[test]
+fn test_write_ply() {
+    let m = test_mesh();
+    let registry = BlockRegistry::builtin();
+    let mut w = MemWriter::new();
+    write_ply(&m, &registry, &mut w).unwrap();
+    let ply = w.unwrap();
+
+    let end = bytes!("end_header\n");
+    let header_len = range(0, ply.len()).find(|&i| ply.slice_from(i).starts_with(end)).unwrap() + end.len();
+    let header = ::std::str::from_utf8(ply.slice_to(header_len)).unwrap();
+    assert!(header.starts_with("ply\nformat binary_little_endian 1.0\n"));
+    assert!(header.contains("element vertex 7\n"));
+    assert!(header.contains("element face 3\n"));
+
+    // Six floats and four bytes per vertex, then a count and three indices
+    // per face
+    assert_eq!(ply.len() - header_len, 7 * (6 * 4 + 4) + 3 * (1 + 3 * 4));
+}
+
+#[test]
+fn test_write_glb() {
+    use std::io::BufReader;
+
+    let m = test_mesh();
+    let registry = BlockRegistry::builtin();
+    let mut w = MemWriter::new();
+    write_glb(&m, &registry, &mut w).unwrap();
+    let glb = w.unwrap();
+
+    let mut r = BufReader::new(glb);
+    assert_eq!(r.read_le_u32().unwrap(), GLB_MAGIC);
+    assert_eq!(r.read_le_u32().unwrap(), GLB_VERSION);
+    assert_eq!(r.read_le_u32().unwrap() as uint, glb.len());
+
+    let json_len = r.read_le_u32().unwrap() as uint;
+    assert_eq!(json_len % 4, 0);
+    assert_eq!(r.read_le_u32().unwrap(), GLB_CHUNK_JSON);
+    let root = json::from_str(::std::str::from_utf8(r.read_bytes(json_len).unwrap()).unwrap()).unwrap();
+    assert_eq!(json_list_len(&root, "materials"), 2);
+    // Positions, normals and the indices of each block type
+    assert_eq!(json_list_len(&root, "accessors"), 4);
+
+    let bin_len = r.read_le_u32().unwrap() as uint;
+    assert_eq!(r.read_le_u32().unwrap(), GLB_CHUNK_BIN);
+    assert_eq!(bin_len, 7 * 12 * 2 + 9 * 4);
+    assert_eq!(20 + json_len + 8 + bin_len, glb.len());
+}
+
+#[test]
+fn test_write_gltf() {
+    use serialize::base64::FromBase64;
+
+    let m = test_mesh();
+    let registry = BlockRegistry::builtin();
+    let mut w = MemWriter::new();
+    write_gltf(&m, &registry, &mut w).unwrap();
+    let root = json::from_str(::std::str::from_utf8_owned(w.unwrap()).unwrap()).unwrap();
+
+    assert_eq!(json_list_len(&root, "materials"), 2);
+    let buffers = root.find(&~"buffers").unwrap().as_list().unwrap();
+    assert_eq!(buffers.len(), 1);
+    let length = buffers[0].find(&~"byteLength").unwrap().as_number().unwrap() as uint;
+    let uri = buffers[0].find(&~"uri").unwrap().as_string().unwrap();
+    let prefix = "data:application/octet-stream;base64,";
+    assert!(uri.starts_with(prefix));
+    assert_eq!(uri.slice_from(prefix.len()).from_base64().unwrap().len(), length);
+}
@@ -0,0 +1,254 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![feature(globs)]
+#![feature(macro_rules)]
+
+extern crate collections;
+extern crate getopts;
+extern crate serialize;
+extern crate time;
+extern crate cgmath;
+extern crate world = "cubeland-world";
+
+use std::os;
+use std::io::File;
+use std::io::IoResult;
+
+use time::precise_time_ns;
+
+use cgmath::vector::Vector;
+use cgmath::vector::Vec3;
+
+use world::terrain::TerrainGenerator;
+use world::light::LightMap;
+use world::mesh;
+use world::mesh::Mesh;
+use world::registry::BlockRegistry;
+use world::config::WorldConfig;
+use world::CHUNK_SIZE;
+
+mod formats;
+
+/// Triangles from a box of chunks in world coordinates
+///
+/// Every vertex belongs to exactly one greedy-meshed quad, so it has a single
+/// normal and block type.
+pub struct ExportMesh {
+    positions: ~[Vec3<f32>],
+    normals: ~[Vec3<f32>],
+    blocktypes: ~[u8],
+    indices: ~[u32],
+}
+
+impl ExportMesh {
+    fn new() -> ExportMesh {
+        ExportMesh {
+            positions: ~[],
+            normals: ~[],
+            blocktypes: ~[],
+            indices: ~[],
+        }
+    }
+
+    fn append(&mut self, mesh: &Mesh, offset: Vec3<f32>) {
+        let base = self.positions.len() as u32;
+
+        let mut normals : ~[Vec3<f32>] =
+            range(0, mesh.vertices.len()).map(|_| Vec3::new(0.0f32, 0.0, 0.0)).collect();
+
+        for &ranges in [&mesh.face_ranges, &mesh.translucent_face_ranges].iter() {
+            for face in mesh::faces.iter() {
+                let (start, count) = ranges[face.index];
+                for &e in mesh.elements.slice(start, start + count).iter() {
+                    normals[e as uint] = face.normal;
+                    self.indices.push(base + e as u32);
+                }
+            }
+        }
+
+        for (v, n) in mesh.vertices.iter().zip(normals.iter()) {
+            self.positions.push(v.position.add_v(&offset));
+            self.normals.push(*n);
+            self.blocktypes.push(v.blocktype as u8);
+        }
+    }
+
+    /// Block types used by at least one triangle, in ascending order
+    pub fn used_blocktypes(&self) -> ~[u8] {
+        let mut used = [false, ..256];
+        for &i in self.indices.iter() {
+            used[self.blocktypes[i as uint] as uint] = true;
+        }
+        range(0u, 256).filter(|&b| used[b]).map(|b| b as u8).collect()
+    }
+
+    /// Indices of the triangles whose vertices have the given block type
+    pub fn indices_for(&self, blocktype: u8) -> ~[u32] {
+        let mut out = ~[];
+        for tri in self.indices.chunks(3) {
+            if self.blocktypes[tri[0] as uint] == blocktype {
+                out.push_all(tri);
+            }
+        }
+        out
+    }
+}
+
+#[deriving(Eq)]
+enum Format {
+    FormatObj,
+    FormatGltf,
+    FormatGlb,
+    FormatPly,
+}
+
+fn parse_format(s: &str) -> Option<Format> {
+    match s {
+        "obj" => Some(FormatObj),
+        "gltf" => Some(FormatGltf),
+        "glb" => Some(FormatGlb),
+        "ply" => Some(FormatPly),
+        _ => None,
+    }
+}
+
+fn main() {
+    let args = os::args();
+    let program = args[0].clone();
+
+    let mut opts = WorldConfig::opts();
+    opts.push_all_move(~[
+        getopts::optopt("f", "format", "output format: obj, gltf, glb or ply (default: from the file extension)", "FORMAT"),
+        getopts::optopt("o", "output", "output file (default: terrain.obj)", "FILE"),
+        getopts::optopt("b", "blocks", "block registry (default: data/blocks.json)", "FILE"),
+        getopts::optflag("h", "help", "show this help"),
+    ]);
+
+    let brief = format!("Usage: {} [options] X0 Y0 Z0 X1 Y1 Z1\n\n\
+                         Exports the inclusive box of chunk coordinates X0..X1, Y0..Y1, Z0..Z1.\n\
+                         Put -- before the coordinates if any of them are negative.",
+                        program);
+
+    let matches = match getopts::getopts(args.tail(), opts) {
+        Ok(m) => m,
+        Err(f) => {
+            println!("{}\n\n{}", f.to_err_msg(), getopts::usage(brief, opts));
+            os::set_exit_status(1);
+            return;
+        }
+    };
+
+    if matches.opt_present("h") {
+        println!("{}", getopts::usage(brief, opts));
+        return;
+    }
+
+    let coords : ~[i64] = matches.free.iter().filter_map(|s| from_str::<i64>(s.as_slice())).collect();
+    if matches.free.len() != 6 || coords.len() != 6 {
+        println!("{}", getopts::usage(brief, opts));
+        os::set_exit_status(1);
+        return;
+    }
+
+    let min = Vec3::new(coords[0].min(coords[3]), coords[1].min(coords[4]), coords[2].min(coords[5]));
+    let max = Vec3::new(coords[0].max(coords[3]), coords[1].max(coords[4]), coords[2].max(coords[5]));
+
+    let config = match WorldConfig::from_matches(&matches) {
+        Ok(config) => config,
+        Err(msg) => {
+            println!("error in world config: {}", msg);
+            os::set_exit_status(1);
+            return;
+        }
+    };
+
+    let output = Path::new(matches.opt_str("o").unwrap_or(~"terrain.obj"));
+
+    let format_name = match matches.opt_str("f") {
+        Some(f) => f,
+        None => output.extension_str().unwrap_or("").to_owned(),
+    };
+
+    let format = match parse_format(format_name.as_slice()) {
+        Some(f) => f,
+        None => {
+            println!("unknown output format: {}", format_name);
+            os::set_exit_status(1);
+            return;
+        }
+    };
+
+    let blocks_path = Path::new(matches.opt_str("b").unwrap_or(~"data/blocks.json"));
+    let registry = match BlockRegistry::load(&blocks_path) {
+        Ok(registry) => registry,
+        Err(msg) => {
+            println!("failed to load block registry: {}", msg);
+            os::set_exit_status(1);
+            return;
+        }
+    };
+
+    let start_time = precise_time_ns();
+    let terrain_generator = TerrainGenerator::new(&config, &registry);
+    let mut export_mesh = ExportMesh::new();
+    let mut num_chunks = 0;
+
+    for x in range(min.x, max.x + 1) {
+        for y in range(min.y, max.y + 1) {
+            for z in range(min.z, max.z + 1) {
+                let p = Vec3::new(x as f64, y as f64, z as f64).mul_s(CHUNK_SIZE as f64);
+                let terrain = terrain_generator.gen(p);
+                // The export formats have no light, so it is left out
+                let mesh = Mesh::gen(terrain, LightMap::daylight(), &registry);
+                export_mesh.append(mesh, Vec3::new(p.x as f32, p.y as f32, p.z as f32));
+                num_chunks += 1;
+            }
+        }
+    }
+
+    println!("meshed {} chunks in {}ms: {} vertices, {} triangles",
+             num_chunks, (precise_time_ns() - start_time)/1000/1000,
+             export_mesh.positions.len(), export_mesh.indices.len() / 3);
+
+    if export_mesh.indices.is_empty() {
+        println!("selected chunks contain no geometry");
+        os::set_exit_status(1);
+        return;
+    }
+
+    match write(&export_mesh, &registry, format, &output) {
+        Ok(()) => println!("wrote {}", output.display()),
+        Err(e) => {
+            println!("failed to write {}: {}", output.display(), e);
+            os::set_exit_status(1);
+        }
+    }
+}
+
+fn write(export_mesh: &ExportMesh, registry: &BlockRegistry, format: Format, output: &Path) -> IoResult<()> {
+    let mut file = try!(File::create(output));
+    match format {
+        FormatObj => {
+            let mtl_path = output.with_extension("mtl");
+            let mut mtl_file = try!(File::create(&mtl_path));
+            try!(formats::write_mtl(export_mesh, registry, &mut mtl_file));
+            let mtl_name = mtl_path.filename_str().unwrap_or("terrain.mtl");
+            formats::write_obj(export_mesh, registry, mtl_name, &mut file)
+        },
+        FormatGltf => formats::write_gltf(export_mesh, registry, &mut file),
+        FormatGlb => formats::write_glb(export_mesh, registry, &mut file),
+        FormatPly => formats::write_ply(export_mesh, registry, &mut file),
+    }
+}
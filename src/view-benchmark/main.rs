@@ -0,0 +1,150 @@
+// Copyright 2014 Rich Lane.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate getopts;
+extern crate collections;
+extern crate time;
+extern crate cgmath;
+extern crate world = "cubeland-world";
+
+use std::os;
+use collections::HashSet;
+
+use time::precise_time_ns;
+
+use cgmath::vector::Vec3;
+
+use world::chunk::nearby_chunk_coords;
+use world::config::WorldConfig;
+use world::view::ViewSet;
+
+static FRAMES : uint = 3000;
+
+/// Blocks per frame, about 30 blocks per second at 60 fps
+static SPEED : f64 = 0.5;
+
+/// Chunks requested per frame, as ChunkLoader allows in flight
+static LOADS_PER_FRAME : uint = 8;
+
+/// Camera position and direction at a frame: flying in a wide circle while
+/// looking around
+fn camera_at(frame: uint) -> (Vec3<f64>, Vec3<f64>) {
+    let t = frame as f64 * SPEED / 500.0;
+    let position = Vec3::new(t.cos() * 500.0, 20.0, t.sin() * 500.0);
+    let yaw = frame as f64 * 0.01;
+    (position, Vec3::new(yaw.cos(), 0.0, yaw.sin()))
+}
+
+/// The old per-frame work: build and sort the nearby coordinates once to
+/// request chunks and once to find the ones to draw
+fn full_scan(frame: uint, radius: uint, loaded: &mut HashSet<(i64, i64, i64)>) -> uint {
+    let (position, _) = camera_at(frame);
+
+    let mut requested = 0;
+    for c in nearby_chunk_coords(position, radius).iter() {
+        if requested < LOADS_PER_FRAME && loaded.insert((c.x, c.y, c.z)) {
+            requested += 1;
+        }
+    }
+
+    nearby_chunk_coords(position, radius).iter().
+        filter(|c| loaded.contains(&(c.x, c.y, c.z))).
+        count()
+}
+
+fn view_set(frame: uint, view: &mut ViewSet, loaded: &mut HashSet<(i64, i64, i64)>) -> uint {
+    let (position, direction) = camera_at(frame);
+    view.update(position, direction);
+
+    let mut requested = 0;
+    while requested < LOADS_PER_FRAME {
+        match view.pop() {
+            Some(c) => if loaded.insert((c.x, c.y, c.z)) {
+                requested += 1;
+            },
+            None => break,
+        }
+    }
+
+    view.coords().iter().
+        filter(|c| loaded.contains(&(c.x, c.y, c.z))).
+        count()
+}
+
+fn report(name: &str, times: &mut [u64]) {
+    times.sort();
+    let total = times.iter().fold(0, |a, &b| a + b);
+    println!("{}:", name);
+    println!("  minimum : {}us", times[0]);
+    println!("  median  : {}us", times[times.len()/2]);
+    println!("  maximum : {}us", times[times.len()-1]);
+    println!("  mean    : {}us", total / times.len() as u64);
+}
+
+fn main() {
+    let args = os::args();
+    let mut opts = WorldConfig::opts();
+    opts.push(getopts::optflag("h", "help", "show this help"));
+    let brief = format!("Usage: {} [options]\n\n\
+                         Compares the per-frame CPU time of choosing chunks to load and draw.",
+                        args[0]);
+
+    let matches = match getopts::getopts(args.tail(), opts) {
+        Ok(m) => m,
+        Err(f) => {
+            println!("{}\n\n{}", f.to_err_msg(), getopts::usage(brief, opts));
+            os::set_exit_status(1);
+            return;
+        }
+    };
+
+    if matches.opt_present("h") {
+        println!("{}", getopts::usage(brief, opts));
+        return;
+    }
+
+    let config = match WorldConfig::from_matches(&matches) {
+        Ok(config) => config,
+        Err(msg) => {
+            println!("error in world config: {}", msg);
+            os::set_exit_status(1);
+            return;
+        }
+    };
+
+    let mut full_scan_times = ~[];
+    let mut loaded = HashSet::new();
+    let mut drawn = 0;
+    for frame in range(0, FRAMES) {
+        let start_time = precise_time_ns();
+        drawn += full_scan(frame, config.visible_radius, &mut loaded);
+        full_scan_times.push((precise_time_ns() - start_time)/1000);
+    }
+    println!("full scan drew {} chunks", drawn);
+
+    let mut view_set_times = ~[];
+    let mut loaded = HashSet::new();
+    let mut view = ViewSet::new(config.visible_radius);
+    let mut drawn = 0;
+    for frame in range(0, FRAMES) {
+        let start_time = precise_time_ns();
+        drawn += view_set(frame, &mut view, &mut loaded);
+        view_set_times.push((precise_time_ns() - start_time)/1000);
+    }
+    println!("view set drew {} chunks", drawn);
+
+    println!("{} frames, view radius {}", FRAMES, config.visible_radius);
+    report("full scan", full_scan_times);
+    report("view set", view_set_times);
+}